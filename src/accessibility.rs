@@ -0,0 +1,421 @@
+use std::fmt::Write;
+
+use epub_builder::EpubVersion;
+use roxmltree::{Document, Node, ParsingOptions};
+
+use crate::config::Accessibility;
+use crate::package::xml_escape;
+
+/// Checks the chapters for common accessibility problems and collects what
+/// the accessibility metadata is derived from.
+#[derive(Debug, Default)]
+pub(crate) struct Audit {
+    /// Problems found, with the document they're in.
+    issues: Vec<(String, String)>,
+    images: usize,
+    images_without_alt: usize,
+    skipped_headings: usize,
+    tables_without_headers: usize,
+    /// Whether there's animation, video or sound, which may be hazardous.
+    media: bool,
+    math: usize,
+    math_without_alt: usize,
+    /// Whether the page breaks of the print edition are marked.
+    page_breaks: bool,
+}
+
+impl Audit {
+    /// Check a rendered XHTML document.
+    pub(crate) fn check(&mut self, document: &str, xhtml: &str) {
+        let options = ParsingOptions {
+            allow_dtd: true,
+            ..ParsingOptions::default()
+        };
+        let doc = match Document::parse_with_options(xhtml, options) {
+            Ok(doc) => doc,
+            Err(e) => {
+                self.issue(document, format!("could not be checked: {e}"));
+                return;
+            }
+        };
+
+        let mut last_heading = None;
+        for node in doc.descendants().filter(Node::is_element) {
+            match node.tag_name().name() {
+                "img" => {
+                    self.images += 1;
+                    let src = node.attribute("src").unwrap_or_default();
+                    // Markdown images always get an alt attribute, if only an empty one
+                    let alt = node.attribute("alt").unwrap_or_default();
+                    if alt.trim().is_empty() && !is_decorative(node) {
+                        self.images_without_alt += 1;
+                        self.issue(document, format!("image \"{src}\" has no alt text"));
+                    }
+                    if src.to_ascii_lowercase().ends_with(".gif") {
+                        self.media = true;
+                    }
+                }
+                "video" | "audio" => self.media = true,
+                "math" => {
+                    self.math += 1;
+                    let alt = node.attribute("alttext").unwrap_or_default();
+                    if alt.trim().is_empty() {
+                        self.math_without_alt += 1;
+                    }
+                }
+                "span" if node.attribute("role") == Some("doc-pagebreak") => {
+                    self.page_breaks = true
+                }
+                "table" => {
+                    let has_headers = node
+                        .descendants()
+                        .any(|n| n.is_element() && n.tag_name().name() == "th");
+                    if !has_headers {
+                        self.tables_without_headers += 1;
+                        self.issue(document, "table has no header cells".to_string());
+                    }
+                }
+                name => {
+                    let Some(level) = heading_level(name) else {
+                        continue;
+                    };
+                    if let Some(last) = last_heading.filter(|&last| level > last + 1) {
+                        self.skipped_headings += 1;
+                        let text: String = node
+                            .descendants()
+                            .filter(Node::is_text)
+                            .filter_map(|n| n.text())
+                            .collect();
+                        self.issue(
+                            document,
+                            format!(
+                                "heading \"{}\" skips from h{} to h{}",
+                                text.trim(),
+                                last,
+                                level
+                            ),
+                        );
+                    }
+                    last_heading = Some(level);
+                }
+            }
+        }
+    }
+
+    fn issue(&mut self, document: &str, message: String) {
+        warn!("Accessibility: {}: {}", document, message);
+        self.issues.push((document.to_string(), message));
+    }
+
+    fn access_modes(&self, config: &Accessibility) -> Vec<String> {
+        if !config.access_modes.is_empty() {
+            return config.access_modes.clone();
+        }
+        let mut modes = vec!["textual".to_string()];
+        if self.images > 0 {
+            modes.push("visual".to_string());
+        }
+        modes
+    }
+
+    fn access_modes_sufficient(&self, config: &Accessibility) -> Vec<String> {
+        if !config.access_modes_sufficient.is_empty() {
+            return config.access_modes_sufficient.clone();
+        }
+        match (self.images, self.images_without_alt) {
+            (0, _) => vec!["textual".to_string()],
+            (_, 0) => vec!["textual,visual".to_string(), "textual".to_string()],
+            _ => vec!["textual,visual".to_string()],
+        }
+    }
+
+    fn features(&self, config: &Accessibility) -> Vec<String> {
+        if !config.features.is_empty() {
+            return config.features.clone();
+        }
+        let mut features = vec!["tableOfContents", "readingOrder"];
+        if self.skipped_headings == 0 {
+            features.push("structuralNavigation");
+        }
+        if self.images > 0 && self.images_without_alt == 0 {
+            features.push("alternativeText");
+        }
+        if self.math > 0 {
+            features.push("MathML");
+            if self.math_without_alt == 0 {
+                features.push("describedMath");
+            }
+        }
+        if self.page_breaks {
+            features.extend(["pageBreakMarkers", "pageNavigation"]);
+        }
+        features.into_iter().map(String::from).collect()
+    }
+
+    fn hazards(&self, config: &Accessibility) -> Vec<String> {
+        if !config.hazards.is_empty() {
+            return config.hazards.clone();
+        }
+        let hazard = if self.media { "unknown" } else { "none" };
+        vec![hazard.to_string()]
+    }
+
+    fn summary(&self, config: &Accessibility) -> String {
+        if let Some(ref summary) = config.summary {
+            return summary.clone();
+        }
+        let mut summary =
+            String::from("The table of contents links to every chapter, in reading order.");
+        if self.skipped_headings == 0 {
+            summary.push_str(" Headings structure the content without skipping levels.");
+        }
+        match (self.images, self.images_without_alt) {
+            (0, _) => {}
+            (_, 0) => summary.push_str(" All images have alternative text."),
+            (_, n) => write!(summary, " {n} images have no alternative text.").unwrap(),
+        }
+        match (self.math, self.math_without_alt) {
+            (0, _) => {}
+            (_, 0) => summary.push_str(" Math is written in MathML with a text alternative."),
+            _ => summary.push_str(" Math is written in MathML."),
+        }
+        summary
+    }
+
+    /// The pairs of accessibility properties and their values.
+    fn properties(&self, config: &Accessibility) -> Vec<(&'static str, String)> {
+        let mut properties = Vec::new();
+        for mode in self.access_modes(config) {
+            properties.push(("schema:accessMode", mode));
+        }
+        for modes in self.access_modes_sufficient(config) {
+            properties.push(("schema:accessModeSufficient", modes));
+        }
+        for feature in self.features(config) {
+            properties.push(("schema:accessibilityFeature", feature));
+        }
+        for hazard in self.hazards(config) {
+            properties.push(("schema:accessibilityHazard", hazard));
+        }
+        properties.push(("schema:accessibilitySummary", self.summary(config)));
+        if let Some(ref conforms_to) = config.conforms_to {
+            properties.push(("dcterms:conformsTo", conforms_to.clone()));
+        }
+        if let Some(ref certified_by) = config.certified_by {
+            properties.push(("a11y:certifiedBy", certified_by.clone()));
+        }
+        properties
+    }
+
+    /// The accessibility metadata elements of the package document.
+    pub(crate) fn metadata(&self, config: &Accessibility, version: EpubVersion) -> Vec<String> {
+        if config.conforms_to.is_some() && !self.issues.is_empty() {
+            warn!(
+                "The book claims to conform to {} despite {} accessibility issues",
+                config.conforms_to.as_deref().unwrap_or_default(),
+                self.issues.len()
+            );
+        }
+        self.properties(config)
+            .into_iter()
+            .map(|(property, value)| match version {
+                EpubVersion::V30 => {
+                    format!(
+                        "<meta property=\"{property}\">{}</meta>",
+                        xml_escape(&value)
+                    )
+                }
+                _ => format!(
+                    "<meta name=\"{property}\" content=\"{}\"/>",
+                    xml_escape(&value)
+                ),
+            })
+            .collect()
+    }
+
+    /// A plain text report of the accessibility metadata and the problems
+    /// found.
+    pub(crate) fn report(&self, title: &str, config: &Accessibility) -> String {
+        let mut report = format!("Accessibility report for {title}\n\nMetadata:\n");
+        for (property, value) in self.properties(config) {
+            writeln!(report, "  {property}: {value}").unwrap();
+        }
+
+        writeln!(report, "\nChecks:").unwrap();
+        let checks = [
+            ("Images without alt text", self.images_without_alt),
+            ("Headings skipping a level", self.skipped_headings),
+            ("Tables without header cells", self.tables_without_headers),
+        ];
+        for (check, count) in checks {
+            writeln!(report, "  {check}: {count}").unwrap();
+        }
+
+        if self.issues.is_empty() {
+            writeln!(report, "\nNo issues found.").unwrap();
+        } else {
+            writeln!(report, "\nIssues:").unwrap();
+            for (document, message) in &self.issues {
+                writeln!(report, "  {document}: {message}").unwrap();
+            }
+        }
+        report
+    }
+}
+
+/// Whether an image is marked as decorative, so it needs no alt text.
+fn is_decorative(node: Node) -> bool {
+    matches!(node.attribute("role"), Some("presentation" | "none"))
+        || node.attribute("aria-hidden") == Some("true")
+}
+
+/// The level of a heading element, e.g. 2 for `h2`.
+fn heading_level(name: &str) -> Option<u32> {
+    match name.strip_prefix('h')?.parse() {
+        Ok(level @ 1..=6) => Some(level),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn xhtml(body: &str) -> String {
+        format!("<html xmlns=\"http://www.w3.org/1999/xhtml\"><body>{body}</body></html>")
+    }
+
+    #[test]
+    fn find_accessibility_issues() {
+        let mut audit = Audit::default();
+        audit.check(
+            "a.html",
+            &xhtml(
+                "<h1>One</h1><h3>Three <em>deep</em></h3><h2>Two</h2><img src=\"a.png\" />\
+                <img src=\"b.png\" alt=\" \" /><img src=\"c.png\" alt=\"\" role=\"presentation\" />\
+                <table><tr><td>1</td></tr></table>\
+                <table><tr><th>head</th></tr></table>",
+            ),
+        );
+        audit.check("b.html", &xhtml("<h2>Start</h2><h4>Skip</h4>"));
+
+        assert_eq!(
+            audit.issues,
+            vec![
+                (
+                    "a.html".to_string(),
+                    "heading \"Three deep\" skips from h1 to h3".to_string()
+                ),
+                (
+                    "a.html".to_string(),
+                    "image \"a.png\" has no alt text".to_string()
+                ),
+                (
+                    "a.html".to_string(),
+                    "image \"b.png\" has no alt text".to_string()
+                ),
+                (
+                    "a.html".to_string(),
+                    "table has no header cells".to_string()
+                ),
+                (
+                    "b.html".to_string(),
+                    "heading \"Skip\" skips from h2 to h4".to_string()
+                ),
+            ]
+        );
+        let report = audit.report("Book", &Accessibility::default());
+        assert!(report.contains("  Images without alt text: 2\n  Headings skipping a level: 2\n"));
+        assert!(report.contains("\nIssues:\n  a.html: heading"));
+    }
+
+    #[test]
+    fn flag_markdown_images_without_alt_text() {
+        let mut html = String::new();
+        pulldown_cmark::html::push_html(
+            &mut html,
+            pulldown_cmark::Parser::new("# One\n\n![](img.png) ![Logo](logo.png)\n"),
+        );
+        let mut audit = Audit::default();
+        audit.check("a.html", &xhtml(&html));
+
+        assert_eq!(audit.images, 2);
+        assert_eq!(audit.images_without_alt, 1);
+        let config = Accessibility::default();
+        assert!(!audit
+            .features(&config)
+            .contains(&"alternativeText".to_string()));
+        assert!(audit
+            .summary(&config)
+            .ends_with(" 1 images have no alternative text."));
+    }
+
+    #[test]
+    fn describe_math_only_with_alt_text() {
+        let math = "<math xmlns=\"http://www.w3.org/1998/Math/MathML\"";
+        let mut audit = Audit::default();
+        audit.check(
+            "a.html",
+            &xhtml(&format!("<p>{math} alttext=\"x\"><mi>x</mi></math></p>")),
+        );
+        let config = Accessibility::default();
+        assert!(audit
+            .features(&config)
+            .contains(&"describedMath".to_string()));
+
+        audit.check(
+            "b.html",
+            &xhtml(&format!("<p>{math}><mi>y</mi></math></p>")),
+        );
+        let features = audit.features(&config);
+        assert!(features.contains(&"MathML".to_string()));
+        assert!(!features.contains(&"describedMath".to_string()));
+        assert!(audit
+            .summary(&config)
+            .ends_with(" Math is written in MathML."));
+    }
+
+    #[test]
+    fn derive_metadata() {
+        let mut audit = Audit::default();
+        audit.check(
+            "a.html",
+            &xhtml("<h1>One</h1><p><img src=\"a.png\" alt=\"A\" /></p>"),
+        );
+        let config = Accessibility::default();
+        assert_eq!(
+            audit.metadata(&config, EpubVersion::V30),
+            vec![
+                "<meta property=\"schema:accessMode\">textual</meta>",
+                "<meta property=\"schema:accessMode\">visual</meta>",
+                "<meta property=\"schema:accessModeSufficient\">textual,visual</meta>",
+                "<meta property=\"schema:accessModeSufficient\">textual</meta>",
+                "<meta property=\"schema:accessibilityFeature\">tableOfContents</meta>",
+                "<meta property=\"schema:accessibilityFeature\">readingOrder</meta>",
+                "<meta property=\"schema:accessibilityFeature\">structuralNavigation</meta>",
+                "<meta property=\"schema:accessibilityFeature\">alternativeText</meta>",
+                "<meta property=\"schema:accessibilityHazard\">none</meta>",
+                "<meta property=\"schema:accessibilitySummary\">The table of contents links to \
+                every chapter, in reading order. Headings structure the content without skipping \
+                levels. All images have alternative text.</meta>",
+            ]
+        );
+
+        let config = Accessibility {
+            hazards: vec!["flashing".to_string()],
+            summary: Some("Fully accessible".to_string()),
+            conforms_to: Some("EPUB Accessibility 1.1 - WCAG 2.1 Level AA".to_string()),
+            ..Accessibility::default()
+        };
+        let metadata = audit.metadata(&config, EpubVersion::V20);
+        assert_eq!(
+            metadata[metadata.len() - 3..],
+            [
+                "<meta name=\"schema:accessibilityHazard\" content=\"flashing\"/>",
+                "<meta name=\"schema:accessibilitySummary\" content=\"Fully accessible\"/>",
+                "<meta name=\"dcterms:conformsTo\" \
+                content=\"EPUB Accessibility 1.1 - WCAG 2.1 Level AA\"/>",
+            ]
+        );
+    }
+}
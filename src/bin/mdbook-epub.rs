@@ -1,11 +1,11 @@
 #[macro_use]
 extern crate log;
 
-use clap::{value_parser, Parser};
+use clap::{value_parser, Parser, Subcommand};
 use mdbook::renderer::RenderContext;
 use mdbook::MDBook;
 use std::io;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process;
 
 use mdbook_epub::Error;
@@ -23,7 +23,14 @@ fn main() {
     }
 }
 
+/// The setting the `--offline` flag overrides.
+const OFFLINE_KEY: &str = "output.epub.remote.offline";
+
 fn run(args: &Args) -> Result<(), Error> {
+    if let Some(Command::Check { ref file }) = args.command {
+        return check(file);
+    }
+
     // get a `RenderContext`, either from stdin (because we're used as a plugin)
     // or by instrumenting MDBook directly (in standalone mode).
     if args.standalone {
@@ -31,7 +38,10 @@ fn run(args: &Args) -> Result<(), Error> {
             "book.toml root file is not found by a path {:?}",
             &args.root.display()
         );
-        let md = MDBook::load(&args.root).expect(&error);
+        let mut md = MDBook::load(&args.root).expect(&error);
+        if args.offline {
+            md.config.set(OFFLINE_KEY, true)?;
+        }
         let destination = md.build_dir_for("epub");
         debug!(
             "EPUB book destination folder is : {:?}",
@@ -45,15 +55,42 @@ fn run(args: &Args) -> Result<(), Error> {
             mdbook_epub::generate(&ctx)
         }
     } else {
-        let ctx: RenderContext =
+        let mut ctx: RenderContext =
             serde_json::from_reader(io::stdin()).map_err(|_| Error::RenderContext)?;
+        if args.offline {
+            ctx.config.set(OFFLINE_KEY, true)?;
+        }
         mdbook_epub::generate(&ctx)
     }
 }
 
+/// Validate an EPUB file, printing every issue found.
+fn check(file: &Path) -> Result<(), Error> {
+    let report = mdbook_epub::validate(file)?;
+    for issue in &report.issues {
+        println!("{}", issue);
+    }
+    let errors = report.errors().count();
+    if errors > 0 {
+        return Err(Error::EpubCheck(format!(
+            "{} errors found in {}",
+            errors,
+            file.display()
+        )));
+    }
+    println!(
+        "{} is valid ({} warnings)",
+        file.display(),
+        report.warnings().count()
+    );
+    Ok(())
+}
+
 #[derive(Debug, Clone, Parser)]
-#[command(author, version, about, long_about = None)]
+#[command(author, version, about, long_about = None, args_conflicts_with_subcommands = true)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
     #[arg(
         short = 's',
         long = "standalone",
@@ -66,6 +103,20 @@ struct Args {
         help = "Enable preprocessing for standalone mode."
     )]
     preprocess: bool,
+    #[arg(
+        long = "offline",
+        help = "Only use cached remote assets, without accessing the network."
+    )]
+    offline: bool,
     #[arg(help = "The book to render.", value_parser = value_parser!(PathBuf), default_value = ".")]
     root: PathBuf,
 }
+
+#[derive(Debug, Clone, Subcommand)]
+enum Command {
+    /// Check the structure of an EPUB file without the need for `epubcheck`.
+    Check {
+        #[arg(help = "The EPUB file to check.", value_parser = value_parser!(PathBuf))]
+        file: PathBuf,
+    },
+}
@@ -0,0 +1,281 @@
+use std::collections::HashMap;
+
+use handlebars::html_escape;
+use pulldown_cmark::{CodeBlockKind, CowStr, Event, Tag, TagEnd};
+use syntect::highlighting::{Theme, ThemeSet};
+use syntect::html::{css_for_theme_with_class_style, ClassStyle, ClassedHTMLGenerator};
+use syntect::parsing::{SyntaxReference, SyntaxSet};
+use syntect::util::LinesWithEndings;
+
+use crate::Error;
+
+/// Prefix of the highlighting classes, so they don't clash with the classes
+/// of the book's own stylesheets.
+const CLASS_STYLE: ClassStyle = ClassStyle::SpacedPrefixed { prefix: "syn-" };
+
+/// Highlights code blocks at build time, as e-readers don't run the
+/// JavaScript highlighter of the HTML renderer.
+pub(crate) struct Highlighter {
+    syntaxes: SyntaxSet,
+    theme: Theme,
+}
+
+impl Highlighter {
+    /// Load the syntaxes and the theme called `theme_name` bundled with
+    /// syntect.
+    pub(crate) fn new(theme_name: &str) -> Result<Self, Error> {
+        let mut themes = ThemeSet::load_defaults();
+        let theme = themes.themes.remove(theme_name).ok_or_else(|| {
+            let mut names: Vec<_> = themes.themes.keys().map(String::as_str).collect();
+            names.sort_unstable();
+            let message = format!("not found, available themes are {}", names.join(", "));
+            Error::HighlightTheme(theme_name.to_string(), message)
+        })?;
+        Ok(Highlighter {
+            syntaxes: SyntaxSet::load_defaults_newlines(),
+            theme,
+        })
+    }
+
+    /// The stylesheet coloring the highlighted code.
+    pub(crate) fn stylesheet(&self) -> Result<String, Error> {
+        css_for_theme_with_class_style(&self.theme, CLASS_STYLE).map_err(|e| {
+            let name = self.theme.name.clone().unwrap_or_default();
+            Error::HighlightTheme(name, e.to_string())
+        })
+    }
+
+    fn syntax(&self, language: &str) -> Option<&SyntaxReference> {
+        let token = match language {
+            "console" | "shell" | "shell-session" | "zsh" => "bash",
+            other => other,
+        };
+        self.syntaxes.find_syntax_by_token(token)
+    }
+
+    /// Render `code` as HTML with a `<span>` for every token, or `None` if
+    /// the language is unknown.
+    fn highlight(&self, code: &str, language: &str) -> Option<String> {
+        let syntax = self.syntax(language)?;
+        let mut generator =
+            ClassedHTMLGenerator::new_with_class_style(syntax, &self.syntaxes, CLASS_STYLE);
+        for line in LinesWithEndings::from(code) {
+            if let Err(e) = generator.parse_html_for_line_which_includes_newline(line) {
+                warn!("Failed to highlight {} code: {}", language, e);
+                return None;
+            }
+        }
+        Some(generator.finalize())
+    }
+}
+
+/// How the lines of code blocks hidden by mdBook's HTML renderer, like the
+/// `# fn main() {` boilerplate of Rust examples, are rendered.
+#[derive(Debug, Default)]
+pub(crate) struct HiddenLines {
+    /// Keep the hidden lines, without the prefix marking them.
+    pub(crate) show: bool,
+    /// The prefixes of hidden lines by language, the `output.html.code.hidelines`
+    /// table. Rust lines are always hidden with `#`.
+    pub(crate) prefixes: HashMap<String, String>,
+}
+
+impl HiddenLines {
+    /// Remove or reveal the hidden lines of a code block with the given info
+    /// string, like mdBook's HTML renderer does.
+    fn apply(&self, code: &str, info: &str) -> String {
+        let language = language(info);
+        let prefix = info
+            .split([',', ' ', '\t'])
+            .find_map(|attr| attr.strip_prefix("hidelines="))
+            .or_else(|| self.prefixes.get(language).map(String::as_str));
+
+        let mut result = String::with_capacity(code.len());
+        for line in LinesWithEndings::from(code) {
+            let indent_len = line.len() - line.trim_start().len();
+            let (indent, rest) = line.split_at(indent_len);
+            let revealed = if language == "rust" {
+                match rest.strip_prefix('#') {
+                    // `##` escapes a line starting with `#`
+                    Some(escaped) if escaped.starts_with('#') => {
+                        result.push_str(indent);
+                        result.push_str(escaped);
+                        continue;
+                    }
+                    Some(hidden) if hidden.trim_end_matches(['\r', '\n']).is_empty() => hidden,
+                    Some(hidden) => match hidden.strip_prefix(' ') {
+                        Some(hidden) => hidden,
+                        None => {
+                            result.push_str(line);
+                            continue;
+                        }
+                    },
+                    None => {
+                        result.push_str(line);
+                        continue;
+                    }
+                }
+            } else {
+                match prefix.and_then(|prefix| rest.strip_prefix(prefix)) {
+                    Some(hidden) => hidden,
+                    None => {
+                        result.push_str(line);
+                        continue;
+                    }
+                }
+            };
+            if self.show {
+                result.push_str(indent);
+                result.push_str(revealed);
+            }
+        }
+        result
+    }
+}
+
+/// The language of a fenced code block, e.g. `rust` for ```` ```rust,ignore ````.
+fn language(info: &str) -> &str {
+    info.split([',', ' ', '\t']).next().unwrap_or_default()
+}
+
+/// Rewrite the fenced code blocks of a chapter, removing the lines hidden in
+/// the HTML renderer and highlighting the code if there's a `highlighter`.
+pub(crate) fn rewrite_code_blocks<'a, I>(
+    mut events: I,
+    hidden_lines: &HiddenLines,
+    highlighter: Option<&Highlighter>,
+) -> Vec<Event<'a>>
+where
+    I: Iterator<Item = Event<'a>>,
+{
+    let mut rewritten = Vec::new();
+
+    while let Some(event) = events.next() {
+        let Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(ref info))) = event else {
+            rewritten.push(event);
+            continue;
+        };
+        let language = language(info).to_string();
+
+        let mut code = String::new();
+        for event in events.by_ref() {
+            match event {
+                Event::End(TagEnd::CodeBlock) => break,
+                Event::Text(text) => code.push_str(&text),
+                _ => {}
+            }
+        }
+        let code = hidden_lines.apply(&code, info);
+
+        match highlighter.and_then(|highlighter| highlighter.highlight(&code, &language)) {
+            Some(highlighted) => {
+                let html = format!(
+                    "<pre class=\"syn-code\"><code class=\"language-{}\">{}</code></pre>\n",
+                    html_escape(&language),
+                    highlighted
+                );
+                rewritten.push(Event::Html(CowStr::from(html)));
+            }
+            None => {
+                rewritten.push(event);
+                rewritten.push(Event::Text(CowStr::from(code)));
+                rewritten.push(Event::End(TagEnd::CodeBlock));
+            }
+        }
+    }
+
+    rewritten
+}
+
+#[cfg(test)]
+mod tests {
+    use mdbook::utils::new_cmark_parser;
+    use pulldown_cmark::html;
+
+    use super::*;
+
+    fn render(content: &str, highlighter: &Highlighter) -> String {
+        let events = rewrite_code_blocks(
+            new_cmark_parser(content, false),
+            &HiddenLines::default(),
+            Some(highlighter),
+        );
+        let mut buf = String::new();
+        html::push_html(&mut buf, events.into_iter());
+        buf
+    }
+
+    #[test]
+    fn highlight_rust_and_shell() {
+        let highlighter = Highlighter::new("InspiredGitHub").unwrap();
+        let content = "```rust,ignore\nfn main() {}\n```\n\n```console\n$ cargo run\n```\n\n\
+            ```unknown\na < b\n```\n\n    indented\n";
+        let rendered = render(content, &highlighter);
+
+        assert!(rendered.starts_with(
+            "<pre class=\"syn-code\"><code class=\"language-rust\"><span class=\"syn-source syn-rust\">"
+        ));
+        assert!(rendered
+            .contains("<span class=\"syn-entity syn-name syn-function syn-rust\">main</span>"));
+        assert!(rendered
+            .contains("<code class=\"language-console\"><span class=\"syn-source syn-shell"));
+        assert!(rendered.contains(
+            "<pre><code class=\"language-unknown\">a &lt; b\n</code></pre>\n\
+            <pre><code>indented\n</code></pre>"
+        ));
+    }
+
+    #[test]
+    fn theme_stylesheet() {
+        let highlighter = Highlighter::new("base16-ocean.light").unwrap();
+        let css = highlighter.stylesheet().unwrap();
+        assert!(css.contains(".syn-code {\n color: #4f5b66;\n background-color: #eff1f5;\n}"));
+
+        assert!(matches!(
+            Highlighter::new("no-such-theme"),
+            Err(Error::HighlightTheme(name, _)) if name == "no-such-theme"
+        ));
+    }
+
+    #[test]
+    fn hide_rust_lines() {
+        let code = "# #![allow(unused)]\n#fn hidden() {}\n#\n## not hidden\n#[derive(Debug)]\n\
+            struct A;\n    # let x = 1;\nfn main() {}\n";
+        let mut hidden_lines = HiddenLines::default();
+        assert_eq!(
+            hidden_lines.apply(code, "rust,ignore"),
+            "#fn hidden() {}\n# not hidden\n#[derive(Debug)]\nstruct A;\nfn main() {}\n"
+        );
+        assert_eq!(hidden_lines.apply(code, "toml"), code);
+
+        hidden_lines.show = true;
+        assert_eq!(
+            hidden_lines.apply(code, "rust"),
+            "#![allow(unused)]\n#fn hidden() {}\n\n# not hidden\n#[derive(Debug)]\nstruct A;\n    \
+            let x = 1;\nfn main() {}\n"
+        );
+    }
+
+    #[test]
+    fn hide_lines_with_prefix() {
+        let code = "~import os\nprint(1)\n    ~hidden()\n!!!other\n";
+        let hidden_lines = HiddenLines {
+            show: false,
+            prefixes: HashMap::from([("python".to_string(), "~".to_string())]),
+        };
+        assert_eq!(hidden_lines.apply(code, "python"), "print(1)\n!!!other\n");
+        assert_eq!(
+            hidden_lines.apply(code, "python,hidelines=!!!"),
+            "~import os\nprint(1)\n    ~hidden()\n"
+        );
+
+        let content = "```python\n~import os\nprint(1)\n```\n";
+        let events = rewrite_code_blocks(new_cmark_parser(content, false), &hidden_lines, None);
+        let mut rendered = String::new();
+        html::push_html(&mut rendered, events.into_iter());
+        assert_eq!(
+            rendered,
+            "<pre><code class=\"language-python\">print(1)\n</code></pre>\n"
+        );
+    }
+}
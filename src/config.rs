@@ -1,7 +1,9 @@
+use chrono::{DateTime, Utc};
 use epub_builder::EpubVersion;
 use mdbook::renderer::RenderContext;
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
 
 use crate::Error;
 
@@ -20,12 +22,64 @@ pub struct Config {
     pub index_template: Option<PathBuf>,
     /// A cover image to use for the epub.
     pub cover_image: Option<PathBuf>,
+    /// The cover generated if there's no `cover_image`.
+    pub generated_cover: GeneratedCover,
     /// Additional assets to include in the ebook, such as typefaces.
     pub additional_resources: Vec<PathBuf>,
+    /// Fonts embedded with generated `@font-face` rules.
+    pub fonts: Fonts,
+    /// Processing of the embedded images.
+    pub images: Images,
+    /// Downloading of remote assets.
+    pub remote: Remote,
     /// Don't render section labels.
     pub no_section_label: bool,
     /// Use "smart quotes" instead of the usual `"` character.
     pub curly_quotes: bool,
+    /// How the part titles (`# Part I`) of `SUMMARY.md` are rendered.
+    pub part_titles: PartTitles,
+    /// Where EPUB 3 popup footnotes are placed.
+    pub footnotes: Footnotes,
+    /// Additional metadata of the book.
+    pub metadata: Metadata,
+    /// Accessibility metadata of the book and its report.
+    pub accessibility: Accessibility,
+    /// Chapters marked as landmarks of the book, like its index, by their
+    /// path relative to the source directory.
+    pub landmarks: BTreeMap<Landmark, PathBuf>,
+    /// The marker of a page break of the print edition in the Markdown, with
+    /// `{}` in place of the page number, e.g. `<!-- page {} -->`. The page
+    /// breaks are listed in a page list if set.
+    pub page_marker: Option<String>,
+    /// The deepest heading level listed in the table of contents. The default
+    /// of `1` only lists chapters, `3` also lists their `h2` and `h3` headings.
+    pub toc_depth: u32,
+    /// The syntect theme to highlight code blocks with, e.g. `InspiredGitHub`.
+    /// Code blocks aren't highlighted if omitted.
+    pub highlight_theme: Option<String>,
+    /// Keep the lines of code blocks which mdBook hides, like `# fn main() {`
+    /// in Rust examples.
+    pub show_hidden_lines: bool,
+    /// Produce byte-identical output for the same book, see
+    /// [`Config::build_time`].
+    pub reproducible: bool,
+    /// Check the structure of the generated EPUB, failing on any error.
+    pub validate: bool,
+    /// Convert the LaTeX math of books using `output.html.mathjax-support`
+    /// into MathML, or into SVG images in EPUB 2.
+    pub math: bool,
+    /// The command rendering a LaTeX formula, passed as its last argument,
+    /// into an SVG image on its standard output, e.g. `tex2svg` of
+    /// mathjax-node-cli. Inline formulas also get the `--inline` argument.
+    pub math_svg_command: String,
+    /// Languages of chapters differing from `book.language`, by the path of
+    /// a chapter or a directory of chapters relative to the source directory.
+    pub languages: BTreeMap<PathBuf, String>,
+    /// The direction pages are turned in. Follows `writing-mode`, or else
+    /// `book.text-direction`, if omitted.
+    pub page_progression: Option<PageProgression>,
+    /// The direction lines of text are laid out in.
+    pub writing_mode: WritingMode,
     /// EPUB version to use if specified, otherwise defaults to the epub-builder default.
     epub_version: Option<u8>,
 }
@@ -62,6 +116,42 @@ impl Config {
         }
     }
 
+    /// The fixed modification time of a reproducible build: the
+    /// `SOURCE_DATE_EPOCH` environment variable, which also turns on
+    /// reproducible builds, or 1980-01-01, the earliest time a zip file can
+    /// hold. `None` if the build isn't reproducible.
+    pub fn build_time(&self) -> Result<Option<DateTime<Utc>>, Error> {
+        self.build_time_at(std::env::var("SOURCE_DATE_EPOCH").ok().as_deref())
+    }
+
+    fn build_time_at(&self, epoch: Option<&str>) -> Result<Option<DateTime<Utc>>, Error> {
+        match epoch {
+            Some(epoch) => {
+                let time = epoch
+                    .trim()
+                    .parse()
+                    .ok()
+                    .and_then(|secs| DateTime::from_timestamp(secs, 0))
+                    .ok_or_else(|| {
+                        Error::EpubDocCreate(format!("Invalid SOURCE_DATE_EPOCH: {epoch}"))
+                    })?;
+                Ok(Some(time))
+            }
+            None if self.reproducible => Ok(DateTime::from_timestamp(315_532_800, 0)),
+            None => Ok(None),
+        }
+    }
+
+    /// The language configured in `languages` for the chapter at `path`,
+    /// taking the most specific entry.
+    pub fn chapter_language(&self, path: &Path) -> Option<&str> {
+        self.languages
+            .iter()
+            .filter(|(prefix, _)| path.starts_with(prefix))
+            .max_by_key(|(prefix, _)| prefix.components().count())
+            .map(|(_, language)| language.as_str())
+    }
+
     pub fn epub_version(&self) -> Result<EpubVersion, Error> {
         match self.epub_version {
             Some(2) | None => Ok(EpubVersion::V20),
@@ -74,6 +164,333 @@ impl Config {
     }
 }
 
+/// The ways to render the part titles of a book.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PartTitles {
+    /// Add a title page for every part, listed in the table of contents as the
+    /// parent of the part's chapters.
+    Page,
+    /// Only add a table of contents entry, linking to the first chapter of the
+    /// part, as the parent of the part's chapters.
+    Toc,
+    /// Leave part titles out of the document.
+    #[default]
+    Skip,
+}
+
+/// The placements of footnote definitions in an EPUB 3 document.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Footnotes {
+    /// Where they are defined in the chapter.
+    #[default]
+    Inline,
+    /// Collected at the end of every chapter.
+    Chapter,
+    /// Collected from all chapters into one endnotes document.
+    Book,
+}
+
+/// The landmarks a chapter can be marked as, listed in the navigation
+/// document of EPUB 3 and the guide of EPUB 2.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Landmark {
+    /// The start of the main content, the first numbered chapter by default.
+    Bodymatter,
+    Acknowledgements,
+    Bibliography,
+    Colophon,
+    CopyrightPage,
+    Dedication,
+    Epigraph,
+    Foreword,
+    Glossary,
+    Index,
+    /// A list of illustrations.
+    Loi,
+    /// A list of tables.
+    Lot,
+    Preface,
+    Titlepage,
+}
+
+/// The directions pages of an EPUB 3 book are turned in.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PageProgression {
+    /// Left to right, like English books.
+    Ltr,
+    /// Right to left, like Arabic, Hebrew and vertical Japanese books.
+    Rtl,
+}
+
+/// The CSS writing modes of a book.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum WritingMode {
+    /// Horizontal lines stacked from top to bottom.
+    #[default]
+    HorizontalTb,
+    /// Vertical lines stacked from right to left, like traditional Japanese
+    /// and Chinese books.
+    VerticalRl,
+    /// Vertical lines stacked from left to right, like Mongolian books.
+    VerticalLr,
+}
+
+/// Metadata of the book beyond what's in the `[book]` table, set in the
+/// `[output.epub.metadata]` table.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct Metadata {
+    /// The publisher of the book.
+    pub publisher: Option<String>,
+    /// A statement about the rights held in and over the book, e.g. its license.
+    pub rights: Option<String>,
+    /// Subjects or keywords describing the book.
+    pub subjects: Vec<String>,
+    /// The publication date, e.g. `2024-05-01`.
+    pub date: Option<String>,
+    /// The unique identifier of the book, e.g. an ISBN, a UUID or any URN.
+    /// A random UUID is generated otherwise.
+    pub identifier: Option<String>,
+    /// The authors of the book, replacing `book.authors` so they can be
+    /// given sort names.
+    pub authors: Vec<Contributor>,
+    /// Everyone else who contributed to the book, like editors, illustrators
+    /// and translators.
+    pub contributors: Vec<Contributor>,
+    /// The series the book belongs to.
+    pub series: Option<Series>,
+}
+
+/// Fonts embedded in the book, set in the `[output.epub.fonts]` table.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct Fonts {
+    /// The font files and the families they belong to.
+    pub faces: Vec<FontFace>,
+    /// Reduce TrueType and OpenType fonts to the glyphs used in the book.
+    /// This drops kerning and ligatures, so fonts are kept whole if the book
+    /// has text that needs their layout tables, like Arabic.
+    pub subset: bool,
+    /// Obfuscate the fonts, as some licenses require.
+    pub obfuscation: Option<FontObfuscation>,
+}
+
+/// A font file of a font family.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct FontFace {
+    pub family: String,
+    /// The font file, relative to the book root.
+    pub file: PathBuf,
+    /// The `font-weight` of the file, e.g. `bold` or `300`.
+    #[serde(default)]
+    pub weight: Option<String>,
+    /// The `font-style` of the file, e.g. `italic`.
+    #[serde(default)]
+    pub style: Option<String>,
+}
+
+/// The font obfuscation algorithms of EPUB reading systems.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FontObfuscation {
+    /// The algorithm of the EPUB specification.
+    Idpf,
+    /// Adobe's algorithm, for older reading systems. Requires a UUID as the
+    /// identifier of the book.
+    Adobe,
+}
+
+/// Processing of the images embedded in the book, set in the
+/// `[output.epub.images]` table. Images are embedded as they are by default.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct Images {
+    /// Downscale images wider or taller than this many pixels.
+    pub max_size: Option<u32>,
+    /// Recompress JPEG images at this quality, from 1 to 100, and PNG images
+    /// with the best compression.
+    pub quality: Option<u8>,
+    /// Convert WebP images to PNG, which EPUB 2 reading systems support.
+    pub convert: bool,
+    /// Rasterise SVG images to PNG.
+    pub rasterize_svg: bool,
+}
+
+/// Downloading of remote assets, set in the `[output.epub.remote]` table.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct Remote {
+    /// Seconds to wait for connecting and for each read of a response.
+    pub timeout: u64,
+    /// How often a download failing with a network error, a timeout or a
+    /// server error is retried, waiting twice as long before each retry.
+    pub retries: u32,
+    /// How many redirects are followed.
+    pub redirects: u32,
+    /// How many assets are downloaded at the same time.
+    pub workers: usize,
+    /// The directory caching downloaded assets, relative to the book root.
+    /// It can be shared by books, and is `mdbook-epub` in the user's cache
+    /// directory by default.
+    pub cache_dir: Option<PathBuf>,
+    /// Seconds a cached asset is used before it's revalidated.
+    pub cache_ttl: u64,
+    /// Only use cached assets, failing on assets which aren't cached.
+    pub offline: bool,
+    /// Patterns of the hosts assets are downloaded from, `*.example.com`
+    /// matching the subdomains of `example.com`. All hosts are allowed if
+    /// empty.
+    pub allowed_hosts: Vec<String>,
+    /// Patterns of the hosts assets are never downloaded from.
+    pub denied_hosts: Vec<String>,
+    /// The maximum size of an asset in bytes.
+    pub max_bytes: Option<u64>,
+    /// The maximum size of all remote assets of the book in bytes.
+    pub max_total_bytes: Option<u64>,
+    /// The media types of the assets, `image/*` matching all images. All
+    /// media types are allowed if empty.
+    pub media_types: Vec<String>,
+    /// Keep the remote links of assets which aren't allowed instead of
+    /// failing as configured by `on-error`.
+    pub keep_disallowed: bool,
+    /// What happens to an asset which can't be downloaded.
+    pub on_error: RemoteFailure,
+}
+
+impl Default for Remote {
+    fn default() -> Remote {
+        Remote {
+            timeout: 30,
+            retries: 2,
+            redirects: 5,
+            workers: 8,
+            cache_dir: None,
+            cache_ttl: 24 * 60 * 60,
+            offline: false,
+            allowed_hosts: Vec::new(),
+            denied_hosts: Vec::new(),
+            max_bytes: None,
+            max_total_bytes: None,
+            media_types: Vec::new(),
+            keep_disallowed: false,
+            on_error: RemoteFailure::Abort,
+        }
+    }
+}
+
+impl Remote {
+    /// The directory caching downloaded assets, in the build directory if
+    /// the platform has no cache directory.
+    pub(crate) fn cache_dir(&self, root: &Path, destination: &Path) -> PathBuf {
+        match self.cache_dir {
+            Some(ref dir) => root.join(dir),
+            None => dirs::cache_dir()
+                .map(|dir| dir.join("mdbook-epub"))
+                .unwrap_or_else(|| destination.join("cache")),
+        }
+    }
+}
+
+/// What happens to a remote asset which can't be downloaded.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RemoteFailure {
+    /// Fail the build.
+    #[default]
+    Abort,
+    /// Warn and keep the remote link.
+    Skip,
+    /// Warn and embed a placeholder image instead, or keep the remote link of
+    /// other media.
+    Placeholder,
+}
+
+/// A cover rendered from the title, subtitle and authors of the book, set in
+/// the `[output.epub.generated-cover]` table.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct GeneratedCover {
+    /// Generate the cover if no `cover-image` is set.
+    pub enable: bool,
+    /// A line shown below the title.
+    pub subtitle: Option<String>,
+    /// The background colour, in any SVG colour syntax.
+    pub background: String,
+    /// The colour of the text.
+    pub foreground: String,
+    /// The font family of the text.
+    pub font: String,
+    /// An image shown above the title, relative to the source directory.
+    pub logo: Option<PathBuf>,
+}
+
+impl Default for GeneratedCover {
+    fn default() -> GeneratedCover {
+        GeneratedCover {
+            enable: false,
+            subtitle: None,
+            background: String::from("#1f3a5f"),
+            foreground: String::from("#ffffff"),
+            font: String::from("serif"),
+            logo: None,
+        }
+    }
+}
+
+/// Accessibility metadata of the book, set in the `[output.epub.accessibility]`
+/// table. Every list left empty is derived from the content.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct Accessibility {
+    /// The ways the content can be perceived, e.g. `textual` and `visual`.
+    pub access_modes: Vec<String>,
+    /// The combinations of access modes sufficient to perceive all of the
+    /// content, e.g. `textual,visual`.
+    pub access_modes_sufficient: Vec<String>,
+    /// Features making the book accessible, e.g. `alternativeText`.
+    pub features: Vec<String>,
+    /// Physiological hazards of the content, e.g. `flashing`, or `none`.
+    pub hazards: Vec<String>,
+    /// A human-readable summary of the accessibility of the book.
+    pub summary: Option<String>,
+    /// The accessibility standard the book conforms to, e.g.
+    /// `EPUB Accessibility 1.1 - WCAG 2.1 Level AA`.
+    pub conforms_to: Option<String>,
+    /// Who evaluated the conformance of the book.
+    pub certified_by: Option<String>,
+    /// Write an `accessibility-report.txt` next to the book.
+    pub report: bool,
+}
+
+/// A person or organisation contributing to the book.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Contributor {
+    pub name: String,
+    /// The name used for sorting, e.g. `Doe, Jane`.
+    #[serde(default)]
+    pub file_as: Option<String>,
+    /// A MARC relator code (`edt`) or role name (`editor`). Defaults to `aut`
+    /// for authors and `ctb` for other contributors.
+    #[serde(default)]
+    pub role: Option<String>,
+}
+
+/// A series (or any other collection) of books.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Series {
+    pub name: String,
+    /// The position of the book in the series.
+    #[serde(default)]
+    pub position: Option<u32>,
+}
+
 impl Default for Config {
     fn default() -> Config {
         Config {
@@ -81,9 +498,29 @@ impl Default for Config {
             additional_css: Vec::new(),
             index_template: None,
             cover_image: None,
+            generated_cover: GeneratedCover::default(),
             additional_resources: Vec::new(),
+            fonts: Fonts::default(),
+            images: Images::default(),
+            remote: Remote::default(),
             no_section_label: false,
             curly_quotes: false,
+            part_titles: PartTitles::default(),
+            footnotes: Footnotes::default(),
+            metadata: Metadata::default(),
+            accessibility: Accessibility::default(),
+            landmarks: BTreeMap::new(),
+            page_marker: None,
+            toc_depth: 1,
+            highlight_theme: None,
+            show_hidden_lines: false,
+            reproducible: false,
+            validate: false,
+            math: false,
+            math_svg_command: String::from("tex2svg"),
+            languages: BTreeMap::new(),
+            page_progression: None,
+            writing_mode: WritingMode::default(),
             epub_version: None,
         }
     }
@@ -110,11 +547,145 @@ mod tests {
         assert!(matches!(c.epub_version(), Err(Error::EpubDocCreate(_))));
     }
 
+    #[test]
+    fn test_part_titles() {
+        let c = Config::from_render_context(&ctx_with_options(json!({}))).unwrap();
+        assert_eq!(PartTitles::Skip, c.part_titles);
+
+        let options = json!({"part-titles": "toc"});
+        let c = Config::from_render_context(&ctx_with_options(options)).unwrap();
+        assert_eq!(PartTitles::Toc, c.part_titles);
+
+        let options = json!({"part-titles": "chapter"});
+        assert!(Config::from_render_context(&ctx_with_options(options)).is_err());
+    }
+
+    #[test]
+    fn test_metadata() {
+        let options = json!({"metadata": {
+            "publisher": "Acme",
+            "identifier": "978-3-16-148410-0",
+            "authors": [{"name": "Jane Doe", "file-as": "Doe, Jane"}],
+            "contributors": [{"name": "John Roe", "role": "translator"}],
+            "series": {"name": "Manuals", "position": 2}
+        }});
+        let c = Config::from_render_context(&ctx_with_options(options)).unwrap();
+        assert_eq!(c.metadata.publisher.as_deref(), Some("Acme"));
+        assert_eq!(c.metadata.authors[0].file_as.as_deref(), Some("Doe, Jane"));
+        assert_eq!(
+            c.metadata.contributors[0].role.as_deref(),
+            Some("translator")
+        );
+        assert_eq!(
+            c.metadata.series,
+            Some(Series {
+                name: "Manuals".to_string(),
+                position: Some(2)
+            })
+        );
+        assert!(c.metadata.subjects.is_empty());
+    }
+
+    #[test]
+    fn test_chapter_language() {
+        let options = json!({"languages": {"ja": "ja", "ja/appendix/ar.md": "ar"}});
+        let c = Config::from_render_context(&ctx_with_options(options)).unwrap();
+        assert_eq!(c.chapter_language(Path::new("ja/intro.md")), Some("ja"));
+        assert_eq!(
+            c.chapter_language(Path::new("ja/appendix/ar.md")),
+            Some("ar")
+        );
+        assert_eq!(c.chapter_language(Path::new("japan.md")), None);
+        assert_eq!(c.chapter_language(Path::new("intro.md")), None);
+    }
+
+    #[test]
+    fn test_fonts() {
+        let options = json!({"fonts": {
+            "faces": [
+                {"family": "Brand Sans", "file": "fonts/BrandSans.ttf"},
+                {"family": "Brand Sans", "file": "fonts/BrandSans-Bold.ttf", "weight": "bold"}
+            ],
+            "obfuscation": "idpf"
+        }});
+        let c = Config::from_render_context(&ctx_with_options(options)).unwrap();
+        assert_eq!(c.fonts.faces.len(), 2);
+        assert_eq!(c.fonts.faces[1].weight.as_deref(), Some("bold"));
+        assert_eq!(c.fonts.obfuscation, Some(FontObfuscation::Idpf));
+        assert!(!c.fonts.subset);
+    }
+
+    #[test]
+    fn test_generated_cover() {
+        let c = Config::from_render_context(&ctx_with_options(json!({}))).unwrap();
+        assert!(!c.generated_cover.enable);
+
+        let options = json!({"generated-cover": {"enable": true, "background": "navy"}});
+        let c = Config::from_render_context(&ctx_with_options(options)).unwrap();
+        assert!(c.generated_cover.enable);
+        assert_eq!(c.generated_cover.background, "navy");
+        assert_eq!(c.generated_cover.foreground, "#ffffff");
+    }
+
+    #[test]
+    fn test_landmarks() {
+        let options = json!({
+            "landmarks": {"index": "index.md", "copyright-page": "front/copyright.md"},
+            "page-marker": "<!-- page {} -->"
+        });
+        let c = Config::from_render_context(&ctx_with_options(options)).unwrap();
+        assert_eq!(
+            c.landmarks.get(&Landmark::CopyrightPage),
+            Some(&PathBuf::from("front/copyright.md"))
+        );
+        assert_eq!(c.landmarks.len(), 2);
+        assert_eq!(c.page_marker.as_deref(), Some("<!-- page {} -->"));
+
+        let options = json!({"landmarks": {"appendix": "appendix.md"}});
+        assert!(Config::from_render_context(&ctx_with_options(options)).is_err());
+    }
+
+    #[test]
+    fn test_writing_direction() {
+        let c = Config::from_render_context(&ctx_with_options(json!({}))).unwrap();
+        assert_eq!(c.page_progression, None);
+        assert_eq!(c.writing_mode, WritingMode::HorizontalTb);
+
+        let options = json!({"page-progression": "rtl", "writing-mode": "vertical-rl"});
+        let c = Config::from_render_context(&ctx_with_options(options)).unwrap();
+        assert_eq!(c.page_progression, Some(PageProgression::Rtl));
+        assert_eq!(c.writing_mode, WritingMode::VerticalRl);
+    }
+
+    #[test]
+    fn test_build_time() {
+        let mut c = Config::default();
+        assert_eq!(c.build_time_at(None).unwrap(), None);
+        c.reproducible = true;
+        assert_eq!(
+            c.build_time_at(None).unwrap(),
+            DateTime::parse_from_rfc3339("1980-01-01T00:00:00Z")
+                .ok()
+                .map(|t| t.to_utc())
+        );
+
+        let c = Config::default();
+        assert_eq!(
+            c.build_time_at(Some("1700000000")).unwrap(),
+            DateTime::from_timestamp(1_700_000_000, 0)
+        );
+        assert!(c.build_time_at(Some("yesterday")).is_err());
+    }
+
     fn ctx_with_version(ver: Option<u8>) -> RenderContext {
         let options = match ver {
             Some(v) => json!({"epub-version": v}),
             None => json!({}),
         };
+        ctx_with_options(options)
+    }
+
+    fn ctx_with_options(options: serde_json::Value) -> RenderContext {
         let ctx = json!({
             "version": mdbook::MDBOOK_VERSION,
             "root": "tests/dummy",
@@ -0,0 +1,149 @@
+use std::fmt::Write;
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+use crate::config::GeneratedCover;
+use crate::package::xml_escape;
+
+const WIDTH: u32 = 1200;
+const HEIGHT: u32 = 1800;
+/// The space left free on each side of the text.
+const MARGIN: u32 = 120;
+const TITLE_SIZE: u32 = 110;
+const SUBTITLE_SIZE: u32 = 60;
+const AUTHOR_SIZE: u32 = 54;
+const LOGO_SIZE: u32 = 280;
+
+/// The text of a generated cover.
+#[derive(Debug)]
+pub(crate) struct CoverText<'a> {
+    pub(crate) title: &'a str,
+    pub(crate) subtitle: Option<&'a str>,
+    pub(crate) authors: Vec<&'a str>,
+    pub(crate) language: &'a str,
+}
+
+/// Render an SVG cover with the title, subtitle and authors of the book. The
+/// logo is embedded as a data URL, as covers shown as images can't load
+/// other files.
+pub(crate) fn svg(
+    text: &CoverText,
+    config: &GeneratedCover,
+    logo: Option<(&[u8], &str)>,
+) -> String {
+    let mut svg = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+        <svg xmlns=\"http://www.w3.org/2000/svg\" xmlns:xlink=\"http://www.w3.org/1999/xlink\" \
+        version=\"1.1\" width=\"{WIDTH}\" height=\"{HEIGHT}\" viewBox=\"0 0 {WIDTH} {HEIGHT}\" \
+        xml:lang=\"{}\">\n<title>{}</title>\n\
+        <rect width=\"{WIDTH}\" height=\"{HEIGHT}\" fill=\"{}\"/>\n",
+        xml_escape(text.language),
+        xml_escape(text.title),
+        xml_escape(&config.background),
+    );
+
+    let mut y = HEIGHT / 4;
+    if let Some((data, mime)) = logo {
+        writeln!(
+            svg,
+            "<image x=\"{}\" y=\"{}\" width=\"{LOGO_SIZE}\" height=\"{LOGO_SIZE}\" \
+            xlink:href=\"data:{};base64,{}\"/>",
+            (WIDTH - LOGO_SIZE) / 2,
+            MARGIN,
+            mime,
+            STANDARD.encode(data)
+        )
+        .unwrap();
+        y = y.max(MARGIN * 2 + LOGO_SIZE);
+    }
+
+    writeln!(
+        svg,
+        "<g fill=\"{}\" font-family=\"{}\" text-anchor=\"middle\">",
+        xml_escape(&config.foreground),
+        xml_escape(&config.font)
+    )
+    .unwrap();
+    for line in wrap(text.title, TITLE_SIZE) {
+        y += TITLE_SIZE * 6 / 5;
+        svg.push_str(&text_element(&line, y, TITLE_SIZE, " font-weight=\"bold\""));
+    }
+    if let Some(subtitle) = text.subtitle {
+        y += SUBTITLE_SIZE;
+        for line in wrap(subtitle, SUBTITLE_SIZE) {
+            y += SUBTITLE_SIZE * 6 / 5;
+            svg.push_str(&text_element(&line, y, SUBTITLE_SIZE, ""));
+        }
+    }
+    // authors are stacked up from the bottom
+    let mut y = HEIGHT - MARGIN;
+    for author in text.authors.iter().rev() {
+        svg.push_str(&text_element(author, y, AUTHOR_SIZE, ""));
+        y -= AUTHOR_SIZE * 6 / 5;
+    }
+    svg.push_str("</g>\n</svg>\n");
+    svg
+}
+
+fn text_element(text: &str, y: u32, size: u32, attributes: &str) -> String {
+    format!(
+        "<text x=\"{}\" y=\"{y}\" font-size=\"{size}\"{attributes}>{}</text>\n",
+        WIDTH / 2,
+        xml_escape(text)
+    )
+}
+
+/// Break text into lines fitting the width of the cover, estimating the
+/// width of a character as half the font size since SVG doesn't wrap text.
+fn wrap(text: &str, size: u32) -> Vec<String> {
+    let max = ((WIDTH - 2 * MARGIN) / (size / 2)) as usize;
+    let mut lines: Vec<String> = Vec::new();
+    for word in text.split_whitespace() {
+        match lines.last_mut() {
+            Some(line) if line.chars().count() + 1 + word.chars().count() <= max => {
+                line.push(' ');
+                line.push_str(word);
+            }
+            _ => lines.push(word.to_string()),
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wrap_long_titles() {
+        assert_eq!(
+            wrap("The Rust Programming Language", TITLE_SIZE),
+            ["The Rust", "Programming", "Language"]
+        );
+        assert_eq!(wrap("Handbook", TITLE_SIZE), ["Handbook"]);
+        assert!(wrap("  ", TITLE_SIZE).is_empty());
+    }
+
+    #[test]
+    fn render_cover() {
+        let text = CoverText {
+            title: "Ops & Support",
+            subtitle: Some("Internal handbook"),
+            authors: vec!["Jane Doe", "Max Mustermann"],
+            language: "en",
+        };
+        let config = GeneratedCover {
+            background: "#000".to_string(),
+            ..GeneratedCover::default()
+        };
+        let svg = svg(&text, &config, Some((b"<svg/>", "image/svg+xml")));
+
+        assert!(roxmltree::Document::parse(&svg).is_ok());
+        assert!(svg.contains("<title>Ops &amp; Support</title>"));
+        assert!(svg.contains("<rect width=\"1200\" height=\"1800\" fill=\"#000\"/>"));
+        assert!(svg.contains("xlink:href=\"data:image/svg+xml;base64,PHN2Zy8+\""));
+        assert!(svg.contains(">Internal handbook</text>"));
+        assert!(svg.contains("y=\"1680\" font-size=\"54\">Max Mustermann</text>"));
+        assert!(svg.contains("y=\"1616\" font-size=\"54\">Jane Doe</text>"));
+    }
+}
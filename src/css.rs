@@ -0,0 +1,140 @@
+/// A reference to another file in a stylesheet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Reference<'a> {
+    /// The URL of a `url()`, e.g. of a font or a background image.
+    Url(&'a str),
+    /// The URL of an `@import` rule, with its media queries, which may be
+    /// empty.
+    Import(&'a str, &'a str),
+}
+
+/// Replace the references of a stylesheet by what `resolve` returns for
+/// them: a new URL for a `url()`, the CSS replacing an `@import` rule. The
+/// reference is kept as it is if `resolve` returns `None`.
+pub(crate) fn rewrite<F>(css: &str, mut resolve: F) -> String
+where
+    F: FnMut(Reference) -> Option<String>,
+{
+    let mut rewritten = String::with_capacity(css.len());
+    let mut rest = css;
+
+    while let Some(start) = rest.find(['/', '@', 'u', 'U']) {
+        rewritten.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        if rest.starts_with("/*") {
+            let end = rest.find("*/").map_or(rest.len(), |end| end + 2);
+            rewritten.push_str(&rest[..end]);
+            rest = &rest[end..];
+        } else if let Some((url, end)) = url_function(rest) {
+            match resolve(Reference::Url(url)) {
+                Some(url) => rewritten.push_str(&format!("url(\"{url}\")")),
+                None => rewritten.push_str(&rest[..end]),
+            }
+            rest = &rest[end..];
+        } else if let Some((url, media, end)) = import_rule(rest) {
+            match resolve(Reference::Import(url, media)) {
+                Some(css) => rewritten.push_str(&css),
+                None => rewritten.push_str(&rest[..end]),
+            }
+            rest = &rest[end..];
+        } else {
+            let len = rest.chars().next().map_or(0, char::len_utf8);
+            rewritten.push_str(&rest[..len]);
+            rest = &rest[len..];
+        }
+    }
+
+    rewritten.push_str(rest);
+    rewritten
+}
+
+/// Parse the `url()` at the start of `css`, returning its URL and length.
+fn url_function(css: &str) -> Option<(&str, usize)> {
+    if !css.get(..4)?.eq_ignore_ascii_case("url(") {
+        return None;
+    }
+    let inner = &css[4..];
+    let trimmed = inner.trim_start();
+    let offset = 4 + inner.len() - trimmed.len();
+    let (url, len) = match trimmed.chars().next()? {
+        quote @ ('"' | '\'') => {
+            let end = trimmed[1..].find(quote)?;
+            let close = trimmed[end + 2..].find(')')?;
+            (&trimmed[1..end + 1], end + 2 + close + 1)
+        }
+        _ => {
+            let close = trimmed.find(')')?;
+            (trimmed[..close].trim_end(), close + 1)
+        }
+    };
+    Some((url, offset + len))
+}
+
+/// Parse the `@import` rule at the start of `css`, returning its URL, media
+/// queries and length.
+fn import_rule(css: &str) -> Option<(&str, &str, usize)> {
+    if !css.get(..7)?.eq_ignore_ascii_case("@import") {
+        return None;
+    }
+    let inner = &css[7..];
+    let trimmed = inner.trim_start();
+    let offset = 7 + inner.len() - trimmed.len();
+    let (url, len) = match trimmed.chars().next()? {
+        quote @ ('"' | '\'') => {
+            let end = trimmed[1..].find(quote)?;
+            (&trimmed[1..end + 1], end + 2)
+        }
+        _ => url_function(trimmed)?,
+    };
+    let end = trimmed[len..].find(';')?;
+    let media = trimmed[len..len + end].trim();
+    Some((url, media, offset + len + end + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn find_references() {
+        let css = "@import \"base.css\";\n@import url(print.css) print;\n\
+            /* url(comment.png) */\n\
+            @font-face { src: url( 'fonts/Inter.woff2' ) format(\"woff2\"), URL(x.ttf); }\n\
+            body { background: url(\"../img/bg.png\"); }\n\
+            .u { underline: none; }";
+        let mut references = Vec::new();
+        let rewritten = rewrite(css, |reference| {
+            references.push(format!("{reference:?}"));
+            match reference {
+                Reference::Url("x.ttf") => None,
+                Reference::Url(url) => Some(format!("new/{url}")),
+                Reference::Import(url, _) => Some(format!("/* {url} */")),
+            }
+        });
+
+        assert_eq!(
+            references,
+            [
+                "Import(\"base.css\", \"\")",
+                "Import(\"print.css\", \"print\")",
+                "Url(\"fonts/Inter.woff2\")",
+                "Url(\"x.ttf\")",
+                "Url(\"../img/bg.png\")",
+            ]
+        );
+        assert_eq!(
+            rewritten,
+            "/* base.css */\n/* print.css */\n/* url(comment.png) */\n\
+            @font-face { src: url(\"new/fonts/Inter.woff2\") format(\"woff2\"), URL(x.ttf); }\n\
+            body { background: url(\"new/../img/bg.png\"); }\n\
+            .u { underline: none; }"
+        );
+    }
+
+    #[test]
+    fn keep_incomplete_references() {
+        let css = "a { background: url(unclosed.png }\n@import \"no-semicolon.css\"";
+        assert_eq!(rewrite(css, |_| Some(String::new())), css);
+    }
+}
@@ -0,0 +1,216 @@
+use std::collections::BTreeSet;
+
+use subsetter::Profile;
+use ttf_parser::{Face, Tag};
+
+use crate::config::{FontFace, FontObfuscation};
+use crate::Error;
+
+/// Directory of the embedded fonts in the EPUB.
+pub(crate) const FONT_DIR: &str = "fonts";
+
+/// The `@font-face` rule of an embedded font, at `href` relative to the
+/// stylesheet.
+pub(crate) fn font_face_rule(face: &FontFace, href: &str) -> String {
+    let mut rule = format!(
+        "\n@font-face {{\n    font-family: \"{}\";\n",
+        face.family.replace('"', "\\\"")
+    );
+    if let Some(ref weight) = face.weight {
+        rule.push_str(&format!("    font-weight: {weight};\n"));
+    }
+    if let Some(ref style) = face.style {
+        rule.push_str(&format!("    font-style: {style};\n"));
+    }
+    rule.push_str(&format!("    src: url(\"{href}\");\n}}\n"));
+    rule
+}
+
+/// Reduce a TrueType or OpenType font to the glyphs of `chars`, keeping
+/// their glyph ids. Layout tables like kerning and ligatures are dropped, so
+/// fonts with them aren't subset if any of `chars` needs shaping, returning
+/// `None`.
+pub(crate) fn subset(data: &[u8], chars: &BTreeSet<char>) -> Result<Option<Vec<u8>>, String> {
+    let face = Face::parse(data, 0).map_err(|e| e.to_string())?;
+    let layout = [b"GSUB", b"GPOS"]
+        .iter()
+        .any(|tag| face.raw_face().table(Tag::from_bytes(tag)).is_some());
+    if layout && chars.iter().any(|&c| needs_shaping(c)) {
+        return Ok(None);
+    }
+    // the glyph for missing characters is always kept
+    let mut glyphs = vec![0];
+    glyphs.extend(
+        chars
+            .iter()
+            .filter_map(|&c| face.glyph_index(c))
+            .map(|id| id.0),
+    );
+    glyphs.sort_unstable();
+    glyphs.dedup();
+    subsetter::subset(data, 0, Profile::pdf(&glyphs))
+        .map(Some)
+        .map_err(|e| e.to_string())
+}
+
+/// Whether a character is a combining mark or of a script whose glyphs are
+/// joined, reordered or positioned by the layout tables of a font.
+fn needs_shaping(c: char) -> bool {
+    matches!(c as u32,
+        0x0300..=0x036F // combining diacritical marks
+        | 0x0591..=0x05C7 // Hebrew points
+        | 0x0600..=0x08FF // Arabic, Syriac, Thaana, N'Ko and others
+        | 0x0900..=0x0DFF // Indic scripts
+        | 0x0F00..=0x109F // Tibetan and Myanmar
+        | 0x1780..=0x18AF // Khmer and Mongolian
+        | 0x1A00..=0x1CFF // Buginese to Vedic extensions
+        | 0x1DC0..=0x1DFF // combining diacritical marks supplement
+        | 0x20D0..=0x20FF // combining marks for symbols
+        | 0xA800..=0xABFF // Syloti Nagri to Meetei Mayek
+        | 0xFB1D..=0xFDFF // Hebrew and Arabic presentation forms
+        | 0xFE20..=0xFE2F // combining half marks
+        | 0xFE70..=0xFEFF // Arabic presentation forms
+        | 0x10A00..=0x10A5F // Kharoshthi
+        | 0x11000..=0x11DFF // Brahmi and other historic Indic scripts
+    )
+}
+
+/// The number of leading bytes of a font obfuscated by an algorithm.
+fn obfuscated_len(obfuscation: FontObfuscation) -> usize {
+    match obfuscation {
+        FontObfuscation::Idpf => 1040,
+        FontObfuscation::Adobe => 1024,
+    }
+}
+
+/// The URI of an obfuscation algorithm in `encryption.xml`.
+fn algorithm(obfuscation: FontObfuscation) -> &'static str {
+    match obfuscation {
+        FontObfuscation::Idpf => "http://www.idpf.org/2008/embedding",
+        FontObfuscation::Adobe => "http://ns.adobe.com/pdf/enc#RC",
+    }
+}
+
+/// The obfuscation key derived from the unique identifier of the book: its
+/// SHA-1 hash for the IDPF algorithm, the bytes of its UUID for Adobe's.
+pub(crate) fn obfuscation_key(
+    identifier: &str,
+    obfuscation: FontObfuscation,
+) -> Result<Vec<u8>, Error> {
+    match obfuscation {
+        FontObfuscation::Idpf => {
+            let identifier: String = identifier
+                .chars()
+                .filter(|c| !matches!(c, ' ' | '\t' | '\r' | '\n'))
+                .collect();
+            Ok(sha1_smol::Sha1::from(identifier).digest().bytes().to_vec())
+        }
+        FontObfuscation::Adobe => {
+            let uuid = identifier.trim();
+            let uuid = uuid.strip_prefix("urn:uuid:").unwrap_or(uuid);
+            uuid::Uuid::parse_str(uuid)
+                .map(|uuid| uuid.as_bytes().to_vec())
+                .map_err(|_| {
+                    Error::EpubDocCreate(format!(
+                        "Adobe font obfuscation needs a UUID identifier, got \"{identifier}\""
+                    ))
+                })
+        }
+    }
+}
+
+/// Obfuscate, or deobfuscate, a font by XORing its leading bytes with the
+/// key.
+pub(crate) fn obfuscate(data: &mut [u8], key: &[u8], obfuscation: FontObfuscation) {
+    let len = data.len().min(obfuscated_len(obfuscation));
+    for (byte, key) in data[..len].iter_mut().zip(key.iter().cycle()) {
+        *byte ^= key;
+    }
+}
+
+/// The `META-INF/encryption.xml` declaring the obfuscated fonts by their
+/// path in the EPUB.
+pub(crate) fn encryption_xml(fonts: &[(String, FontObfuscation)]) -> String {
+    let mut xml = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+        <encryption xmlns=\"urn:oasis:names:tc:opendocument:xmlns:container\" \
+        xmlns:enc=\"http://www.w3.org/2001/04/xmlenc#\">\n",
+    );
+    for (path, obfuscation) in fonts {
+        xml.push_str(&format!(
+            "  <enc:EncryptedData>\n    <enc:EncryptionMethod Algorithm=\"{}\"/>\n    \
+            <enc:CipherData>\n      <enc:CipherReference URI=\"{}\"/>\n    \
+            </enc:CipherData>\n  </enc:EncryptedData>\n",
+            algorithm(*obfuscation),
+            crate::package::xml_escape(path)
+        ));
+    }
+    xml.push_str("</encryption>\n");
+    xml
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn subset_font() {
+        let font = mdbook::theme::FONT_AWESOME_TTF;
+        let chars = BTreeSet::from(['\u{f000}', '\u{f001}']);
+        let subset = subset(font, &chars).unwrap().unwrap();
+        assert!(subset.len() < font.len() / 10);
+
+        let face = Face::parse(&subset, 0).unwrap();
+        let glyph = face.glyph_index('\u{f000}').unwrap();
+        assert!(face.glyph_bounding_box(glyph).is_some());
+        let dropped = face.glyph_index('\u{f002}').unwrap();
+        assert!(face.glyph_bounding_box(dropped).is_none());
+
+        assert!(super::subset(b"not a font", &chars).is_err());
+    }
+
+    #[test]
+    fn keep_fonts_shaping_text() {
+        // the font with its GDEF table renamed to GPOS, keeping the tables sorted
+        let mut font = mdbook::theme::FONT_AWESOME_TTF.to_vec();
+        let table = font.windows(4).position(|tag| tag == b"GDEF").unwrap();
+        font[table..table + 4].copy_from_slice(b"GPOS");
+
+        let latin = BTreeSet::from(['a', '\u{f000}']);
+        assert!(subset(&font, &latin).unwrap().is_some());
+        let arabic = BTreeSet::from(['\u{0628}', '\u{f000}']);
+        assert!(subset(&font, &arabic).unwrap().is_none());
+        // fonts without layout tables don't shape any text
+        assert!(subset(mdbook::theme::FONT_AWESOME_TTF, &arabic)
+            .unwrap()
+            .is_some());
+    }
+
+    #[test]
+    fn obfuscate_fonts() {
+        let identifier = "urn:uuid:a3b3a2e4-0ec5-4a6c-8e3b-1c6d2f5e8b90";
+        let key = obfuscation_key(identifier, FontObfuscation::Idpf).unwrap();
+        assert_eq!(key.len(), 20);
+        assert_eq!(
+            key,
+            obfuscation_key(&format!(" {identifier}\n"), FontObfuscation::Idpf).unwrap()
+        );
+
+        let font = vec![7u8; 2000];
+        let mut obfuscated = font.clone();
+        obfuscate(&mut obfuscated, &key, FontObfuscation::Idpf);
+        assert_ne!(obfuscated[..1040], font[..1040]);
+        assert_eq!(obfuscated[1040..], font[1040..]);
+        obfuscate(&mut obfuscated, &key, FontObfuscation::Idpf);
+        assert_eq!(obfuscated, font);
+
+        let key = obfuscation_key(identifier, FontObfuscation::Adobe).unwrap();
+        assert_eq!(key[..2], [0xa3, 0xb3]);
+        assert!(obfuscation_key("978-3-16-148410-0", FontObfuscation::Adobe).is_err());
+
+        let xml = encryption_xml(&[("OEBPS/fonts/a.ttf".to_string(), FontObfuscation::Idpf)]);
+        assert!(xml
+            .contains("<enc:EncryptionMethod Algorithm=\"http://www.idpf.org/2008/embedding\"/>"));
+        assert!(xml.contains("<enc:CipherReference URI=\"OEBPS/fonts/a.ttf\"/>"));
+    }
+}
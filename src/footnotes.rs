@@ -0,0 +1,288 @@
+use std::collections::{HashMap, HashSet};
+
+use pulldown_cmark::{html, CowStr, Event, Tag, TagEnd};
+
+/// Rewrite footnote references and definitions into EPUB 3 popup footnotes,
+/// i.e. `epub:type="noteref"` links to `<aside epub:type="footnote">` notes.
+pub(crate) struct FootnoteRewriter<'s> {
+    /// Where the footnote definitions go.
+    placement: Placement<'s>,
+    /// Prepended to all ids so they are unique across the book.
+    id_prefix: &'s str,
+    /// Footnote numbers by label, in order of first appearance.
+    numbers: HashMap<String, usize>,
+    /// How often every footnote has been referenced so far.
+    references: HashMap<String, usize>,
+    /// The part of the ids of every footnote made from its label.
+    slugs: HashMap<String, String>,
+    /// All ids used so far, as labels differing in punctuation only would
+    /// otherwise get the same slug.
+    ids: HashSet<String>,
+}
+
+/// Placement of footnote definitions.
+pub(crate) enum Placement<'s> {
+    /// Where they are defined in the chapter.
+    Inline,
+    /// Collected at the end of the chapter.
+    Chapter,
+    /// Collected in the endnotes document at `href`, as seen from the chapter.
+    /// The chapter itself is at `back_href` as seen from the endnotes.
+    Book { href: &'s str, back_href: &'s str },
+}
+
+impl<'s> FootnoteRewriter<'s> {
+    pub(crate) fn new(placement: Placement<'s>, id_prefix: &'s str) -> Self {
+        Self {
+            placement,
+            id_prefix,
+            numbers: HashMap::new(),
+            references: HashMap::new(),
+            slugs: HashMap::new(),
+            ids: HashSet::new(),
+        }
+    }
+
+    /// Rewrite the chapter's events, returning the rendered notes which are
+    /// to be placed in the endnotes document in [`Placement::Book`] mode.
+    pub(crate) fn rewrite<'a, I>(mut self, mut events: I) -> (Vec<Event<'a>>, Vec<String>)
+    where
+        I: Iterator<Item = Event<'a>>,
+    {
+        let mut rewritten = Vec::new();
+        let mut notes = Vec::new();
+
+        while let Some(event) = events.next() {
+            match event {
+                Event::FootnoteReference(label) => {
+                    rewritten.push(Event::Html(CowStr::from(self.reference(&label))));
+                }
+                Event::Start(Tag::FootnoteDefinition(label)) => {
+                    let mut inner = Vec::new();
+                    for event in events.by_ref() {
+                        match event {
+                            Event::End(TagEnd::FootnoteDefinition) => break,
+                            Event::FootnoteReference(label) => {
+                                inner.push(Event::Html(CowStr::from(self.reference(&label))))
+                            }
+                            _ => inner.push(event),
+                        }
+                    }
+                    let note = self.definition(&label, inner);
+                    match self.placement {
+                        Placement::Inline => rewritten.push(Event::Html(CowStr::from(note))),
+                        Placement::Chapter | Placement::Book { .. } => notes.push(note),
+                    }
+                }
+                _ => rewritten.push(event),
+            }
+        }
+
+        if let Placement::Chapter = self.placement {
+            if !notes.is_empty() {
+                let section = format!(
+                    "<section class=\"footnotes\" epub:type=\"footnotes\">\n{}</section>\n",
+                    notes.concat()
+                );
+                rewritten.push(Event::Html(CowStr::from(section)));
+                notes.clear();
+            }
+        }
+        (rewritten, notes)
+    }
+
+    fn number(&mut self, label: &str) -> usize {
+        let next = self.numbers.len() + 1;
+        *self.numbers.entry(label.to_string()).or_insert(next)
+    }
+
+    fn id(&mut self, kind: &str, label: &str) -> String {
+        let slug = match self.slugs.get(label) {
+            Some(slug) => slug.clone(),
+            None => self.slug(label),
+        };
+        format!("{}{}-{}", kind, self.id_prefix, slug)
+    }
+
+    /// Turn a label into a part of an id, unique among the footnotes.
+    fn slug(&mut self, label: &str) -> String {
+        let base: String = label
+            .chars()
+            .map(|c| if c.is_alphanumeric() { c } else { '-' })
+            .collect();
+        let ids =
+            |slug: &str| ["fn", "fnref"].map(|kind| format!("{}{}-{}", kind, self.id_prefix, slug));
+        let mut slug = base.clone();
+        let mut counter = 0;
+        while ids(&slug).iter().any(|id| self.ids.contains(id)) {
+            counter += 1;
+            slug = format!("{base}-{counter}");
+        }
+        self.ids.extend(ids(&slug));
+        self.slugs.insert(label.to_string(), slug.clone());
+        slug
+    }
+
+    /// Make an id unique among the footnotes by appending a counter.
+    fn unique(&mut self, id: String) -> String {
+        let mut unique = id.clone();
+        let mut counter = 0;
+        while self.ids.contains(&unique) {
+            counter += 1;
+            unique = format!("{id}-{counter}");
+        }
+        self.ids.insert(unique.clone());
+        unique
+    }
+
+    fn reference(&mut self, label: &str) -> String {
+        let number = self.number(label);
+        let count = self.references.entry(label.to_string()).or_insert(0);
+        *count += 1;
+        // Only the first reference is the target of the note's back link.
+        let id = match *count {
+            1 => self.id("fnref", label),
+            n => {
+                let id = format!("{}-{}", self.id("fnref", label), n);
+                self.unique(id)
+            }
+        };
+        let href = match self.placement {
+            Placement::Book { href, .. } => href,
+            _ => "",
+        };
+        let note_id = self.id("fn", label);
+        format!(
+            "<sup class=\"footnote-reference\"><a epub:type=\"noteref\" id=\"{}\" href=\"{}#{}\">{}</a></sup>",
+            id, href, note_id, number
+        )
+    }
+
+    fn definition(&mut self, label: &str, inner: Vec<Event>) -> String {
+        let number = self.number(label);
+        let (note_type, back_href) = match self.placement {
+            Placement::Book { back_href, .. } => ("endnote", back_href),
+            _ => ("footnote", ""),
+        };
+        let mut body = String::new();
+        html::push_html(&mut body, inner.into_iter());
+        let (id, reference_id) = (self.id("fn", label), self.id("fnref", label));
+        format!(
+            "<aside class=\"footnote-definition\" epub:type=\"{}\" id=\"{}\">\
+            <sup class=\"footnote-definition-label\"><a href=\"{}#{}\">{}</a></sup>\n{}</aside>\n",
+            note_type, id, back_href, reference_id, number, body
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mdbook::utils::new_cmark_parser;
+
+    use super::*;
+
+    const CONTENT: &str = "Text[^note] and more[^2].\n\n[^note]: A *note*.\n\nAgain[^note].\n\n\
+        [^2]: Second.\n";
+
+    fn render(placement: Placement) -> (String, Vec<String>) {
+        let rewriter = FootnoteRewriter::new(placement, "");
+        let (events, notes) = rewriter.rewrite(new_cmark_parser(CONTENT, false));
+        let mut buf = String::new();
+        html::push_html(&mut buf, events.into_iter());
+        (buf, notes)
+    }
+
+    #[test]
+    fn inline_footnotes() {
+        let (rendered, notes) = render(Placement::Inline);
+        assert!(notes.is_empty());
+        assert_eq!(
+            rendered,
+            "<p>Text<sup class=\"footnote-reference\"><a epub:type=\"noteref\" id=\"fnref-note\" \
+            href=\"#fn-note\">1</a></sup> and more<sup class=\"footnote-reference\">\
+            <a epub:type=\"noteref\" id=\"fnref-2\" href=\"#fn-2\">2</a></sup>.</p>\n\
+            <aside class=\"footnote-definition\" epub:type=\"footnote\" id=\"fn-note\">\
+            <sup class=\"footnote-definition-label\"><a href=\"#fnref-note\">1</a></sup>\n\
+            <p>A <em>note</em>.</p>\n</aside>\n\
+            <p>Again<sup class=\"footnote-reference\"><a epub:type=\"noteref\" id=\"fnref-note-2\" \
+            href=\"#fn-note\">1</a></sup>.</p>\n\
+            <aside class=\"footnote-definition\" epub:type=\"footnote\" id=\"fn-2\">\
+            <sup class=\"footnote-definition-label\"><a href=\"#fnref-2\">2</a></sup>\n\
+            <p>Second.</p>\n</aside>\n"
+        );
+    }
+
+    #[test]
+    fn footnotes_at_chapter_end() {
+        let (rendered, notes) = render(Placement::Chapter);
+        assert!(notes.is_empty());
+        let section = rendered
+            .find("<section class=\"footnotes\" epub:type=\"footnotes\">\n<aside")
+            .unwrap();
+        assert!(rendered[..section].contains("<p>Again"));
+        assert!(rendered.ends_with("<p>Second.</p>\n</aside>\n</section>\n"));
+    }
+
+    #[test]
+    fn footnotes_in_endnotes_document() {
+        let placement = Placement::Book {
+            href: "../endnotes.html",
+            back_href: "ch/one.html",
+        };
+        let (events, notes) =
+            FootnoteRewriter::new(placement, "-ch-one").rewrite(new_cmark_parser(CONTENT, false));
+        let mut rendered = String::new();
+        html::push_html(&mut rendered, events.into_iter());
+
+        assert!(!rendered.contains("<aside"));
+        assert!(rendered.contains(
+            "<a epub:type=\"noteref\" id=\"fnref-ch-one-note\" href=\"../endnotes.html#fn-ch-one-note\">1</a>"
+        ));
+        assert_eq!(notes.len(), 2);
+        assert!(notes[0].starts_with(
+            "<aside class=\"footnote-definition\" epub:type=\"endnote\" id=\"fn-ch-one-note\">\
+            <sup class=\"footnote-definition-label\"><a href=\"ch/one.html#fnref-ch-one-note\">1</a>"
+        ));
+    }
+
+    #[test]
+    fn unique_footnote_ids() {
+        let content = "One[^a.b], two[^a-b], three[^a b], again[^a.b].\n\n\
+            [^a.b]: First.\n[^a-b]: Second.\n[^a b]: Third.\n";
+        let (events, _) =
+            FootnoteRewriter::new(Placement::Inline, "").rewrite(new_cmark_parser(content, false));
+        let mut rendered = String::new();
+        html::push_html(&mut rendered, events.into_iter());
+
+        for id in [
+            "fnref-a-b",
+            "fnref-a-b-1",
+            "fnref-a-b-2",
+            "fnref-a-b-2-1",
+            "fn-a-b",
+            "fn-a-b-1",
+            "fn-a-b-2",
+        ] {
+            assert_eq!(rendered.matches(&format!("id=\"{id}\"")).count(), 1, "{id}");
+        }
+        assert!(rendered.contains(
+            "id=\"fn-a-b-1\"><sup class=\"footnote-definition-label\">\
+            <a href=\"#fnref-a-b-1\">2</a>"
+        ));
+    }
+
+    #[test]
+    fn references_in_footnote_definitions() {
+        let content = "Text[^1].\n\n[^1]: See[^2].\n\n[^2]: Nested.\n";
+        let (events, _) =
+            FootnoteRewriter::new(Placement::Inline, "").rewrite(new_cmark_parser(content, false));
+        let mut rendered = String::new();
+        html::push_html(&mut rendered, events.into_iter());
+
+        assert!(rendered.contains(
+            "<p>See<sup class=\"footnote-reference\"><a epub:type=\"noteref\" id=\"fnref-2\" \
+            href=\"#fn-2\">2</a></sup>.</p>"
+        ));
+        assert!(!rendered.contains("footnote-reference\"><a href="));
+    }
+}
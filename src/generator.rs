@@ -1,29 +1,63 @@
 use std::{
-    collections::{HashMap, HashSet},
-    ffi::OsString,
+    borrow::Cow,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
     fmt::{self, Debug, Formatter},
-    fs::File,
-    io::{Read, Write},
+    fs::{self, File},
+    io::{self, Read, Write},
     iter,
     path::{Path, PathBuf},
 };
 
-use epub_builder::{EpubBuilder, EpubContent, ZipLibrary};
-use handlebars::{Handlebars, RenderError, RenderErrorReason};
-use html_parser::{Dom, Node};
+use epub_builder::{EpubBuilder, EpubContent, EpubVersion, ReferenceType, TocElement, ZipLibrary};
+use handlebars::{html_escape, Handlebars, RenderError, RenderErrorReason};
 use mdbook::book::{BookItem, Chapter};
+use mdbook::config::TextDirection;
 use mdbook::renderer::RenderContext;
 use mdbook::utils::new_cmark_parser;
+use mime_guess::{mime, Mime};
 use pulldown_cmark::{html, CowStr, Event, Tag};
 use serde_json::json;
 use url::Url;
+use uuid::Uuid;
 
-use crate::config::Config;
-use crate::resources::handler::{ContentRetriever, ResourceHandler};
+use crate::accessibility::Audit;
+use crate::code::{self, HiddenLines, Highlighter};
+use crate::config::{
+    Config, Footnotes, Landmark, PageProgression, PartTitles, RemoteFailure, WritingMode,
+};
+use crate::cover::{self, CoverText};
+use crate::css::{self, Reference};
+use crate::fonts::{self, FONT_DIR};
+use crate::footnotes::{FootnoteRewriter, Placement};
+use crate::headings;
+use crate::images::ImageProcessor;
+use crate::links::{self, ChapterLinkFilter};
+use crate::math::{MathOutput, MathRenderer};
+use crate::metadata;
+use crate::package::Package;
+use crate::pages::PageMarker;
+use crate::resources::handler::{download_all, ContentRetriever, ResourceHandler};
 use crate::resources::{self, Asset, AssetKind};
+use crate::validation::{self, Severity};
+use crate::xhtml;
 use crate::Error;
 use crate::DEFAULT_CSS;
 
+/// The document collecting all footnotes of the book, see [`Footnotes::Book`].
+const ENDNOTES_FILE: &str = "endnotes.html";
+/// The page showing the cover image.
+const COVER_FILE: &str = "cover.xhtml";
+/// The cover image generated if none is configured.
+const GENERATED_COVER_FILE: &str = "cover.svg";
+/// The image embedded for remote images which failed to download, see
+/// [`RemoteFailure::Placeholder`].
+const PLACEHOLDER_FILE: &str = "placeholder.svg";
+const PLACEHOLDER_IMAGE: &str = "<svg xmlns=\"http://www.w3.org/2000/svg\" \
+    width=\"320\" height=\"180\" viewBox=\"0 0 320 180\">\
+    <rect x=\"1\" y=\"1\" width=\"318\" height=\"178\" fill=\"#eeeeee\" stroke=\"#999999\"/>\
+    <text x=\"160\" y=\"96\" font-family=\"sans-serif\" font-size=\"18\" \
+    text-anchor=\"middle\" fill=\"#666666\">Image unavailable</text></svg>\n";
+
 /// The actual EPUB book renderer.
 pub struct Generator<'a> {
     ctx: &'a RenderContext,
@@ -31,23 +65,69 @@ pub struct Generator<'a> {
     config: Config,
     hbs: Handlebars<'a>,
     assets: HashMap<String, Asset>,
+    /// Chapter source paths mapped to their rendered XHTML files.
+    chapter_targets: HashMap<PathBuf, PathBuf>,
+    /// Number of part titles seen so far.
+    parts: usize,
+    /// Extra table of contents level for chapters nested in a part.
+    part_level: i32,
+    /// A part title waiting for its first chapter to link to.
+    pending_part: Option<String>,
+    /// Rendered footnotes of every chapter for the endnotes document.
+    endnotes: Vec<(String, Vec<String>)>,
+    /// Changes to the package document applied to the finished EPUB.
+    package: Package,
+    /// How lines hidden by mdBook in code blocks are rendered.
+    hidden_lines: HiddenLines,
+    /// Highlights code blocks if a theme is configured.
+    highlighter: Option<Highlighter>,
+    /// Converts LaTeX math if enabled.
+    math: Option<MathRenderer>,
+    /// Accessibility checks of the rendered chapters.
+    audit: Audit,
+    /// Finds the page breaks of the print edition if configured.
+    page_marker: Option<PageMarker>,
+    /// Whether a chapter was marked as the start of the main content.
+    bodymatter: bool,
+    /// Processes the embedded images if configured.
+    images: Option<ImageProcessor>,
+    /// The content of processed images by their location on disk.
+    processed_images: HashMap<PathBuf, Vec<u8>>,
+    /// The file names of converted images by their original ones.
+    converted_images: HashMap<PathBuf, PathBuf>,
+    /// The additional stylesheets with the files they reference added as
+    /// assets and their imports inlined.
+    stylesheets: Vec<String>,
+    /// Characters of the book, which subset fonts keep.
+    chars: BTreeSet<char>,
     handler: Box<dyn ContentRetriever>,
 }
 
 impl<'a> Generator<'a> {
     pub fn new(ctx: &'a RenderContext) -> Result<Generator<'a>, Error> {
-        Self::new_with_handler(ctx, ResourceHandler)
+        let config = Config::from_render_context(ctx)?;
+        let handler = ResourceHandler::new(&config.remote);
+        Self::with_config(ctx, config, handler)
     }
 
+    #[cfg(test)]
     fn new_with_handler(
         ctx: &'a RenderContext,
         handler: impl ContentRetriever + 'static,
+    ) -> Result<Generator<'a>, Error> {
+        Self::with_config(ctx, Config::from_render_context(ctx)?, handler)
+    }
+
+    fn with_config(
+        ctx: &'a RenderContext,
+        config: Config,
+        handler: impl ContentRetriever + 'static,
     ) -> Result<Generator<'a>, Error> {
         let handler = Box::new(handler);
-        let config = Config::from_render_context(ctx)?;
 
+        let version = config.epub_version()?;
         let mut builder = EpubBuilder::new(ZipLibrary::new()?)?;
-        builder.epub_version(config.epub_version()?);
+        builder.epub_version(version);
 
         let mut hbs = Handlebars::new();
         hbs.register_template_string("index", config.template()?)
@@ -55,6 +135,35 @@ impl<'a> Generator<'a> {
         const BLANK_TEMPLATE: &str = include_str!("blank.hbs");
         hbs.register_template_string("blank", BLANK_TEMPLATE)
             .map_err(|_| Error::TemplateParse)?;
+        const PART_TEMPLATE: &str = include_str!("part.hbs");
+        hbs.register_template_string("part", PART_TEMPLATE)
+            .map_err(|_| Error::TemplateParse)?;
+
+        let hidden_lines = HiddenLines {
+            show: config.show_hidden_lines,
+            prefixes: match ctx.config.get("output.html.code.hidelines") {
+                Some(prefixes) => prefixes.clone().try_into()?,
+                None => HashMap::new(),
+            },
+        };
+        let highlighter = match config.highlight_theme {
+            Some(ref theme) => Some(Highlighter::new(theme)?),
+            None => None,
+        };
+        let math = config.math.then(|| {
+            MathRenderer::new(match version {
+                EpubVersion::V30 => MathOutput::MathMl,
+                _ => MathOutput::Svg {
+                    command: config.math_svg_command.clone(),
+                },
+            })
+        });
+
+        let page_marker = match config.page_marker {
+            Some(ref pattern) => Some(PageMarker::new(pattern)?),
+            None => None,
+        };
+        let images = ImageProcessor::new(&config.images);
 
         Ok(Generator {
             builder,
@@ -62,6 +171,23 @@ impl<'a> Generator<'a> {
             config,
             hbs,
             assets: HashMap::new(),
+            chapter_targets: links::chapter_targets(&ctx.book),
+            parts: 0,
+            part_level: 0,
+            pending_part: None,
+            endnotes: Vec::new(),
+            package: Package::default(),
+            hidden_lines,
+            highlighter,
+            math,
+            audit: Audit::default(),
+            page_marker,
+            bodymatter: false,
+            images,
+            processed_images: HashMap::new(),
+            converted_images: HashMap::new(),
+            stylesheets: Vec::new(),
+            chars: BTreeSet::new(),
             handler,
         })
     }
@@ -79,9 +205,29 @@ impl<'a> Generator<'a> {
             self.builder.metadata("description", desc)?;
         }
 
-        if !self.ctx.config.book.authors.is_empty() {
-            self.builder
-                .metadata("author", self.ctx.config.book.authors.join(", "))?;
+        let meta = &self.config.metadata;
+        for subject in &meta.subjects {
+            self.builder.metadata("subject", subject.as_str())?;
+        }
+        if let Some(ref rights) = meta.rights {
+            self.builder.metadata("license", rights.as_str())?;
+        }
+        // Creators are written with their roles and sort names by the package.
+        self.package.metadata = metadata::elements(
+            meta,
+            &self.ctx.config.book.authors,
+            self.config.epub_version()?,
+        );
+        self.package.identifier = meta.identifier.as_deref().map(metadata::identifier_urn);
+
+        if let Some(time) = self.config.build_time()? {
+            debug!("Reproducible build at {}", time);
+            self.builder.set_modified_date(time);
+            if self.package.identifier.is_none() {
+                self.builder.set_uuid(self.content_uuid()?);
+            }
+            // zip files can't hold times before 1980, which is also the default
+            self.package.timestamp = Some(time.naive_utc().try_into().unwrap_or_default());
         }
 
         self.builder.metadata("generator", env!("CARGO_PKG_NAME"))?;
@@ -91,22 +237,89 @@ impl<'a> Generator<'a> {
         } else {
             self.builder.metadata("lang", "en")?;
         }
+        let rtl = match (self.config.page_progression, self.config.writing_mode) {
+            (Some(progression), _) => progression == PageProgression::Rtl,
+            (None, WritingMode::VerticalRl) => true,
+            (None, WritingMode::VerticalLr) => false,
+            (None, WritingMode::HorizontalTb) => {
+                self.ctx.config.book.realized_text_direction() == TextDirection::RightToLeft
+            }
+        };
+        if rtl {
+            // `epub_direction()` doesn't reach the package document
+            self.builder.metadata("direction", "rtl")?;
+        }
 
         Ok(())
     }
 
-    pub fn generate<W: Write>(mut self, writer: W) -> Result<(), Error> {
+    /// The language and text direction of a document for the `lang` and
+    /// `dir` attributes of its template, those of `book.language` unless
+    /// `languages` has an entry for the chapter at `chapter_path`.
+    fn language(&self, chapter_path: Option<&Path>) -> (String, &'static str) {
+        let book = &self.ctx.config.book;
+        let (language, direction) =
+            match chapter_path.and_then(|path| self.config.chapter_language(path)) {
+                Some(language) => {
+                    let code = language.split('-').next().unwrap_or_default();
+                    (language.to_string(), TextDirection::from_lang_code(code))
+                }
+                None => (
+                    book.language.clone().unwrap_or_else(|| "en".to_string()),
+                    book.realized_text_direction(),
+                ),
+            };
+        let dir = match direction {
+            TextDirection::LeftToRight => "ltr",
+            TextDirection::RightToLeft => "rtl",
+        };
+        (language, dir)
+    }
+
+    /// An identifier derived from the content and configuration of the book,
+    /// so it only changes when the book does.
+    fn content_uuid(&self) -> Result<Uuid, Error> {
+        let mut content = serde_json::to_vec(&self.ctx.book)?;
+        // options only affecting how the book is built don't change it
+        let mut config = self.ctx.config.clone();
+        if let Some(remote) = config
+            .get_mut("output.epub.remote")
+            .and_then(|remote| remote.as_table_mut())
+        {
+            remote.remove("offline");
+        }
+        content.extend(serde_json::to_vec(&config)?);
+        Ok(Uuid::new_v5(&Uuid::NAMESPACE_OID, &content))
+    }
+
+    pub fn generate<W: Write>(mut self, mut writer: W) -> Result<(), Error> {
         info!("Generating the EPUB book");
 
         self.populate_metadata()?;
         self.find_assets()?;
+        self.add_cover_page()?;
         self.generate_chapters()?;
+        self.add_endnotes()?;
+        self.add_math_images()?;
+        self.add_accessibility()?;
+        self.add_fonts()?;
 
         self.add_cover_image()?;
         self.embed_stylesheets()?;
         self.additional_assets()?;
         self.additional_resources()?;
-        self.builder.generate(writer)?;
+
+        let mut epub = Vec::new();
+        self.builder.generate(&mut epub)?;
+        if self.config.validate {
+            let mut patched = Vec::new();
+            self.package.write(&epub, &mut patched)?;
+            // books failing validation aren't published
+            check(&patched)?;
+            writer.write_all(&patched)?;
+        } else {
+            self.package.write(&epub, writer)?;
+        }
         info!("Generating the EPUB book - DONE !");
         Ok(())
     }
@@ -117,33 +330,442 @@ impl<'a> Generator<'a> {
         let error = String::from("Failed finding/fetch resource taken from content? Look up content for possible error...");
         // resources::find can emit very unclear error based on internal MD content,
         // so let's give a tip to user in error message
-        let assets = resources::find(self.ctx).map_err(|e| {
+        let cache_dir = self
+            .config
+            .remote
+            .cache_dir(&self.ctx.root, &self.ctx.destination);
+        let assets = resources::find(self.ctx, &cache_dir).map_err(|e| {
             error!("{} Caused by: {}", error, e);
             e
         })?;
         self.assets.extend(assets);
+        self.download_assets()?;
+        self.find_stylesheet_assets()?;
+        self.process_images()
+    }
+
+    /// Resolve the references of the additional stylesheets, so the images
+    /// they reference are processed like the ones of the chapters.
+    fn find_stylesheet_assets(&mut self) -> Result<(), Error> {
+        for additional_css in self.config.additional_css.clone() {
+            debug!("generating stylesheet: {:?}", &additional_css);
+            let full_path: PathBuf;
+            if let Ok(full_path_internal) = additional_css.canonicalize() {
+                debug!("Found stylesheet by a path = {:?}", full_path_internal);
+                full_path = full_path_internal;
+            } else {
+                debug!("Failed to find stylesheet, trying to compose path...");
+                let full_path_composed = self.ctx.root.join(&additional_css);
+                debug!("Try stylesheet by a path = {:?}", full_path_composed);
+                let error =
+                    format!("Failed to find stylesheet by full path-name = {full_path_composed:?}");
+                full_path = full_path_composed.canonicalize().expect(&error);
+            }
+            let mut css = String::new();
+            let mut f = File::open(&full_path).map_err(|_| Error::CssOpen(full_path.clone()))?;
+            f.read_to_string(&mut css)
+                .map_err(|_| Error::StylesheetRead)?;
+            let css = self.resolve_css_references(&css, &full_path, &mut vec![full_path.clone()]);
+            self.stylesheets.push(css);
+        }
+        Ok(())
+    }
+
+    /// Process the images before the chapters link to them, so converted
+    /// images are linked by their new file name and images failing to
+    /// decode by their original one.
+    fn process_images(&mut self) -> Result<(), Error> {
+        let Some(ref mut images) = self.images else {
+            return Ok(());
+        };
+        // links to the same file share their asset, which is processed once
+        let mut files: BTreeMap<PathBuf, Asset> = BTreeMap::new();
+        for asset in self.assets.values() {
+            if asset.mimetype.type_() == mime::IMAGE {
+                files
+                    .entry(asset.location_on_disk.clone())
+                    .or_insert_with(|| asset.clone());
+            }
+        }
+        let mut filenames: HashSet<PathBuf> =
+            self.assets.values().map(|a| a.filename.clone()).collect();
+
+        for (path, asset) in files {
+            let source = asset.stored_type();
+            let target = match images.convert_to(&source) {
+                Ok(target) => target.unwrap_or_else(|| source.clone()),
+                Err(e) => {
+                    warn!(
+                        "Failed to convert image {}, embedding it as it is: {}",
+                        path.display(),
+                        e
+                    );
+                    continue;
+                }
+            };
+            let mut content = Vec::new();
+            self.handler
+                .read(&path, &mut content)
+                .map_err(|_| Error::AssetOpen)?;
+            let processed = match images.process(&content, &source, &target) {
+                Ok(processed) => processed,
+                Err(e) => {
+                    warn!(
+                        "Failed to process image {}, embedding it as it is: {}",
+                        path.display(),
+                        e
+                    );
+                    continue;
+                }
+            };
+            if target != source {
+                let filename = unique_filename(
+                    asset.filename.with_extension(target.subtype().as_str()),
+                    &filenames,
+                );
+                filenames.insert(filename.clone());
+                for asset in self.assets.values_mut() {
+                    if asset.location_on_disk == path {
+                        self.converted_images
+                            .insert(asset.filename.clone(), filename.clone());
+                        asset.filename = filename.clone();
+                        asset.mimetype = target.clone();
+                    }
+                }
+            }
+            if processed != content {
+                self.processed_images.insert(path, processed);
+            }
+        }
+        Ok(())
+    }
+
+    /// Download the remote assets before the chapters link to them, so assets
+    /// failing to download can be skipped or replaced as configured.
+    fn download_assets(&mut self) -> Result<(), Error> {
+        // links to the same URL share their cache file, which is downloaded once
+        let mut remote: BTreeMap<&Path, (&Asset, Vec<String>)> = BTreeMap::new();
+        for (link, asset) in &self.assets {
+            if let AssetKind::Remote(_) = asset.source {
+                remote
+                    .entry(asset.location_on_disk.as_path())
+                    .or_insert_with(|| (asset, Vec::new()))
+                    .1
+                    .push(link.clone());
+            }
+        }
+        if remote.is_empty() {
+            return Ok(());
+        }
+
+        let assets: Vec<&Asset> = remote.values().map(|(asset, _)| *asset).collect();
+        let results = download_all(self.handler.as_ref(), &assets, &self.config.remote);
+        let mut total = 0;
+        let mut failed: Vec<(Vec<String>, Error)> = Vec::new();
+        let mut retyped: Vec<(Vec<String>, Mime)> = Vec::new();
+        for ((asset, links), result) in remote.into_values().zip(results) {
+            // assets are embedded while they fit into the size of the book
+            let result = result.and_then(|()| match self.config.remote.max_total_bytes {
+                Some(max) => {
+                    let len = fs::metadata(&asset.location_on_disk)?.len();
+                    if total + len > max {
+                        return Err(Error::DisallowedAsset {
+                            url: links[0].clone(),
+                            reason: format!("the remote assets would be larger than {max} bytes"),
+                        });
+                    }
+                    total += len;
+                    Ok(())
+                }
+                None => Ok(()),
+            });
+            match result {
+                Ok(()) => {
+                    // the URL may have no or a wrong file extension
+                    let mimetype = asset.stored_type();
+                    if mimetype != asset.mimetype {
+                        retyped.push((links, mimetype));
+                    }
+                }
+                Err(e) => failed.push((links, e)),
+            }
+        }
+        for (links, mimetype) in retyped {
+            for link in links {
+                let asset = self
+                    .assets
+                    .get_mut(&link)
+                    .expect("downloaded asset was found");
+                debug!("Embedding {} as {}", link, mimetype);
+                asset.set_media_type(mimetype.clone());
+            }
+        }
+
+        if self.config.remote.keep_disallowed {
+            failed.retain(|(links, e)| {
+                if !matches!(e, Error::DisallowedAsset { .. }) {
+                    return true;
+                }
+                info!("{}, keeping the remote link", e);
+                for link in links {
+                    self.assets.remove(link);
+                }
+                false
+            });
+        }
+        if self.config.remote.on_error == RemoteFailure::Abort {
+            let mut errors: Vec<Error> = failed.into_iter().map(|(_, e)| e).collect();
+            return match errors.len() {
+                0 => Ok(()),
+                1 => Err(errors.remove(0)),
+                _ => Err(Error::RemoteAssets(errors)),
+            };
+        }
+        for (links, e) in failed {
+            for link in links {
+                let asset = self.assets.remove(&link).expect("failed asset was found");
+                if self.config.remote.on_error == RemoteFailure::Placeholder
+                    && asset.mimetype.type_() == mime::IMAGE
+                {
+                    warn!("{}, embedding a placeholder image instead", e);
+                    let placeholder = self.placeholder_asset(asset.source)?;
+                    self.assets.insert(link, placeholder);
+                } else {
+                    warn!("{}, keeping the remote link", e);
+                }
+            }
+        }
         Ok(())
     }
 
+    /// The placeholder image shared by remote images which failed to download.
+    fn placeholder_asset(&self, source: AssetKind) -> Result<Asset, Error> {
+        let filename = Path::new("cache").join(PLACEHOLDER_FILE);
+        let location_on_disk = self.ctx.destination.join(&filename);
+        if !location_on_disk.is_file() {
+            fs::create_dir_all(self.ctx.destination.join("cache"))?;
+            fs::write(&location_on_disk, PLACEHOLDER_IMAGE)?;
+        }
+        Ok(Asset {
+            location_on_disk,
+            filename,
+            mimetype: mime::IMAGE_SVG,
+            source,
+        })
+    }
+
     fn generate_chapters(&mut self) -> Result<(), Error> {
         debug!("Rendering Chapters");
 
         for item in &self.ctx.book.sections {
-            if let BookItem::Chapter(ref ch) = *item {
-                trace!("Adding chapter \"{}\"", ch);
-                self.add_chapter(ch)?;
+            match *item {
+                BookItem::Chapter(ref ch) => {
+                    trace!("Adding chapter \"{}\"", ch);
+                    self.add_chapter(ch)?;
+                }
+                BookItem::PartTitle(ref title) => self.add_part_title(title)?,
+                BookItem::Separator => self.end_part(),
+            }
+        }
+        self.end_part();
+
+        Ok(())
+    }
+
+    /// Add the document with the footnotes collected from all chapters.
+    fn add_endnotes(&mut self) -> Result<(), Error> {
+        if self.endnotes.is_empty() {
+            return Ok(());
+        }
+        debug!("Adding endnotes");
+
+        let mut body =
+            String::from("<h1>Notes</h1>\n<section class=\"footnotes\" epub:type=\"endnotes\">\n");
+        for (chapter, notes) in &self.endnotes {
+            body.push_str(&format!("<h2>{}</h2>\n", html_escape(chapter)));
+            body.push_str(&notes.concat());
+        }
+        body.push_str("</section>\n");
+        let body = well_formed(&body, "Notes");
+        let (lang, dir) = self.language(None);
+        let ctx = json!({
+            "title": "Notes",
+            "body": body,
+            "stylesheet": "stylesheet.css",
+            "lang": lang,
+            "dir": dir,
+        });
+        let rendered = self.hbs.render("index", &ctx)?;
+        if self.config.fonts.subset {
+            self.chars.extend(rendered.chars());
+        }
+        let content = EpubContent::new(ENDNOTES_FILE, rendered.as_bytes())
+            .title("Notes")
+            .reftype(ReferenceType::Notes);
+        self.builder.add_content(content)?;
+        Ok(())
+    }
+
+    /// Add a page showing the cover image at the start of the book, and the
+    /// landmarks `epub-builder` doesn't add.
+    fn add_cover_page(&mut self) -> Result<(), Error> {
+        if self.config.epub_version()? == EpubVersion::V30 {
+            self.package.landmarks.push((
+                "toc",
+                "nav.xhtml#toc".to_string(),
+                "Table Of Contents".to_string(),
+            ));
+        }
+
+        let src = match self.config.cover_image {
+            Some(ref path) => path.display().to_string().replace('\\', "/"),
+            None if self.config.generated_cover.enable => GENERATED_COVER_FILE.to_string(),
+            None => return Ok(()),
+        };
+        debug!("Adding cover page");
+        let title = self.ctx.config.book.title.clone().unwrap_or_default();
+        let body = format!(
+            "<div class=\"cover\"><img src=\"{}\" alt=\"{}\" /></div>",
+            html_escape(&src),
+            html_escape(&title)
+        );
+        let (lang, dir) = self.language(None);
+        let ctx = json!({
+            "title": "Cover",
+            "body": body,
+            "stylesheet": "stylesheet.css",
+            "lang": lang,
+            "dir": dir,
+        });
+        let rendered = self.hbs.render("index", &ctx)?;
+        // without a title it's neither in the table of contents nor a
+        // landmark of `epub-builder`
+        self.builder
+            .add_content(EpubContent::new(COVER_FILE, rendered.as_bytes()))?;
+        self.package
+            .landmarks
+            .insert(0, ("cover", COVER_FILE.to_string(), "Cover".to_string()));
+        Ok(())
+    }
+
+    /// The landmark of a chapter, if it's configured as one or is the first
+    /// numbered chapter, which starts the main content by default.
+    fn landmark(&mut self, ch: &Chapter) -> Option<ReferenceType> {
+        let landmark = self
+            .config
+            .landmarks
+            .iter()
+            .find(|(_, path)| ch.path.as_deref() == Some(path.as_path()))
+            .map(|(landmark, _)| *landmark);
+        let landmark = match landmark {
+            Some(landmark) => landmark,
+            None if !self.bodymatter
+                && ch.number.is_some()
+                && !self.config.landmarks.contains_key(&Landmark::Bodymatter) =>
+            {
+                Landmark::Bodymatter
             }
+            None => return None,
+        };
+        if landmark == Landmark::Bodymatter {
+            self.bodymatter = true;
+        }
+        Some(match landmark {
+            Landmark::Bodymatter => ReferenceType::Text,
+            Landmark::Acknowledgements => ReferenceType::Acknowledgements,
+            Landmark::Bibliography => ReferenceType::Bibliography,
+            Landmark::Colophon => ReferenceType::Colophon,
+            Landmark::CopyrightPage => ReferenceType::Copyright,
+            Landmark::Dedication => ReferenceType::Dedication,
+            Landmark::Epigraph => ReferenceType::Epigraph,
+            Landmark::Foreword => ReferenceType::Foreword,
+            Landmark::Glossary => ReferenceType::Glossary,
+            Landmark::Index => ReferenceType::Index,
+            Landmark::Loi => ReferenceType::Loi,
+            Landmark::Lot => ReferenceType::Lot,
+            Landmark::Preface => ReferenceType::Preface,
+            Landmark::Titlepage => ReferenceType::TitlePage,
+        })
+    }
+
+    /// Add the SVG images of the formulas rendered for EPUB 2.
+    fn add_math_images(&mut self) -> Result<(), Error> {
+        let Some(ref mut math) = self.math else {
+            return Ok(());
+        };
+        for (path, svg) in math.take_images() {
+            self.builder
+                .add_resource(path, svg.as_slice(), "image/svg+xml")?;
+        }
+        Ok(())
+    }
+
+    /// Add the accessibility metadata derived from the chapters, and write
+    /// the accessibility report if enabled.
+    fn add_accessibility(&mut self) -> Result<(), Error> {
+        let config = &self.config.accessibility;
+        let version = self.config.epub_version()?;
+        self.package
+            .metadata
+            .extend(self.audit.metadata(config, version));
+
+        if config.report {
+            let title = self.ctx.config.book.title.as_deref().unwrap_or_default();
+            let path = self.ctx.destination.join("accessibility-report.txt");
+            debug!("Writing the accessibility report to {}", path.display());
+            fs::write(path, self.audit.report(title, config))?;
         }
+        Ok(())
+    }
 
+    /// Start a new part, the following chapters are nested under its title in
+    /// the table of contents.
+    fn add_part_title(&mut self, title: &str) -> Result<(), Error> {
+        self.end_part();
+        if self.config.fonts.subset {
+            self.chars.extend(title.chars());
+        }
+        match self.config.part_titles {
+            PartTitles::Skip => {
+                trace!("Skip part title: {}.", title);
+                return Ok(());
+            }
+            PartTitles::Page => {
+                self.parts += 1;
+                let path = format!("part_{}.html", self.parts);
+                trace!("add a part title {:?} by a path = {:?}", title, path);
+                let (lang, dir) = self.language(None);
+                let rendered = self.hbs.render(
+                    "part",
+                    &json!({"title": title, "stylesheet": "stylesheet.css", "lang": lang, "dir": dir}),
+                )?;
+                let content = EpubContent::new(path, rendered.as_bytes())
+                    .title(title)
+                    .level(0);
+                self.builder.add_content(content)?;
+            }
+            PartTitles::Toc => self.pending_part = Some(title.to_string()),
+        }
+        self.part_level = 1;
         Ok(())
     }
 
+    /// Chapters after a separator or the end of the book no longer belong to
+    /// the last part.
+    fn end_part(&mut self) {
+        if let Some(title) = self.pending_part.take() {
+            warn!("Part \"{}\" has no chapters and is left out.", title);
+        }
+        self.part_level = 0;
+    }
+
     fn add_chapter(&mut self, ch: &Chapter) -> Result<(), Error> {
         let (path, rendered) = match &ch.path {
             Some(ch_path) => {
                 trace!("add a chapter {:?} by a path = {:?}", &ch.name, ch_path);
                 let path = ch_path.with_extension("html").display().to_string();
                 let rendered = self.render_chapter(ch)?;
+                self.audit.check(&path, &rendered);
                 (path, rendered)
             }
             None => {
@@ -154,7 +776,11 @@ impl<'a> Generator<'a> {
                     // Render a blank page for draft chapter that has sub chapters.
                     let path = format!("{}.html", sanitize_filename::sanitize(&ch.name));
                     trace!("add a blank chapter {:?} by a path = {:?}", &ch.name, path);
-                    let rendered = self.hbs.render("blank", &json!({"title": ch.name}))?;
+                    let (lang, dir) = self.language(None);
+                    let rendered = self.hbs.render(
+                        "blank",
+                        &json!({"title": ch.name, "lang": lang, "dir": dir}),
+                    )?;
                     (path, rendered)
                 }
             }
@@ -163,10 +789,33 @@ impl<'a> Generator<'a> {
             (false, Some(section_number)) => format! {"{} {}", section_number, ch.name},
             _ => ch.name.clone(),
         };
+        if self.config.fonts.subset {
+            self.chars.extend(title.chars());
+            self.chars.extend(rendered.chars());
+        }
+        let headings = if self.config.toc_depth > 1 {
+            headings::find_headings(&ch.content, self.config.curly_quotes)
+        } else {
+            Vec::new()
+        };
+        let toc_children = headings::toc_elements(&headings, &path, self.config.toc_depth);
         let mut content = EpubContent::new(path, rendered.as_bytes()).title(title);
+        if let Some(landmark) = self.landmark(ch) {
+            content = content.reftype(landmark);
+        }
 
-        let level = ch.number.as_ref().map(|n| n.len() as i32 - 1).unwrap_or(0);
+        let level = ch.number.as_ref().map(|n| n.len() as i32 - 1).unwrap_or(0) + self.part_level;
         content = content.level(level);
+        for child in toc_children {
+            content = content.child(child);
+        }
+        if let Some(part) = self.pending_part.take() {
+            // The part's entry links to its first chapter and takes the
+            // chapter as its first child.
+            let part_toc = TocElement::new(content.toc.url.as_str(), part).level(level - 1);
+            let chapter_toc = std::mem::replace(&mut content.toc, part_toc);
+            content.toc = content.toc.child(chapter_toc);
+        }
 
         self.builder.add_content(content)?;
 
@@ -182,14 +831,15 @@ impl<'a> Generator<'a> {
     }
 
     /// Render the chapter into its fully formed HTML representation.
-    fn render_chapter(&self, ch: &Chapter) -> Result<String, RenderError> {
-        let chapter_dir = if let Some(chapter_file_path) = &ch.path {
-            chapter_file_path.parent().ok_or_else(|| {
+    fn render_chapter(&mut self, ch: &Chapter) -> Result<String, RenderError> {
+        let (chapter_path, chapter_dir) = if let Some(chapter_file_path) = &ch.path {
+            let chapter_dir = chapter_file_path.parent().ok_or_else(|| {
                 RenderError::from(RenderErrorReason::Other(format!(
                     "No CSS found by a path = {:?}",
                     ch.path
                 )))
-            })?
+            })?;
+            (chapter_file_path, chapter_dir)
         } else {
             return Err(RenderError::from(RenderErrorReason::Other(format!(
                 "Draft chapter: {} could not be rendered.",
@@ -198,19 +848,97 @@ impl<'a> Generator<'a> {
         };
         let mut body = String::new();
         let p = new_cmark_parser(&ch.content, self.config.curly_quotes);
-        let asset_link_filter = AssetLinkFilter::new(&self.assets);
-        let events = p.map(|event| asset_link_filter.apply(event));
-
-        html::push_html(&mut body, events);
+        let asset_link_filter = AssetLinkFilter::new(&self.assets, chapter_dir);
+        let chapter_link_filter = ChapterLinkFilter::new(&self.chapter_targets, chapter_path);
+        let events = p
+            .map(|event| asset_link_filter.apply(event))
+            .map(|event| chapter_link_filter.apply(event));
+        let (events, headings) = headings::anchor_headings(events);
+        let events = match self.page_marker {
+            Some(ref marker) => {
+                let version = self.config.epub_version().unwrap_or(EpubVersion::V20);
+                let taken = headings.into_iter().map(|heading| heading.id).collect();
+                let (events, pages) = marker.mark(events.into_iter(), version, taken);
+                let href = chapter_path.with_extension("html");
+                let href = href.display().to_string().replace('\\', "/");
+                self.package.pages.extend(
+                    pages
+                        .into_iter()
+                        .map(|page| (page.label, format!("{}#{}", href, page.id))),
+                );
+                events
+            }
+            None => events,
+        };
+        let events = code::rewrite_code_blocks(
+            events.into_iter(),
+            &self.hidden_lines,
+            self.highlighter.as_ref(),
+        );
 
-        let stylesheet_path = chapter_dir
-            .components()
-            .map(|_| "..")
-            .chain(iter::once("stylesheet.css"))
-            .collect::<Vec<_>>()
-            .join("/");
+        let to_root = |file: &str| {
+            chapter_dir
+                .components()
+                .map(|_| "..")
+                .chain(iter::once(file))
+                .collect::<Vec<_>>()
+                .join("/")
+        };
+        let events = match self.math {
+            Some(ref mut math) => {
+                let (events, has_mathml) = math.render(events.into_iter(), to_root);
+                if has_mathml {
+                    let href = chapter_path.with_extension("html");
+                    self.package
+                        .mathml
+                        .push(href.display().to_string().replace('\\', "/"));
+                }
+                events
+            }
+            None => events,
+        };
+        let stylesheet_path = to_root("stylesheet.css");
+
+        if matches!(self.config.epub_version(), Ok(EpubVersion::V30)) {
+            let endnotes_href = to_root(ENDNOTES_FILE);
+            let back_href = chapter_path.with_extension("html").display().to_string();
+            let id_prefix = match self.config.footnotes {
+                Footnotes::Book => iter::once('-')
+                    .chain(
+                        back_href
+                            .chars()
+                            .map(|c| if c.is_alphanumeric() { c } else { '-' }),
+                    )
+                    .collect(),
+                _ => String::new(),
+            };
+            let placement = match self.config.footnotes {
+                Footnotes::Inline => Placement::Inline,
+                Footnotes::Chapter => Placement::Chapter,
+                Footnotes::Book => Placement::Book {
+                    href: &endnotes_href,
+                    back_href: &back_href,
+                },
+            };
+            let (events, notes) =
+                FootnoteRewriter::new(placement, &id_prefix).rewrite(events.into_iter());
+            html::push_html(&mut body, events.into_iter());
+            if !notes.is_empty() {
+                self.endnotes.push((ch.name.clone(), notes));
+            }
+        } else {
+            html::push_html(&mut body, events.into_iter());
+        }
 
-        let ctx = json!({ "title": ch.name, "body": body, "stylesheet": stylesheet_path });
+        let body = well_formed(&body, &ch.name);
+        let (lang, dir) = self.language(Some(chapter_path));
+        let ctx = json!({
+            "title": ch.name,
+            "body": body,
+            "stylesheet": stylesheet_path,
+            "lang": lang,
+            "dir": dir,
+        });
 
         self.hbs.render("index", &ctx)
     }
@@ -230,18 +958,22 @@ impl<'a> Generator<'a> {
 
         // An image may be used multiple times with different links that associate with the same asset.
         let all_assets: HashSet<&Asset> = HashSet::from_iter(self.assets.values());
+        // Embed them in a stable order.
+        let mut all_assets = Vec::from_iter(all_assets);
+        all_assets.sort_by(|a, b| a.filename.cmp(&b.filename));
 
-        // TODO: have a list of Asset URLs and try to download all of them (in parallel?)
-        // to a temporary location.
         for asset in all_assets {
-            if let AssetKind::Remote(_url) = &asset.source {
-                self.handler.download(asset)?;
-            }
             debug!("Embedding asset : {}", asset.filename.display());
-            let mut content = Vec::new();
-            self.handler
-                .read(&asset.location_on_disk, &mut content)
-                .map_err(|_| Error::AssetOpen)?;
+            let content = match self.processed_images.remove(&asset.location_on_disk) {
+                Some(processed) => processed,
+                None => {
+                    let mut content = Vec::new();
+                    self.handler
+                        .read(&asset.location_on_disk, &mut content)
+                        .map_err(|_| Error::AssetOpen)?;
+                    content
+                }
+            };
             let mt = asset.mimetype.to_string();
             self.builder.add_resource(&asset.filename, &*content, mt)?;
         }
@@ -294,6 +1026,67 @@ impl<'a> Generator<'a> {
         Ok(())
     }
 
+    /// Embed the configured fonts, subset to the characters of the book if
+    /// enabled.
+    fn add_fonts(&mut self) -> Result<(), Error> {
+        let fonts = &self.config.fonts;
+        if fonts.faces.is_empty() {
+            return Ok(());
+        }
+        debug!("Embedding fonts");
+        if let Some(ref title) = self.ctx.config.book.title {
+            self.chars.extend(title.chars());
+        }
+        // generated text like "Table Of Contents" and list numbers
+        self.chars.extend(' '..='~');
+
+        for face in &fonts.faces {
+            let full_path = self.ctx.root.join(&face.file);
+            let mut data = fs::read(&full_path).map_err(|_| {
+                Error::AssetFileNotFound(format!("Font was not found: {}", full_path.display()))
+            })?;
+            let Some(name) = face.file.file_name() else {
+                return Err(Error::AssetFile(full_path));
+            };
+            let mt = mime_guess::from_path(&full_path).first_or_octet_stream();
+            if fonts.subset {
+                let extension = full_path.extension().unwrap_or_default();
+                if extension.eq_ignore_ascii_case("ttf") || extension.eq_ignore_ascii_case("otf") {
+                    match fonts::subset(&data, &self.chars) {
+                        Ok(Some(subset)) => {
+                            debug!(
+                                "Subset font {} from {} to {} bytes",
+                                full_path.display(),
+                                data.len(),
+                                subset.len()
+                            );
+                            data = subset;
+                        }
+                        Ok(None) => warn!(
+                            "Embedding all of font {} as the book has text it shapes",
+                            full_path.display()
+                        ),
+                        Err(e) => warn!("Failed to subset font {}: {}", full_path.display(), e),
+                    }
+                } else {
+                    warn!(
+                        "Only TrueType and OpenType fonts can be subset, embedding all of {}",
+                        full_path.display()
+                    );
+                }
+            }
+
+            let path = Path::new(FONT_DIR).join(name);
+            if let Some(obfuscation) = fonts.obfuscation {
+                let zip_path = format!("OEBPS/{}", path.display()).replace('\\', "/");
+                self.package.obfuscated.push((zip_path, obfuscation));
+            }
+            self.builder
+                .add_resource(&path, data.as_slice(), mt.to_string())?;
+        }
+        Ok(())
+    }
+
     fn add_cover_image(&mut self) -> Result<(), Error> {
         debug!("Adding cover image...");
 
@@ -321,40 +1114,194 @@ impl<'a> Generator<'a> {
             debug!("Adding cover image: {:?} / {:?} ", path, mt.to_string());
             self.builder
                 .add_cover_image(path, content, mt.to_string())?;
+        } else if self.config.generated_cover.enable {
+            self.add_generated_cover()?;
         }
 
         Ok(())
     }
 
+    /// Render a cover from the title, subtitle and authors of the book.
+    fn add_generated_cover(&mut self) -> Result<(), Error> {
+        debug!("Generating cover image");
+        let config = &self.config.generated_cover;
+        let logo = match config.logo {
+            Some(ref path) => {
+                let full_path = self.ctx.root.join(&self.ctx.config.book.src).join(path);
+                let data = fs::read(&full_path).map_err(|_| {
+                    Error::AssetFileNotFound(format!(
+                        "Failed to find the cover logo {}",
+                        full_path.display()
+                    ))
+                })?;
+                let mt = mime_guess::from_path(&full_path).first_or_octet_stream();
+                Some((data, mt.to_string()))
+            }
+            None => None,
+        };
+
+        let book = &self.ctx.config.book;
+        let authors = if self.config.metadata.authors.is_empty() {
+            book.authors.iter().map(String::as_str).collect()
+        } else {
+            self.config
+                .metadata
+                .authors
+                .iter()
+                .map(|author| author.name.as_str())
+                .collect()
+        };
+        let (language, _) = self.language(None);
+        let text = CoverText {
+            title: book.title.as_deref().unwrap_or_default(),
+            subtitle: config.subtitle.as_deref(),
+            authors,
+            language: &language,
+        };
+        let svg = cover::svg(
+            &text,
+            config,
+            logo.as_ref()
+                .map(|(data, mt)| (data.as_slice(), mt.as_str())),
+        );
+        self.builder
+            .add_cover_image(GENERATED_COVER_FILE, svg.as_bytes(), "image/svg+xml")?;
+        Ok(())
+    }
+
     /// Concatenate all provided stylesheets into one long stylesheet.
-    fn generate_stylesheet(&self) -> Result<Vec<u8>, Error> {
+    fn generate_stylesheet(&mut self) -> Result<Vec<u8>, Error> {
         let mut stylesheet = Vec::new();
 
         if self.config.use_default_css {
             stylesheet.extend(DEFAULT_CSS.as_bytes());
         }
 
-        for additional_css in &self.config.additional_css {
-            debug!("generating stylesheet: {:?}", &additional_css);
-            let full_path: PathBuf;
-            if let Ok(full_path_internal) = additional_css.canonicalize() {
-                debug!("Found stylesheet by a path = {:?}", full_path_internal);
-                full_path = full_path_internal;
-            } else {
-                debug!("Failed to find stylesheet, trying to compose path...");
-                let full_path_composed = self.ctx.root.join(additional_css);
-                debug!("Try stylesheet by a path = {:?}", full_path_composed);
-                let error =
-                    format!("Failed to find stylesheet by full path-name = {full_path_composed:?}");
-                full_path = full_path_composed.canonicalize().expect(&error);
+        for face in &self.config.fonts.faces {
+            if let Some(name) = face.file.file_name() {
+                let href = format!("{FONT_DIR}/{}", name.to_string_lossy());
+                stylesheet.extend(fonts::font_face_rule(face, &href).as_bytes());
             }
-            let mut f = File::open(&full_path).map_err(|_| Error::CssOpen(full_path.clone()))?;
-            f.read_to_end(&mut stylesheet)
-                .map_err(|_| Error::StylesheetRead)?;
+        }
+
+        if let Some(css) = writing_mode_css(self.config.writing_mode) {
+            stylesheet.extend(css.as_bytes());
+        }
+
+        if let Some(ref highlighter) = self.highlighter {
+            stylesheet.extend(highlighter.stylesheet()?.as_bytes());
+        }
+
+        for css in &self.stylesheets {
+            // link the images converted after the stylesheet was resolved
+            let css = css::rewrite(css, |reference| match reference {
+                Reference::Url(url) => {
+                    let split = url.find(['?', '#']).unwrap_or(url.len());
+                    let filename = self.converted_images.get(Path::new(&url[..split]))?;
+                    Some(format!(
+                        "{}{}",
+                        filename.display().to_string().replace('\\', "/"),
+                        &url[split..]
+                    ))
+                }
+                Reference::Import(..) => None,
+            });
+            stylesheet.extend(css.as_bytes());
         }
         debug!("found style(s) = [{}]", stylesheet.len());
         Ok(stylesheet)
     }
+
+    /// Add the files referenced by the stylesheet at `path` as assets,
+    /// returning the stylesheet with `url()` references rewritten to their
+    /// paths in the book and the stylesheets it imports inlined. `imported`
+    /// holds the stylesheets being resolved, to break import cycles.
+    fn resolve_css_references(
+        &mut self,
+        css: &str,
+        path: &Path,
+        imported: &mut Vec<PathBuf>,
+    ) -> String {
+        css::rewrite(css, |reference| match reference {
+            Reference::Url(url) => self.css_asset(url, path),
+            Reference::Import(url, media) => {
+                let location = self.css_reference_location(url, path)?;
+                if imported.contains(&location) {
+                    warn!(
+                        "Stylesheet {} imports itself through {}",
+                        location.display(),
+                        path.display()
+                    );
+                    return Some(String::new());
+                }
+                let css = match fs::read_to_string(&location) {
+                    Ok(css) => css,
+                    Err(e) => {
+                        warn!("Failed to read stylesheet {}: {}", location.display(), e);
+                        return None;
+                    }
+                };
+                debug!("Inlining stylesheet {}", location.display());
+                imported.push(location.clone());
+                let css = self.resolve_css_references(&css, &location, imported);
+                imported.pop();
+                if media.is_empty() {
+                    Some(css)
+                } else {
+                    Some(format!("@media {media} {{\n{css}\n}}"))
+                }
+            }
+        })
+    }
+
+    /// Add the file referenced by a `url()` of a stylesheet as an asset,
+    /// returning its path in the book.
+    fn css_asset(&mut self, url: &str, path: &Path) -> Option<String> {
+        let location = self.css_reference_location(url, path)?;
+        let suffix = url.find(['?', '#']).map_or("", |i| &url[i..]);
+
+        let root = self.ctx.root.canonicalize().unwrap_or_default();
+        let filename = match location.strip_prefix(root.join(&self.ctx.config.book.src)) {
+            Ok(filename) => filename.to_path_buf(),
+            Err(_) => match location.strip_prefix(&root) {
+                Ok(filename) => filename.to_path_buf(),
+                Err(_) => Path::new("css").join(location.file_name()?),
+            },
+        };
+        // files listed as additional resources are added as they are
+        if !self.config.additional_resources.contains(&filename) {
+            let asset = Asset::new(&filename, &location, AssetKind::Local(PathBuf::from(url)));
+            debug!("Found asset {:?} in stylesheet {:?}", filename, path);
+            self.assets.insert(location.display().to_string(), asset);
+        }
+        Some(format!(
+            "{}{}",
+            filename.display().to_string().replace('\\', "/"),
+            suffix
+        ))
+    }
+
+    /// The file a reference in the stylesheet at `path` points to, if it's a
+    /// local file. Warns if it doesn't exist.
+    fn css_reference_location(&self, url: &str, path: &Path) -> Option<PathBuf> {
+        if url.is_empty() || url.starts_with('#') || Url::parse(url).is_ok() {
+            // fragments of SVG documents, data URLs and remote files
+            return None;
+        }
+        let file = url.split(['?', '#']).next().unwrap_or_default();
+        let dir = path.parent().unwrap_or(Path::new(""));
+        match dir.join(file).canonicalize() {
+            Ok(location) if location.is_file() => Some(location),
+            _ => {
+                warn!(
+                    "Unresolved reference \"{}\" in stylesheet {}",
+                    url,
+                    path.display()
+                );
+                None
+            }
+        }
+    }
 }
 
 impl Debug for Generator<'_> {
@@ -368,13 +1315,85 @@ impl Debug for Generator<'_> {
     }
 }
 
+/// The stylesheet laying out every document in a vertical writing mode.
+fn writing_mode_css(mode: WritingMode) -> Option<String> {
+    let value = match mode {
+        WritingMode::HorizontalTb => return None,
+        WritingMode::VerticalRl => "vertical-rl",
+        WritingMode::VerticalLr => "vertical-lr",
+    };
+    Some(format!(
+        "\nhtml {{\n    -epub-writing-mode: {value};\n    -webkit-writing-mode: {value};\n    \
+        writing-mode: {value};\n}}\n"
+    ))
+}
+
+/// Turn the rendered body of a document into well-formed XHTML, logging every
+/// fix of raw HTML in it.
+fn well_formed(body: &str, title: &str) -> String {
+    let (body, warnings) = xhtml::normalize(body);
+    for warning in warnings {
+        warn!("Fixed invalid XHTML in \"{}\": {}", title, warning);
+    }
+    body
+}
+
+/// The link to a local asset converted to another format, which has its new
+/// file name.
+fn converted_link(link: &str, asset: &Asset) -> Option<String> {
+    let file_name = asset.filename.file_name()?;
+    if asset.location_on_disk.file_name() == Some(file_name) {
+        return None;
+    }
+    let link = Path::new(link).with_file_name(file_name);
+    Some(link.display().to_string().replace('\\', "/"))
+}
+
+/// The file name, or if it's taken the first one with a number appended to
+/// its stem which isn't.
+fn unique_filename(filename: PathBuf, taken: &HashSet<PathBuf>) -> PathBuf {
+    if !taken.contains(&filename) {
+        return filename;
+    }
+    let stem = filename.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = filename.extension().unwrap_or_default().to_string_lossy();
+    (1..)
+        .map(|n| filename.with_file_name(format!("{stem}-{n}.{extension}")))
+        .find(|candidate| !taken.contains(candidate))
+        .unwrap()
+}
+
+/// Validate the generated EPUB, logging every issue found.
+fn check(epub: &[u8]) -> Result<(), Error> {
+    debug!("Validating the EPUB book");
+    let report = validation::validate_reader(io::Cursor::new(epub))?;
+    for issue in &report.issues {
+        match issue.severity {
+            Severity::Error => error!("{}", issue),
+            Severity::Warning => warn!("{}", issue),
+        }
+    }
+    if report.is_valid() {
+        Ok(())
+    } else {
+        Err(Error::EpubCheck(format!(
+            "{} errors found in the generated EPUB",
+            report.errors().count()
+        )))
+    }
+}
+
 struct AssetLinkFilter<'a> {
     assets: &'a HashMap<String, Asset>,
+    chapter_dir: &'a Path,
 }
 
 impl<'a> AssetLinkFilter<'a> {
-    fn new(assets: &'a HashMap<String, Asset>) -> Self {
-        Self { assets }
+    fn new(assets: &'a HashMap<String, Asset>, chapter_dir: &'a Path) -> Self {
+        Self {
+            assets,
+            chapter_dir,
+        }
     }
     fn apply(&self, event: Event<'a>) -> Event<'a> {
         match event {
@@ -384,14 +1403,14 @@ impl<'a> AssetLinkFilter<'a> {
                 ref title,
                 ref id,
             }) => {
-                let asset = self
-                    .assets
-                    .get(&dest_url.to_string())
-                    .expect("found asset shouldn't be None");
+                // remote images which failed to download may be skipped
+                let Some(asset) = self.assets.get(&dest_url.to_string()) else {
+                    return event;
+                };
                 match asset.source {
                     AssetKind::Remote(_) => {
-                        // replace original image link with `/cache/<hash.ext>` in the chapter.
-                        let new = self.path_prefix(asset.filename.as_path());
+                        // replace original image link with `cache/<hash.ext>` relative to the chapter.
+                        let new = self.relative_link(asset.filename.as_path());
                         Event::Start(Tag::Image {
                             link_type,
                             dest_url: CowStr::from(new),
@@ -399,57 +1418,49 @@ impl<'a> AssetLinkFilter<'a> {
                             id: id.to_owned(),
                         })
                     }
-                    _ => event,
+                    AssetKind::Local(_) => match converted_link(dest_url, asset) {
+                        Some(new) => Event::Start(Tag::Image {
+                            link_type,
+                            dest_url: CowStr::from(new),
+                            title: title.to_owned(),
+                            id: id.to_owned(),
+                        }),
+                        None => event,
+                    },
                 }
             }
-            Event::Html(ref html) => {
-                let mut found = Vec::new();
-                if let Ok(dom) = Dom::parse(&html.clone().into_string()) {
-                    for item in dom.children {
-                        match item {
-                            Node::Element(ref element) if element.name == "img" => {
-                                if let Some(dest) = &element.attributes["src"] {
-                                    if Url::parse(dest).is_ok() {
-                                        debug!("Found a valid remote img src:\"{}\".", dest);
-                                        found.push(dest.to_owned());
-                                    }
-                                }
-                            }
-                            _ => {}
+            Event::Html(ref html) | Event::InlineHtml(ref html) => {
+                // remote assets are cached and converted assets renamed
+                let content = resources::rewrite_assets_in_html(html, |link| {
+                    let asset = self.assets.get(link)?;
+                    match asset.source {
+                        AssetKind::Remote(_) => {
+                            debug!("Found a valid remote asset link:\"{}\".", link);
+                            Some(self.relative_link(asset.filename.as_path()))
                         }
+                        AssetKind::Local(_) => converted_link(link, asset),
                     }
+                });
+                if content.as_str() == html.as_ref() {
+                    return event;
                 }
-                if found.is_empty() {
-                    event
-                } else {
-                    found.dedup();
-                    let mut content = html.clone().into_string();
-                    for link in found {
-                        if let Some(asset) = self.assets.get(link.as_str()) {
-                            let new = self.path_prefix(asset.filename.as_path());
-                            content = content.replace(link.as_str(), &CowStr::from(new));
-                        } else {
-                            unreachable!("{link} should be replaced, but it doesn't.");
-                        }
-                    }
-                    Event::Html(CowStr::from(content))
+                match event {
+                    Event::InlineHtml(_) => Event::InlineHtml(CowStr::from(content)),
+                    _ => Event::Html(CowStr::from(content)),
                 }
             }
             _ => event,
         }
     }
-    // prepend a forward slash to the file path of the cached remote image in epub
-    fn path_prefix(&self, path: &Path) -> String {
-        // compatible to Windows, translate to forawrd slash in file path.
-        let mut fsp = OsString::new();
-        for component in path.components() {
-            fsp.push("/");
-            fsp.push(component);
-        }
-        match fsp.into_string() {
-            Ok(s) => s,
-            Err(orig) => orig.to_string_lossy().to_string(),
-        }
+    // link to the file path of the cached remote image in epub, relative to the chapter
+    fn relative_link(&self, path: &Path) -> String {
+        // compatible to Windows, translate to forward slash in file path.
+        self.chapter_dir
+            .components()
+            .map(|_| Cow::Borrowed(".."))
+            .chain(path.components().map(|c| c.as_os_str().to_string_lossy()))
+            .collect::<Vec<_>>()
+            .join("/")
     }
 }
 
@@ -459,7 +1470,9 @@ mod tests {
     use std::path::Path;
 
     use super::*;
-    use crate::resources::{handler::MockContentRetriever, AssetKind};
+    use crate::config::FontObfuscation;
+    use crate::resources::handler::{CacheEntry, MockContentRetriever, Response};
+    use crate::resources::AssetKind;
 
     #[test]
     fn load_assets() {
@@ -473,11 +1486,16 @@ mod tests {
             <img alt=\"Rust Logo in html\" src=\"{svg}\" />\n"
         );
         let destination = tempfile::TempDir::with_prefix("mdbook-epub").unwrap();
-        let json = ctx_with_template(&content, "src", destination.path()).to_string();
-        let ctx = RenderContext::from_json(json.as_bytes()).unwrap();
+        let mut json = ctx_with_template(&content, "src", destination.path());
+        json["config"]["output"]["epub"]["remote"] =
+            json!({ "cache-dir": destination.path().join("cache") });
+        let ctx = RenderContext::from_json(json.to_string().as_bytes()).unwrap();
 
         let mut mock_client = MockContentRetriever::new();
-        mock_client.expect_download().times(1).returning(|_| Ok(()));
+        mock_client
+            .expect_download()
+            .times(1)
+            .returning(|_, _| Ok(()));
         // checks local path of assets
         let book_source = PathBuf::from(&ctx.root)
             .join(&ctx.config.book.src)
@@ -540,7 +1558,7 @@ mod tests {
             links[2], links[0], links[1]
         );
 
-        let filter = AssetLinkFilter::new(&assets);
+        let filter = AssetLinkFilter::new(&assets, Path::new(""));
         let parser = new_cmark_parser(&markdown_str, false);
         let events = parser.map(|ev| filter.apply(ev));
         let mut html_buf = String::new();
@@ -553,7 +1571,7 @@ mod tests {
                 <ul>\n\
                 <li><a href=\"{}\">link</a></li>\n\
                 <li><img src=\"{}\" alt=\"Local Image\" /></li>\n\
-                <li><img alt=\"Remote Image\" src=\"/cache/{}\" >\n\
+                <li><img alt=\"Remote Image\" src=\"cache/{}\" >\n\
                 </li>\n\
                 </ul>\n",
                 links[2], links[0], hashed_filename
@@ -561,6 +1579,86 @@ mod tests {
         );
     }
 
+    #[test]
+    fn render_media_assets() {
+        let root = tempfile::TempDir::with_prefix("mdbook-epub").unwrap();
+        let mut assets = HashMap::new();
+        let mut remote = |link: &str, mimetype: &str| {
+            let url = Url::parse(link).unwrap();
+            let path = Path::new("cache").join(resources::hash_link(&url));
+            assets.insert(
+                link.to_string(),
+                Asset {
+                    location_on_disk: root.path().join("book").join(&path),
+                    filename: path.clone(),
+                    mimetype: mimetype.parse::<mime::Mime>().unwrap(),
+                    source: AssetKind::Remote(url),
+                },
+            );
+            path.display().to_string()
+        };
+        let small = remote("http://server/small.jpg", "image/jpeg");
+        let large = remote("http://server/small.jpg.large", "image/jpeg");
+        let audio = remote("http://server/sample.mp3", "audio/mpeg");
+        let poster = remote("http://server/poster.png", "image/png");
+        let markdown_str = "<picture>\n\
+            <source srcset=\"http://server/small.jpg 1x, http://server/small.jpg.large 2x\">\n\
+            </picture>\n\n\
+            Listen <audio src=\"http://server/sample.mp3\" controls=\"\"></audio>\n\n\
+            <div style=\"background: url(http://server/poster.png)\"></div>\n";
+
+        let filter = AssetLinkFilter::new(&assets, Path::new(""));
+        let parser = new_cmark_parser(markdown_str, false);
+        let events = parser.map(|ev| filter.apply(ev));
+        let mut html_buf = String::new();
+        html::push_html(&mut html_buf, events);
+
+        assert_eq!(
+            html_buf,
+            format!(
+                "<picture>\n\
+                <source srcset=\"{small} 1x, {large} 2x\">\n\
+                </picture>\n\
+                <p>Listen <audio src=\"{audio}\" controls=\"\"></audio></p>\n\
+                <div style=\"background: url(&quot;{poster}&quot;)\"></div>\n"
+            )
+        );
+    }
+
+    #[test]
+    fn render_converted_assets_in_html() {
+        let root = tempfile::TempDir::with_prefix("mdbook-epub").unwrap();
+        let mut assets = HashMap::new();
+        let mut local = |link: &str, filename: &str, mimetype: &str| {
+            assets.insert(
+                link.to_string(),
+                Asset {
+                    location_on_disk: root.path().join(link),
+                    filename: PathBuf::from(filename),
+                    mimetype: mimetype.parse::<mime::Mime>().unwrap(),
+                    source: AssetKind::Local(PathBuf::from(link)),
+                },
+            );
+        };
+        local("a.webp", "a.png", "image/png");
+        local("xa.webp", "xa.webp", "image/webp");
+        let markdown_str = "<p title=\"a.webp\"><img src=\"a.webp\" alt=\"a.webp\"> \
+            <img src='xa.webp'> <a href=\"a.webp\">a.webp</a> <code>a.webp</code></p>\n";
+
+        let filter = AssetLinkFilter::new(&assets, Path::new(""));
+        let parser = new_cmark_parser(markdown_str, false);
+        let events = parser.map(|ev| filter.apply(ev));
+        let mut html_buf = String::new();
+        html::push_html(&mut html_buf, events);
+
+        // only the links to the converted image change
+        assert_eq!(
+            html_buf,
+            "<p title=\"a.webp\"><img src=\"a.png\" alt=\"a.webp\"> \
+            <img src='xa.webp'> <a href=\"a.webp\">a.webp</a> <code>a.webp</code></p>\n"
+        );
+    }
+
     #[test]
     fn render_remote_assets_in_sub_chapter() {
         let link = "https://mdbook.epub/dummy.svg";
@@ -602,19 +1700,25 @@ mod tests {
         chvalue.push(ch2);
 
         let ctx = RenderContext::from_json(json.to_string().as_bytes()).unwrap();
-        let mut g = Generator::new(&ctx).unwrap();
+        let mut mock_client = MockContentRetriever::new();
+        mock_client
+            .expect_download()
+            .times(1)
+            .returning(|_, _| Ok(()));
+        let mut g = Generator::new_with_handler(&ctx, mock_client).unwrap();
         g.find_assets().unwrap();
         assert_eq!(g.assets.len(), 1);
 
-        let pat =
-            |heading| format!("<h1>{heading}</h1>\n<p><img src=\"/cache/811c431d49ec880b.svg\"",);
+        let pat = |heading, id, prefix| {
+            format!("<h1 id=\"{id}\">{heading}</h1>\n<p><img src=\"{prefix}cache/811c431d49ec880b.svg\"",)
+        };
         if let BookItem::Chapter(ref ch) = ctx.book.sections[0] {
             let rendered: String = g.render_chapter(ch).unwrap();
-            assert!(rendered.contains(&pat("Chapter 1")));
+            assert!(rendered.contains(&pat("Chapter 1", "chapter-1", "../")));
 
             if let BookItem::Chapter(ref sub_ch) = ch.sub_items[0] {
                 let sub_rendered = g.render_chapter(sub_ch).unwrap();
-                assert!(sub_rendered.contains(&pat("Subchapter")));
+                assert!(sub_rendered.contains(&pat("Subchapter", "subchapter", "../")));
             } else {
                 panic!();
             }
@@ -623,12 +1727,909 @@ mod tests {
         }
         if let BookItem::Chapter(ref ch) = ctx.book.sections[1] {
             let rendered: String = g.render_chapter(ch).unwrap();
-            assert!(rendered.contains(&pat("Chapter 2")));
+            assert!(rendered.contains(&pat("Chapter 2", "chapter-2", "")));
         } else {
             panic!();
         }
     }
 
+    #[test]
+    fn render_part_titles() {
+        fn chapter(name: &str, number: Option<Vec<u32>>, path: &str) -> serde_json::Value {
+            json!({"Chapter": {
+                "name": name,
+                "content": format!("# {name}"),
+                "number": number,
+                "sub_items": [],
+                "path": path,
+                "parent_names": []
+            }})
+        }
+        fn toc_of(part_titles: &str) -> (Vec<String>, String) {
+            let dest_dir = tempfile::TempDir::with_prefix("mdbook-epub").unwrap();
+            let mut json = ctx_with_template("", "src", dest_dir.path());
+            json["config"]["output"]["epub"]["part-titles"] = json!(part_titles);
+            json["book"]["sections"] = json!([
+                chapter("Intro", None, "intro.md"),
+                {"PartTitle": "Part I"},
+                chapter("One", Some(vec![1]), "one.md"),
+                chapter("Two", Some(vec![2]), "two.md"),
+                "Separator",
+                chapter("Appendix", None, "appendix.md"),
+            ]);
+            let ctx = RenderContext::from_json(json.to_string().as_bytes()).unwrap();
+            let mut epub = Vec::new();
+            Generator::new(&ctx).unwrap().generate(&mut epub).unwrap();
+
+            let doc = epub::doc::EpubDoc::from_reader(std::io::Cursor::new(epub)).unwrap();
+            fn flatten(points: &[epub::doc::NavPoint], depth: usize, out: &mut Vec<String>) {
+                for p in points {
+                    out.push(format!(
+                        "{}{} {}",
+                        "-".repeat(depth),
+                        p.label,
+                        p.content.display()
+                    ));
+                    flatten(&p.children, depth + 1, out);
+                }
+            }
+            let mut toc = Vec::new();
+            flatten(&doc.toc, 0, &mut toc);
+            (toc, doc.spine.join(","))
+        }
+
+        let (toc, spine) = toc_of("page");
+        assert_eq!(
+            toc,
+            vec![
+                "Intro OEBPS/intro.html",
+                "Part I OEBPS/part_1.html",
+                "-1. One OEBPS/one.html",
+                "-2. Two OEBPS/two.html",
+                "Appendix OEBPS/appendix.html",
+            ]
+        );
+        assert!(spine.contains("id_part_1.html,id_one.html"));
+
+        let (toc, spine) = toc_of("toc");
+        assert_eq!(
+            toc,
+            vec![
+                "Intro OEBPS/intro.html",
+                "Part I OEBPS/one.html",
+                "-1. One OEBPS/one.html",
+                "-2. Two OEBPS/two.html",
+                "Appendix OEBPS/appendix.html",
+            ]
+        );
+        assert!(!spine.contains("part_1"));
+
+        let (toc, _) = toc_of("skip");
+        assert_eq!(toc.len(), 4);
+        assert_eq!(toc[1], "1. One OEBPS/one.html");
+    }
+
+    #[test]
+    fn write_configured_metadata() {
+        let dest_dir = tempfile::TempDir::with_prefix("mdbook-epub").unwrap();
+        let mut json = ctx_with_template("# Chapter 1", "src", dest_dir.path());
+        json["config"]["book"]["authors"] = json!(["Jane Doe", "Max Mustermann"]);
+        json["config"]["output"]["epub"]["epub-version"] = json!(3);
+        json["config"]["output"]["epub"]["metadata"] = json!({
+            "publisher": "Acme",
+            "rights": "CC-BY-4.0",
+            "subjects": ["Rust", "Programming"],
+            "identifier": "978-3-16-148410-0",
+            "contributors": [{"name": "John Roe", "role": "editor"}]
+        });
+        let ctx = RenderContext::from_json(json.to_string().as_bytes()).unwrap();
+        let mut epub = Vec::new();
+        Generator::new(&ctx).unwrap().generate(&mut epub).unwrap();
+
+        let mut doc = epub::doc::EpubDoc::from_reader(std::io::Cursor::new(epub)).unwrap();
+        assert_eq!(
+            doc.unique_identifier.as_deref(),
+            Some("urn:isbn:9783161484100")
+        );
+        assert_eq!(doc.metadata["creator"], vec!["Jane Doe", "Max Mustermann"]);
+        assert_eq!(doc.metadata["subject"], vec!["Rust", "Programming"]);
+        assert_eq!(doc.mdata("publisher").as_deref(), Some("Acme"));
+        assert_eq!(doc.mdata("rights").as_deref(), Some("CC-BY-4.0"));
+        let opf = doc.get_resource_str_by_path("OEBPS/content.opf").unwrap();
+        assert!(opf.contains(
+            "<meta refines=\"#contributor-1\" property=\"role\" scheme=\"marc:relators\">edt</meta>"
+        ));
+    }
+
+    #[test]
+    fn reproducible_output() {
+        let dest_dir = tempfile::TempDir::with_prefix("mdbook-epub").unwrap();
+        let mut json = ctx_with_template(
+            "# Chapter 1\n\n![Image](rust-logo.png)",
+            "src",
+            dest_dir.path(),
+        );
+        json["config"]["output"]["epub"]["reproducible"] = json!(true);
+        json["config"]["output"]["epub"]["epub-version"] = json!(3);
+        let ctx = RenderContext::from_json(json.to_string().as_bytes()).unwrap();
+        let generate = || {
+            let mut epub = Vec::new();
+            Generator::new(&ctx).unwrap().generate(&mut epub).unwrap();
+            epub
+        };
+
+        let epub = generate();
+        assert_eq!(epub, generate());
+        let mut doc = epub::doc::EpubDoc::from_reader(std::io::Cursor::new(epub)).unwrap();
+        let opf = doc.get_resource_str_by_path("OEBPS/content.opf").unwrap();
+        assert!(opf.contains("<meta property=\"dcterms:modified\">1980-01-01T00:00:00Z</meta>"));
+
+        json["book"]["sections"][0]["Chapter"]["content"] = json!("# Chapter One");
+        let ctx = RenderContext::from_json(json.to_string().as_bytes()).unwrap();
+        let mut changed = Vec::new();
+        Generator::new(&ctx)
+            .unwrap()
+            .generate(&mut changed)
+            .unwrap();
+        let changed = epub::doc::EpubDoc::from_reader(std::io::Cursor::new(changed)).unwrap();
+        assert!(doc
+            .unique_identifier
+            .as_ref()
+            .unwrap()
+            .starts_with("urn:uuid:"));
+        assert_ne!(doc.unique_identifier, changed.unique_identifier);
+
+        // building offline doesn't change the book
+        json["config"]["output"]["epub"]["remote"] = json!({"offline": true});
+        let offline = RenderContext::from_json(json.to_string().as_bytes()).unwrap();
+        json["config"]["output"]["epub"]["remote"] = json!({});
+        let online = RenderContext::from_json(json.to_string().as_bytes()).unwrap();
+        assert_eq!(
+            Generator::new(&offline).unwrap().content_uuid().unwrap(),
+            Generator::new(&online).unwrap().content_uuid().unwrap()
+        );
+    }
+
+    #[test]
+    fn validate_generated_epub() {
+        let dest_dir = tempfile::TempDir::with_prefix("mdbook-epub").unwrap();
+        let mut json = ctx_with_template("# One {#same}\n\n# Two {#same}", "src", dest_dir.path());
+        let ctx = RenderContext::from_json(json.to_string().as_bytes()).unwrap();
+        assert!(Generator::new(&ctx).unwrap().generate(Vec::new()).is_ok());
+
+        json["config"]["output"]["epub"]["validate"] = json!(true);
+        let ctx = RenderContext::from_json(json.to_string().as_bytes()).unwrap();
+        let result = Generator::new(&ctx).unwrap().generate(Vec::new());
+        assert!(matches!(result, Err(Error::EpubCheck(_))));
+    }
+
+    #[test]
+    fn raw_html_becomes_well_formed() {
+        let content = "# Chapter 1\n\nOne<br>two &nbsp;&amp; <!-- a -- b -->\n\n\
+            <div><p>unclosed<img src=\"rust-logo.png\"></div>";
+        let dest_dir = tempfile::TempDir::with_prefix("mdbook-epub").unwrap();
+        let mut json = ctx_with_template(content, "src", dest_dir.path());
+        json["config"]["output"]["epub"]["validate"] = json!(true);
+        let ctx = RenderContext::from_json(json.to_string().as_bytes()).unwrap();
+        let mut g = Generator::new(&ctx).unwrap();
+        g.find_assets().unwrap();
+
+        let rendered = g
+            .render_chapter(
+                ctx.book
+                    .iter()
+                    .next()
+                    .map(|item| match item {
+                        BookItem::Chapter(ch) => ch,
+                        _ => panic!(),
+                    })
+                    .unwrap(),
+            )
+            .unwrap();
+        assert!(rendered.contains(
+            "<p>One<br />two \u{a0}&amp; </p>\n<div><p>unclosed<img src=\"rust-logo.png\" /></p></div>"
+        ));
+        assert!(Generator::new(&ctx).unwrap().generate(Vec::new()).is_ok());
+    }
+
+    #[test]
+    fn chapter_language_and_direction() {
+        let dest_dir = tempfile::TempDir::with_prefix("mdbook-epub").unwrap();
+        let mut json = ctx_with_template("# Chapter 1", "src", dest_dir.path());
+        json["config"]["book"]["language"] = json!("ja");
+        let ctx = RenderContext::from_json(json.to_string().as_bytes()).unwrap();
+        let render = |ctx: &RenderContext| {
+            let mut g = Generator::new(ctx).unwrap();
+            let ch = match ctx.book.iter().next() {
+                Some(BookItem::Chapter(ch)) => ch.clone(),
+                _ => panic!(),
+            };
+            g.render_chapter(&ch).unwrap()
+        };
+        assert!(render(&ctx).contains("xml:lang=\"ja\" lang=\"ja\" dir=\"ltr\">"));
+
+        json["config"]["output"]["epub"]["languages"] = json!({"chapter_1.md": "ar-EG"});
+        let ctx = RenderContext::from_json(json.to_string().as_bytes()).unwrap();
+        assert!(render(&ctx).contains("xml:lang=\"ar-EG\" lang=\"ar-EG\" dir=\"rtl\">"));
+    }
+
+    #[test]
+    fn page_progression_and_writing_mode() {
+        let dest_dir = tempfile::TempDir::with_prefix("mdbook-epub").unwrap();
+        let mut json = ctx_with_template("# Chapter 1", "src", dest_dir.path());
+        json["config"]["output"]["epub"]["epub-version"] = json!(3);
+        let package = |json: &serde_json::Value| {
+            let ctx = RenderContext::from_json(json.to_string().as_bytes()).unwrap();
+            let mut epub = Vec::new();
+            Generator::new(&ctx).unwrap().generate(&mut epub).unwrap();
+            let mut doc = epub::doc::EpubDoc::from_reader(std::io::Cursor::new(epub)).unwrap();
+            let opf = doc.get_resource_str_by_path("OEBPS/content.opf").unwrap();
+            let css = doc
+                .get_resource_str_by_path("OEBPS/stylesheet.css")
+                .unwrap();
+            (opf, css)
+        };
+
+        let (opf, css) = package(&json);
+        assert!(opf.contains("page-progression-direction=\"ltr\""));
+        assert!(!css.contains("writing-mode"));
+
+        json["config"]["book"]["text-direction"] = json!("rtl");
+        let (opf, _) = package(&json);
+        assert!(opf.contains("page-progression-direction=\"rtl\""));
+
+        json["config"]["book"]["text-direction"] = json!("ltr");
+        json["config"]["output"]["epub"]["writing-mode"] = json!("vertical-rl");
+        let (opf, css) = package(&json);
+        assert!(opf.contains("page-progression-direction=\"rtl\""));
+        assert!(css.contains("    writing-mode: vertical-rl;\n"));
+
+        json["config"]["output"]["epub"]["page-progression"] = json!("ltr");
+        let (opf, _) = package(&json);
+        assert!(opf.contains("page-progression-direction=\"ltr\""));
+    }
+
+    #[test]
+    fn accessibility_metadata_and_report() {
+        let dest_dir = tempfile::TempDir::with_prefix("mdbook-epub").unwrap();
+        let mut json = ctx_with_template(
+            "# Chapter 1\n\n<img src=\"rust-logo.png\">\n\n### Details",
+            "src",
+            dest_dir.path(),
+        );
+        json["config"]["output"]["epub"]["epub-version"] = json!(3);
+        json["config"]["output"]["epub"]["accessibility"] = json!({"report": true});
+        let ctx = RenderContext::from_json(json.to_string().as_bytes()).unwrap();
+        let mut epub = Vec::new();
+        Generator::new(&ctx).unwrap().generate(&mut epub).unwrap();
+
+        let mut doc = epub::doc::EpubDoc::from_reader(std::io::Cursor::new(epub)).unwrap();
+        let opf = doc.get_resource_str_by_path("OEBPS/content.opf").unwrap();
+        assert!(opf.contains("<meta property=\"schema:accessMode\">visual</meta>"));
+        assert!(!opf.contains("alternativeText"));
+
+        let report =
+            std::fs::read_to_string(dest_dir.path().join("accessibility-report.txt")).unwrap();
+        assert!(report.starts_with("Accessibility report for DummyBook\n"));
+        assert!(report.contains(
+            "\nIssues:\n  chapter_1.html: image \"rust-logo.png\" has no alt text\n  \
+            chapter_1.html: heading \"Details\" skips from h1 to h3\n"
+        ));
+    }
+
+    #[test]
+    fn landmarks_and_page_list() {
+        let dest_dir = tempfile::TempDir::with_prefix("mdbook-epub").unwrap();
+        let mut json = ctx_with_template(
+            "# Chapter 1\n\n<!-- page 1 -->\n\nText <!-- page 2 --> more.",
+            "src",
+            dest_dir.path(),
+        );
+        let epub_config = &mut json["config"]["output"]["epub"];
+        epub_config["epub-version"] = json!(3);
+        epub_config["validate"] = json!(true);
+        epub_config["cover-image"] = json!("rust-logo.png");
+        epub_config["page-marker"] = json!("<!-- page {} -->");
+        epub_config["landmarks"] = json!({"index": "chapter_1.md"});
+        let ctx = RenderContext::from_json(json.to_string().as_bytes()).unwrap();
+        let mut epub = Vec::new();
+        Generator::new(&ctx).unwrap().generate(&mut epub).unwrap();
+
+        let mut doc = epub::doc::EpubDoc::from_reader(std::io::Cursor::new(epub)).unwrap();
+        assert_eq!(doc.spine[0], "id_cover.xhtml");
+        let nav = doc.get_resource_str_by_path("OEBPS/nav.xhtml").unwrap();
+        assert!(nav.contains(
+            "<ol>\n      <li><a epub:type=\"cover\" href=\"cover.xhtml\">Cover</a></li>\n      \
+            <li><a epub:type=\"toc\" href=\"nav.xhtml#toc\">Table Of Contents</a></li>\n      \
+            <li><a epub:type=\"index\" href=\"chapter_1.html\">1. Chapter 1</a></li>\n"
+        ));
+        assert!(nav.contains("<li><a href=\"chapter_1.html#page-2\">2</a></li>"));
+        let opf = doc.get_resource_str_by_path("OEBPS/content.opf").unwrap();
+        assert!(
+            opf.contains("<meta property=\"schema:accessibilityFeature\">pageNavigation</meta>")
+        );
+        let ncx = doc.get_resource_str_by_path("OEBPS/toc.ncx").unwrap();
+        assert!(ncx.contains("<meta name=\"dtb:maxPageNumber\" content=\"2\" />"));
+        let chapter = doc
+            .get_resource_str_by_path("OEBPS/chapter_1.html")
+            .unwrap();
+        assert!(chapter.contains(
+            "<p>Text <span epub:type=\"pagebreak\" role=\"doc-pagebreak\" id=\"page-2\" \
+            aria-label=\"2\"></span> more.</p>"
+        ));
+
+        // without configured landmarks the first numbered chapter is the bodymatter
+        json["config"]["output"]["epub"]["landmarks"] = json!({});
+        let ctx = RenderContext::from_json(json.to_string().as_bytes()).unwrap();
+        let mut epub = Vec::new();
+        Generator::new(&ctx).unwrap().generate(&mut epub).unwrap();
+        let mut doc = epub::doc::EpubDoc::from_reader(std::io::Cursor::new(epub)).unwrap();
+        let nav = doc.get_resource_str_by_path("OEBPS/nav.xhtml").unwrap();
+        assert!(nav.contains("<li><a epub:type=\"bodymatter\" href=\"chapter_1.html\">"));
+    }
+
+    #[test]
+    fn generated_cover() {
+        let dest_dir = tempfile::TempDir::with_prefix("mdbook-epub").unwrap();
+        let mut json = ctx_with_template("# Chapter 1\n\nText", "src", dest_dir.path());
+        json["config"]["book"]["authors"] = json!(["Jane Doe"]);
+        let epub_config = &mut json["config"]["output"]["epub"];
+        epub_config["epub-version"] = json!(3);
+        epub_config["validate"] = json!(true);
+        epub_config["generated-cover"] =
+            json!({"enable": true, "subtitle": "A handbook", "logo": "rust-logo.png"});
+        let ctx = RenderContext::from_json(json.to_string().as_bytes()).unwrap();
+        let mut epub = Vec::new();
+        Generator::new(&ctx).unwrap().generate(&mut epub).unwrap();
+
+        let mut doc = epub::doc::EpubDoc::from_reader(std::io::Cursor::new(epub)).unwrap();
+        assert_eq!(doc.spine[0], "id_cover.xhtml");
+        let (cover, mime) = doc.get_cover().unwrap();
+        assert_eq!(mime, "image/svg+xml");
+        let cover = String::from_utf8(cover).unwrap();
+        assert!(cover.contains(">DummyBook</text>"));
+        assert!(cover.contains(">A handbook</text>"));
+        assert!(cover.contains(">Jane Doe</text>"));
+        assert!(cover.contains("xlink:href=\"data:image/png;base64,"));
+        let page = doc.get_resource_str_by_path("OEBPS/cover.xhtml").unwrap();
+        assert!(page.contains("<img src=\"cover.svg\" alt=\"DummyBook\" />"));
+
+        // a configured cover image wins
+        json["config"]["output"]["epub"]["cover-image"] = json!("rust-logo.png");
+        let ctx = RenderContext::from_json(json.to_string().as_bytes()).unwrap();
+        let mut epub = Vec::new();
+        Generator::new(&ctx).unwrap().generate(&mut epub).unwrap();
+        let mut doc = epub::doc::EpubDoc::from_reader(std::io::Cursor::new(epub)).unwrap();
+        assert_eq!(doc.get_cover().unwrap().1, "image/png");
+    }
+
+    #[test]
+    fn embed_stylesheet_references() {
+        let dest_dir = tempfile::TempDir::with_prefix("mdbook-epub").unwrap();
+        let theme = dest_dir.path().join("theme");
+        fs::create_dir_all(theme.join("fonts")).unwrap();
+        fs::write(theme.join("fonts/Inter.woff2"), b"woff2").unwrap();
+        fs::write(
+            theme.join("fonts.css"),
+            "@font-face { src: url(fonts/Inter.woff2?v=4#x) format(\"woff2\"); }",
+        )
+        .unwrap();
+        let logo = Path::new("tests/dummy/src/rust-logo.png")
+            .canonicalize()
+            .unwrap();
+        fs::write(
+            theme.join("style.css"),
+            format!(
+                "@import \"fonts.css\";\n@import 'style.css' print;\n\
+                h1 {{ background: url('{}'); }}\np {{ background: url(missing.png); }}",
+                logo.display()
+            ),
+        )
+        .unwrap();
+
+        let mut json = ctx_with_template("# Chapter 1\n\nText", "src", dest_dir.path());
+        json["config"]["output"]["epub"]["additional-css"] = json!([theme.join("style.css")]);
+        json["config"]["output"]["epub"]["use-default-css"] = json!(false);
+        json["config"]["output"]["epub"]["validate"] = json!(true);
+        let ctx = RenderContext::from_json(json.to_string().as_bytes()).unwrap();
+        let mut epub = Vec::new();
+        Generator::new(&ctx).unwrap().generate(&mut epub).unwrap();
+
+        let mut doc = epub::doc::EpubDoc::from_reader(std::io::Cursor::new(epub)).unwrap();
+        let css = doc
+            .get_resource_str_by_path("OEBPS/stylesheet.css")
+            .unwrap();
+        assert_eq!(
+            css,
+            "@font-face { src: url(\"css/Inter.woff2?v=4#x\") format(\"woff2\"); }\n\
+            \n\
+            h1 { background: url(\"rust-logo.png\"); }\n\
+            p { background: url(missing.png); }"
+        );
+        assert_eq!(
+            doc.get_resource_by_path("OEBPS/css/Inter.woff2").unwrap(),
+            b"woff2"
+        );
+        assert!(doc.get_resource_by_path("OEBPS/rust-logo.png").is_some());
+        assert_eq!(
+            doc.get_resource_mime_by_path("OEBPS/css/Inter.woff2")
+                .unwrap(),
+            "font/woff2"
+        );
+    }
+
+    #[test]
+    fn embed_subset_obfuscated_fonts() {
+        let dest_dir = tempfile::TempDir::with_prefix("mdbook-epub").unwrap();
+        let font = dest_dir.path().join("Icons.ttf");
+        fs::write(&font, mdbook::theme::FONT_AWESOME_TTF).unwrap();
+        let mut json = ctx_with_template("# Chapter 1\n\nIcon \u{f000}", "src", dest_dir.path());
+        let epub_config = &mut json["config"]["output"]["epub"];
+        epub_config["epub-version"] = json!(3);
+        epub_config["validate"] = json!(true);
+        epub_config["metadata"] =
+            json!({"identifier": "urn:uuid:a3b3a2e4-0ec5-4a6c-8e3b-1c6d2f5e8b90"});
+        epub_config["fonts"] = json!({
+            "faces": [{"family": "Icons", "file": font, "weight": "bold"}],
+            "subset": true,
+            "obfuscation": "idpf"
+        });
+        let ctx = RenderContext::from_json(json.to_string().as_bytes()).unwrap();
+        let mut epub = Vec::new();
+        Generator::new(&ctx).unwrap().generate(&mut epub).unwrap();
+
+        let mut doc = epub::doc::EpubDoc::from_reader(std::io::Cursor::new(epub)).unwrap();
+        let css = doc
+            .get_resource_str_by_path("OEBPS/stylesheet.css")
+            .unwrap();
+        assert!(css.contains(
+            "@font-face {\n    font-family: \"Icons\";\n    font-weight: bold;\n    \
+            src: url(\"fonts/Icons.ttf\");\n}"
+        ));
+        let encryption = doc
+            .get_resource_str_by_path("META-INF/encryption.xml")
+            .unwrap();
+        assert!(encryption.contains("<enc:CipherReference URI=\"OEBPS/fonts/Icons.ttf\"/>"));
+
+        let mut data = doc.get_resource_by_path("OEBPS/fonts/Icons.ttf").unwrap();
+        assert!(ttf_parser::Face::parse(&data, 0).is_err());
+        let key = fonts::obfuscation_key(
+            "urn:uuid:a3b3a2e4-0ec5-4a6c-8e3b-1c6d2f5e8b90",
+            FontObfuscation::Idpf,
+        )
+        .unwrap();
+        fonts::obfuscate(&mut data, &key, FontObfuscation::Idpf);
+        let face = ttf_parser::Face::parse(&data, 0).unwrap();
+        let used = face.glyph_index('\u{f000}').unwrap();
+        assert!(face.glyph_bounding_box(used).is_some());
+        let unused = face.glyph_index('\u{f002}').unwrap();
+        assert!(face.glyph_bounding_box(unused).is_none());
+    }
+
+    #[test]
+    fn subset_fonts_for_endnotes() {
+        let dest_dir = tempfile::TempDir::with_prefix("mdbook-epub").unwrap();
+        let font = dest_dir.path().join("Icons.ttf");
+        fs::write(&font, mdbook::theme::FONT_AWESOME_TTF).unwrap();
+        let mut json = ctx_with_template(
+            "# Chapter 1\n\nIcon \u{f000}[^1]\n\n[^1]: Note \u{f002}",
+            "src",
+            dest_dir.path(),
+        );
+        let epub_config = &mut json["config"]["output"]["epub"];
+        epub_config["epub-version"] = json!(3);
+        epub_config["footnotes"] = json!("book");
+        epub_config["fonts"] = json!({
+            "faces": [{"family": "Icons", "file": font}],
+            "subset": true
+        });
+        let ctx = RenderContext::from_json(json.to_string().as_bytes()).unwrap();
+        let mut epub = Vec::new();
+        Generator::new(&ctx).unwrap().generate(&mut epub).unwrap();
+
+        let mut doc = epub::doc::EpubDoc::from_reader(std::io::Cursor::new(epub)).unwrap();
+        let data = doc.get_resource_by_path("OEBPS/fonts/Icons.ttf").unwrap();
+        let face = ttf_parser::Face::parse(&data, 0).unwrap();
+        // the character is only used in a footnote moved to the endnotes
+        let noted = face.glyph_index('\u{f002}').unwrap();
+        assert!(face.glyph_bounding_box(noted).is_some());
+        let unused = face.glyph_index('\u{f003}').unwrap();
+        assert!(face.glyph_bounding_box(unused).is_none());
+    }
+
+    #[test]
+    fn keep_invalid_books_unwritten() {
+        let src_dir = tempfile::TempDir::with_prefix("mdbook-epub").unwrap();
+        let dest_dir = tempfile::TempDir::with_prefix("mdbook-epub").unwrap();
+        fs::write(
+            src_dir.path().join("extra.xhtml"),
+            "<html><p>unclosed</html>",
+        )
+        .unwrap();
+        let mut json = ctx_with_template(
+            "# Chapter 1\n\nText",
+            src_dir.path().to_str().unwrap(),
+            dest_dir.path(),
+        );
+        json["config"]["output"]["epub"]["validate"] = json!(true);
+        json["config"]["output"]["epub"]["additional-resources"] = json!(["extra.xhtml"]);
+        let ctx = RenderContext::from_json(json.to_string().as_bytes()).unwrap();
+        let mut epub = Vec::new();
+        let error = Generator::new(&ctx)
+            .unwrap()
+            .generate(&mut epub)
+            .unwrap_err();
+        assert!(matches!(error, Error::EpubCheck(_)));
+        assert!(epub.is_empty());
+    }
+
+    #[test]
+    fn process_images() {
+        let src_dir = tempfile::TempDir::with_prefix("mdbook-epub").unwrap();
+        let dest_dir = tempfile::TempDir::with_prefix("mdbook-epub").unwrap();
+        let image = image::RgbImage::from_pixel(400, 300, image::Rgb([200, 30, 30]));
+        image.save(src_dir.path().join("large.png")).unwrap();
+        image.save(src_dir.path().join("photo.webp")).unwrap();
+        image::RgbImage::new(4, 4)
+            .save(src_dir.path().join("photo.png"))
+            .unwrap();
+        fs::write(src_dir.path().join("broken.webp"), "not an image").unwrap();
+        fs::write(src_dir.path().join("photo.avif"), "avif").unwrap();
+        image.save(src_dir.path().join("banner.webp")).unwrap();
+        fs::write(
+            src_dir.path().join("style.css"),
+            "h1 { background: url(banner.webp); }\np { background: url('photo.webp?v=2'); }",
+        )
+        .unwrap();
+        let mut json = ctx_with_template(
+            "# Chapter 1\n\n![Large](large.png)\n\n![Photo](photo.webp)\n\n\
+            <img src=\"photo.webp\" alt=\"Photo\"/>\n\n![Small](photo.png)\n\n\
+            ![Broken](broken.webp)\n\n![AVIF](photo.avif)",
+            src_dir.path().to_str().unwrap(),
+            dest_dir.path(),
+        );
+        json["config"]["output"]["epub"]["validate"] = json!(true);
+        json["config"]["output"]["epub"]["images"] = json!({"max-size": 100, "convert": true});
+        json["config"]["output"]["epub"]["additional-css"] =
+            json!([src_dir.path().join("style.css")]);
+        json["config"]["output"]["epub"]["use-default-css"] = json!(false);
+        let ctx = RenderContext::from_json(json.to_string().as_bytes()).unwrap();
+        let mut epub = Vec::new();
+        Generator::new(&ctx).unwrap().generate(&mut epub).unwrap();
+
+        let mut doc = epub::doc::EpubDoc::from_reader(std::io::Cursor::new(epub)).unwrap();
+        // images referenced by stylesheets are processed too
+        let css = doc
+            .get_resource_str_by_path("OEBPS/stylesheet.css")
+            .unwrap();
+        assert_eq!(
+            css,
+            "h1 { background: url(\"banner.png\"); }\n\
+            p { background: url(\"photo-1.png?v=2\"); }"
+        );
+        let banner = doc.get_resource_by_path("OEBPS/banner.png").unwrap();
+        let banner = image::load_from_memory(&banner).unwrap();
+        assert_eq!((banner.width(), banner.height()), (100, 75));
+        assert!(doc.get_resource_by_path("OEBPS/banner.webp").is_none());
+        let chapter = doc
+            .get_resource_str_by_path("OEBPS/chapter_1.html")
+            .unwrap();
+        assert!(chapter.contains("<img src=\"large.png\" alt=\"Large\" />"));
+        // the converted image doesn't replace another one with its name
+        assert!(chapter.contains("<img src=\"photo-1.png\" alt=\"Photo\" />"));
+        assert!(chapter.contains("<img src=\"photo.png\" alt=\"Small\" />"));
+        assert!(!chapter.contains("photo.webp"));
+        // images failing to decode are embedded as they are
+        assert!(chapter.contains("<img src=\"broken.webp\" alt=\"Broken\" />"));
+        assert_eq!(
+            doc.get_resource_by_path("OEBPS/broken.webp").unwrap(),
+            b"not an image"
+        );
+        // AVIF images can't be decoded to convert them
+        assert!(chapter.contains("<img src=\"photo.avif\" alt=\"AVIF\" />"));
+        assert_eq!(
+            doc.get_resource_by_path("OEBPS/photo.avif").unwrap(),
+            b"avif"
+        );
+
+        let large = doc.get_resource_by_path("OEBPS/large.png").unwrap();
+        let large = image::load_from_memory(&large).unwrap();
+        assert_eq!((large.width(), large.height()), (100, 75));
+        assert_eq!(
+            doc.get_resource_mime_by_path("OEBPS/photo-1.png").unwrap(),
+            "image/png"
+        );
+        let photo = doc.get_resource_by_path("OEBPS/photo-1.png").unwrap();
+        let photo = image::load_from_memory(&photo).unwrap();
+        assert_eq!((photo.width(), photo.height()), (100, 75));
+        let small = doc.get_resource_by_path("OEBPS/photo.png").unwrap();
+        let small = image::load_from_memory(&small).unwrap();
+        assert_eq!((small.width(), small.height()), (4, 4));
+        assert!(doc.get_resource_by_path("OEBPS/photo.webp").is_none());
+    }
+
+    #[test]
+    fn handle_failed_downloads() {
+        struct UnavailableHandler;
+        impl ContentRetriever for UnavailableHandler {
+            fn retrieve(
+                &self,
+                url: &str,
+                _cached: Option<&CacheEntry>,
+            ) -> Result<Option<Response>, Error> {
+                Err(Error::RemoteAsset {
+                    url: url.to_string(),
+                    status: Some(503),
+                    reason: "HTTP status 503 Service Unavailable".to_string(),
+                })
+            }
+        }
+        let render = |on_error: &str| {
+            let dest_dir = tempfile::TempDir::with_prefix("mdbook-epub").unwrap();
+            let mut json = ctx_with_template(
+                "# Chapter 1\n\n![Logo](http://server/logo.png)\n\n\
+                <img src=\"http://server/photo.jpg\" alt=\"Photo\"/>\n\n\
+                Listen <audio src=\"http://server/sample.mp3\"></audio>",
+                "src",
+                dest_dir.path(),
+            );
+            json["config"]["output"]["epub"]["remote"] =
+                json!({ "on-error": on_error, "cache-dir": dest_dir.path().join("cache") });
+            let ctx = RenderContext::from_json(json.to_string().as_bytes()).unwrap();
+            let mut epub = Vec::new();
+            Generator::new_with_handler(&ctx, UnavailableHandler)
+                .unwrap()
+                .generate(&mut epub)
+                .map(|_| epub)
+        };
+
+        let error = render("abort").unwrap_err();
+        let Error::RemoteAssets(ref errors) = error else {
+            panic!("every failed download is reported, got {error:?}");
+        };
+        assert_eq!(errors.len(), 3);
+        assert!(error.to_string().starts_with(
+            "Failed to download 3 remote assets:\n  Failed to download http://server/"
+        ));
+
+        let epub = render("skip").unwrap();
+        let mut doc = epub::doc::EpubDoc::from_reader(std::io::Cursor::new(epub)).unwrap();
+        let chapter = doc
+            .get_resource_str_by_path("OEBPS/chapter_1.html")
+            .unwrap();
+        assert!(chapter.contains("<img src=\"http://server/logo.png\" alt=\"Logo\" />"));
+        assert!(chapter.contains("src=\"http://server/photo.jpg\""));
+
+        let epub = render("placeholder").unwrap();
+        let mut doc = epub::doc::EpubDoc::from_reader(std::io::Cursor::new(epub)).unwrap();
+        let chapter = doc
+            .get_resource_str_by_path("OEBPS/chapter_1.html")
+            .unwrap();
+        assert!(chapter.contains("<img src=\"cache/placeholder.svg\" alt=\"Logo\" />"));
+        assert!(chapter.contains("<img src=\"cache/placeholder.svg\" alt=\"Photo\" />"));
+        assert!(chapter.contains("<audio src=\"http://server/sample.mp3\">"));
+        assert_eq!(
+            doc.get_resource_mime_by_path("OEBPS/cache/placeholder.svg")
+                .unwrap(),
+            "image/svg+xml"
+        );
+    }
+
+    #[test]
+    fn keep_disallowed_remote_links() {
+        struct TestHandler;
+        impl ContentRetriever for TestHandler {
+            fn retrieve(
+                &self,
+                _url: &str,
+                _cached: Option<&CacheEntry>,
+            ) -> Result<Option<Response>, Error> {
+                Ok(Some(Response::new(PLACEHOLDER_IMAGE.as_bytes())))
+            }
+        }
+        let dest_dir = tempfile::TempDir::with_prefix("mdbook-epub").unwrap();
+        let mut json = ctx_with_template(
+            "# Chapter 1\n\n![One](http://images.test/one.svg)\n\n\
+            ![Two](http://images.test/two.svg)\n\n![Tracker](http://ads.test/pixel.svg)",
+            "src",
+            dest_dir.path(),
+        );
+        json["config"]["output"]["epub"]["remote"] = json!({
+            "cache-dir": dest_dir.path().join("cache"),
+            "denied-hosts": ["ads.test"],
+            "max-total-bytes": PLACEHOLDER_IMAGE.len() + 10,
+            "keep-disallowed": true,
+        });
+        let ctx = RenderContext::from_json(json.to_string().as_bytes()).unwrap();
+        let mut epub = Vec::new();
+        Generator::new_with_handler(&ctx, TestHandler)
+            .unwrap()
+            .generate(&mut epub)
+            .unwrap();
+
+        let mut doc = epub::doc::EpubDoc::from_reader(std::io::Cursor::new(epub)).unwrap();
+        let chapter = doc
+            .get_resource_str_by_path("OEBPS/chapter_1.html")
+            .unwrap();
+        assert!(chapter.contains("<img src=\"http://ads.test/pixel.svg\" alt=\"Tracker\" />"));
+        // only one of the images fits into the size of the book
+        assert_eq!(chapter.matches("<img src=\"cache/").count(), 1);
+        assert_eq!(chapter.matches("<img src=\"http://images.test/").count(), 1);
+    }
+
+    #[test]
+    fn type_remote_assets_by_content() {
+        struct TestHandler;
+        impl ContentRetriever for TestHandler {
+            fn retrieve(
+                &self,
+                url: &str,
+                _cached: Option<&CacheEntry>,
+            ) -> Result<Option<Response>, Error> {
+                if url.contains("badge") {
+                    return Ok(Some(Response {
+                        content_type: Some("image/svg+xml; charset=utf-8".to_string()),
+                        ..Response::new(PLACEHOLDER_IMAGE.as_bytes())
+                    }));
+                }
+                let mut png = std::io::Cursor::new(Vec::new());
+                image::RgbImage::new(4, 4)
+                    .write_to(&mut png, image::ImageFormat::Png)
+                    .unwrap();
+                Ok(Some(Response {
+                    content_type: Some("application/octet-stream".to_string()),
+                    ..Response::new(std::io::Cursor::new(png.into_inner()))
+                }))
+            }
+        }
+        let dest_dir = tempfile::TempDir::with_prefix("mdbook-epub").unwrap();
+        let badge = "https://img.shields.io/badge/build-passing-green";
+        let chart = "https://charts.test/render?format=png";
+        let mut json = ctx_with_template(
+            &format!("# Chapter 1\n\n![Badge]({badge})\n\n<img src=\"{chart}\" alt=\"Chart\"/>"),
+            "src",
+            dest_dir.path(),
+        );
+        json["config"]["output"]["epub"]["remote"] =
+            json!({ "cache-dir": dest_dir.path().join("cache") });
+        let ctx = RenderContext::from_json(json.to_string().as_bytes()).unwrap();
+        let mut epub = Vec::new();
+        Generator::new_with_handler(&ctx, TestHandler)
+            .unwrap()
+            .generate(&mut epub)
+            .unwrap();
+
+        let badge = Path::new("cache").join(resources::hash_link(&Url::parse(badge).unwrap()));
+        let badge = badge.with_extension("svg");
+        let chart = Path::new("cache").join(resources::hash_link(&Url::parse(chart).unwrap()));
+        let chart = chart.with_extension("png");
+        let mut doc = epub::doc::EpubDoc::from_reader(std::io::Cursor::new(epub)).unwrap();
+        let chapter = doc
+            .get_resource_str_by_path("OEBPS/chapter_1.html")
+            .unwrap();
+        assert!(chapter.contains(&format!(
+            "<img src=\"{}\" alt=\"Badge\" />",
+            badge.display()
+        )));
+        assert!(chapter.contains(&format!(
+            "<img src=\"{}\" alt=\"Chart\" />",
+            chart.display()
+        )));
+        assert_eq!(
+            doc.get_resource_mime_by_path(Path::new("OEBPS").join(&badge))
+                .unwrap(),
+            "image/svg+xml"
+        );
+        assert_eq!(
+            doc.get_resource_mime_by_path(Path::new("OEBPS").join(&chart))
+                .unwrap(),
+            "image/png"
+        );
+    }
+
+    #[test]
+    fn validate_remote_assets() {
+        struct TestHandler;
+        impl ContentRetriever for TestHandler {
+            fn retrieve(
+                &self,
+                _url: &str,
+                _cached: Option<&CacheEntry>,
+            ) -> Result<Option<Response>, Error> {
+                Ok(Some(Response {
+                    content_type: Some("image/svg+xml".to_string()),
+                    ..Response::new(PLACEHOLDER_IMAGE.as_bytes())
+                }))
+            }
+        }
+        let dest_dir = tempfile::TempDir::with_prefix("mdbook-epub").unwrap();
+        let logo = "https://example.test/logo.svg";
+        let mut json = ctx_with_template(
+            &format!("# Chapter 1\n\n![Logo]({logo})\n"),
+            "src",
+            dest_dir.path(),
+        );
+        // a nested chapter links the shared cache with a parent path
+        json["book"]["sections"][0]["Chapter"]["path"] = json!("part/chapter_1.md");
+        json["config"]["output"]["epub"]["validate"] = json!(true);
+        json["config"]["output"]["epub"]["remote"] =
+            json!({ "cache-dir": dest_dir.path().join("cache") });
+        let ctx = RenderContext::from_json(json.to_string().as_bytes()).unwrap();
+        let mut epub = Vec::new();
+        Generator::new_with_handler(&ctx, TestHandler)
+            .unwrap()
+            .generate(&mut epub)
+            .unwrap();
+
+        let logo = Path::new("cache").join(resources::hash_link(&Url::parse(logo).unwrap()));
+        let mut doc = epub::doc::EpubDoc::from_reader(std::io::Cursor::new(epub)).unwrap();
+        let chapter = doc
+            .get_resource_str_by_path("OEBPS/part/chapter_1.html")
+            .unwrap();
+        assert!(chapter.contains(&format!(
+            "<img src=\"../{}\" alt=\"Logo\" />",
+            logo.display()
+        )));
+    }
+
+    #[test]
+    fn convert_math_to_mathml() {
+        let dest_dir = tempfile::TempDir::with_prefix("mdbook-epub").unwrap();
+        let mut json = ctx_with_template(
+            "# Chapter 1\n\nInline \\\\( a^2 \\\\) and\n\n$$\n\\sum_i x_i\n$$",
+            "src",
+            dest_dir.path(),
+        );
+        json["config"]["output"]["epub"]["math"] = json!(true);
+        json["config"]["output"]["epub"]["epub-version"] = json!(3);
+        json["config"]["output"]["epub"]["validate"] = json!(true);
+        let ctx = RenderContext::from_json(json.to_string().as_bytes()).unwrap();
+        let mut epub = Vec::new();
+        Generator::new(&ctx).unwrap().generate(&mut epub).unwrap();
+
+        let mut doc = epub::doc::EpubDoc::from_reader(std::io::Cursor::new(epub)).unwrap();
+        let opf = doc.get_resource_str_by_path("OEBPS/content.opf").unwrap();
+        assert!(opf.contains(
+            "<item properties=\"mathml\" media-type=\"application/xhtml+xml\" \
+            id=\"id_chapter_1.html\" href=\"chapter_1.html\"/>"
+        ));
+        let chapter = doc
+            .get_resource_str_by_path("OEBPS/chapter_1.html")
+            .unwrap();
+        assert!(chapter.contains("<p>Inline <math alttext=\"a^2\""));
+        assert!(chapter.contains("<p><math alttext=\"\\sum_i x_i\""));
+    }
+
+    #[test]
+    fn collect_footnotes_into_endnotes() {
+        let dest_dir = tempfile::TempDir::with_prefix("mdbook-epub").unwrap();
+        let mut json = ctx_with_template(
+            "# Chapter 1\n\nText[^1].\n\n[^1]: The <note>.",
+            "src",
+            dest_dir.path(),
+        );
+        json["config"]["output"]["epub"]["epub-version"] = json!(3);
+        json["config"]["output"]["epub"]["footnotes"] = json!("book");
+        let ctx = RenderContext::from_json(json.to_string().as_bytes()).unwrap();
+        let mut epub = Vec::new();
+        Generator::new(&ctx).unwrap().generate(&mut epub).unwrap();
+
+        let mut doc = epub::doc::EpubDoc::from_reader(std::io::Cursor::new(epub)).unwrap();
+        let chapter = doc
+            .get_resource_str_by_path("OEBPS/chapter_1.html")
+            .unwrap();
+        assert!(chapter.contains(
+            "<a epub:type=\"noteref\" id=\"fnref-chapter-1-html-1\" \
+            href=\"endnotes.html#fn-chapter-1-html-1\">1</a>"
+        ));
+        let endnotes = doc.get_resource_str_by_path("OEBPS/endnotes.html").unwrap();
+        assert!(endnotes.contains(
+            "<h2>Chapter 1</h2>\n<aside class=\"footnote-definition\" \
+            epub:type=\"endnote\" id=\"fn-chapter-1-html-1\">"
+        ));
+        assert!(endnotes.contains("<a href=\"chapter_1.html#fnref-chapter-1-html-1\">1</a>"));
+    }
+
     #[test]
     #[should_panic]
     fn find_assets_with_wrong_src_dir() {
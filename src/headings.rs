@@ -0,0 +1,151 @@
+use std::collections::HashMap;
+
+use epub_builder::TocElement;
+use mdbook::utils::{new_cmark_parser, unique_id_from_content};
+use pulldown_cmark::{html, CowStr, Event, Tag, TagEnd};
+
+/// A heading found in a chapter, with the anchor it is rendered with.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Heading {
+    pub(crate) level: u32,
+    pub(crate) id: String,
+    pub(crate) title: String,
+}
+
+/// Give every heading an `id` attribute, using the same slugs (and
+/// de-duplication) as mdBook's HTML renderer. Explicit ids such as
+/// `# Title {#custom}` are kept as they are.
+pub(crate) fn anchor_headings<'a, I>(mut events: I) -> (Vec<Event<'a>>, Vec<Heading>)
+where
+    I: Iterator<Item = Event<'a>>,
+{
+    let mut id_counter = HashMap::new();
+    let mut anchored = Vec::new();
+    let mut headings = Vec::new();
+
+    while let Some(event) = events.next() {
+        let Event::Start(Tag::Heading {
+            level,
+            id,
+            classes,
+            attrs,
+        }) = event
+        else {
+            anchored.push(event);
+            continue;
+        };
+
+        let mut inner = Vec::new();
+        for event in events.by_ref() {
+            if let Event::End(TagEnd::Heading(_)) = event {
+                break;
+            }
+            inner.push(event);
+        }
+        let id = match id {
+            Some(id) => {
+                *id_counter.entry(id.to_string()).or_insert(0) += 1;
+                id.to_string()
+            }
+            None => {
+                let mut content = String::new();
+                html::push_html(&mut content, inner.iter().cloned());
+                unique_id_from_content(&content, &mut id_counter)
+            }
+        };
+        let title = inner
+            .iter()
+            .filter_map(|event| match event {
+                Event::Text(text) | Event::Code(text) => Some(text.as_ref()),
+                _ => None,
+            })
+            .collect::<String>();
+        headings.push(Heading {
+            level: level as u32,
+            id: id.clone(),
+            title,
+        });
+
+        anchored.push(Event::Start(Tag::Heading {
+            level,
+            id: Some(CowStr::from(id)),
+            classes,
+            attrs,
+        }));
+        anchored.extend(inner);
+        anchored.push(Event::End(TagEnd::Heading(level)));
+    }
+
+    (anchored, headings)
+}
+
+/// Find all headings of a chapter's markdown content.
+pub(crate) fn find_headings(content: &str, curly_quotes: bool) -> Vec<Heading> {
+    anchor_headings(new_cmark_parser(content, curly_quotes)).1
+}
+
+/// Build nested table of contents entries for the headings from `h2` down to
+/// `h<depth>` of the chapter rendered to `href`.
+pub(crate) fn toc_elements(headings: &[Heading], href: &str, depth: u32) -> Vec<TocElement> {
+    let mut root = TocElement::new(href, "").level(1);
+    for heading in headings.iter().filter(|h| h.level > 1 && h.level <= depth) {
+        let url = format!("{}#{}", href, heading.id);
+        root.add(TocElement::new(url, heading.title.as_str()).level(heading.level as i32));
+    }
+    root.children
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn anchors_like_mdbook() {
+        let content = "# Chapter 1\n\n## Install `cargo`\n\n## Usage\n\n### Usage\n\n\
+            ## Custom {#my-id}\n\n## Usage";
+        let mut rendered = String::new();
+        let (events, headings) = anchor_headings(new_cmark_parser(content, false));
+        html::push_html(&mut rendered, events.into_iter());
+
+        assert_eq!(
+            rendered,
+            "<h1 id=\"chapter-1\">Chapter 1</h1>\n\
+            <h2 id=\"install-cargo\">Install <code>cargo</code></h2>\n\
+            <h2 id=\"usage\">Usage</h2>\n\
+            <h3 id=\"usage-1\">Usage</h3>\n\
+            <h2 id=\"my-id\">Custom</h2>\n\
+            <h2 id=\"usage-2\">Usage</h2>\n"
+        );
+        assert_eq!(
+            headings[1],
+            Heading {
+                level: 2,
+                id: "install-cargo".to_string(),
+                title: "Install cargo".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn nest_headings_in_toc() {
+        let headings = find_headings("# Top\n### Orphan\n## A\n### A.1\n#### A.1.1\n## B", false);
+
+        let toc = toc_elements(&headings, "ch.html", 3);
+        let summary = toc
+            .iter()
+            .map(|e| {
+                let children = e.children.iter().map(|c| c.url.as_str()).collect();
+                (e.url.as_str(), children)
+            })
+            .collect::<Vec<(&str, Vec<&str>)>>();
+        assert_eq!(
+            summary,
+            vec![
+                ("ch.html#orphan", vec![]),
+                ("ch.html#a", vec!["ch.html#a1"]),
+                ("ch.html#b", vec![]),
+            ]
+        );
+        assert!(toc_elements(&headings, "ch.html", 1).is_empty());
+    }
+}
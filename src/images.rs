@@ -0,0 +1,235 @@
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::{CompressionType, FilterType, PngEncoder};
+use image::imageops;
+use mime_guess::{mime, Mime};
+use resvg::{tiny_skia, usvg};
+
+use crate::config::Images;
+
+/// The JPEG quality of resized or converted images if none is configured.
+const DEFAULT_QUALITY: u8 = 85;
+
+/// Downscales, recompresses and converts the images embedded in the book.
+pub(crate) struct ImageProcessor {
+    config: Images,
+    /// Options to rasterise SVG images with, created with the system fonts
+    /// when the first SVG image is rasterised.
+    svg_options: Option<usvg::Options<'static>>,
+}
+
+impl ImageProcessor {
+    /// The processor for the configuration, `None` if it doesn't change
+    /// images.
+    pub(crate) fn new(config: &Images) -> Option<Self> {
+        let enabled = config.max_size.is_some()
+            || config.quality.is_some()
+            || config.convert
+            || config.rasterize_svg;
+        enabled.then(|| ImageProcessor {
+            config: config.clone(),
+            svg_options: None,
+        })
+    }
+
+    /// The media type an image of type `mimetype` is converted to, if any.
+    /// AVIF images can't be decoded, so they fail to convert and are embedded
+    /// as they are.
+    pub(crate) fn convert_to(&self, mimetype: &Mime) -> Result<Option<Mime>, String> {
+        match mimetype.essence_str() {
+            "image/webp" if self.config.convert => Ok(Some(mime::IMAGE_PNG)),
+            "image/svg+xml" if self.config.rasterize_svg => Ok(Some(mime::IMAGE_PNG)),
+            "image/avif" if self.config.convert => {
+                Err("AVIF images can't be decoded, convert it to PNG or JPEG".to_string())
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Process an image of type `source` into one of type `target`, which
+    /// differs if it's converted. Images which wouldn't get smaller are
+    /// returned as they are, as are animated GIFs and other types.
+    pub(crate) fn process(
+        &mut self,
+        data: &[u8],
+        source: &Mime,
+        target: &Mime,
+    ) -> Result<Vec<u8>, String> {
+        let converting = source != target;
+        match source.essence_str() {
+            "image/svg+xml" if converting => self.rasterize(data),
+            "image/jpeg" | "image/png" | "image/webp" => {
+                let image = image::load_from_memory(data).map_err(|e| e.to_string())?;
+                let too_large = self
+                    .config
+                    .max_size
+                    .is_some_and(|max| image.width() > max || image.height() > max);
+                if !converting && !too_large && self.config.quality.is_none() {
+                    return Ok(data.to_vec());
+                }
+
+                let image = match self.config.max_size {
+                    Some(max) if too_large => {
+                        image.resize(max, max, imageops::FilterType::Lanczos3)
+                    }
+                    _ => image,
+                };
+                let mut encoded = Vec::new();
+                if target.essence_str() == "image/jpeg" {
+                    let quality = self.config.quality.unwrap_or(DEFAULT_QUALITY).clamp(1, 100);
+                    JpegEncoder::new_with_quality(&mut encoded, quality)
+                        .encode_image(&image.to_rgb8())
+                        .map_err(|e| e.to_string())?;
+                } else {
+                    let encoder = PngEncoder::new_with_quality(
+                        &mut encoded,
+                        CompressionType::Best,
+                        FilterType::Adaptive,
+                    );
+                    image
+                        .write_with_encoder(encoder)
+                        .map_err(|e| e.to_string())?;
+                }
+
+                if !converting && !too_large && encoded.len() >= data.len() {
+                    Ok(data.to_vec())
+                } else {
+                    Ok(encoded)
+                }
+            }
+            _ => Ok(data.to_vec()),
+        }
+    }
+
+    /// Render an SVG image into a PNG image of its size, or `max-size` if
+    /// it's larger.
+    fn rasterize(&mut self, data: &[u8]) -> Result<Vec<u8>, String> {
+        let options = self.svg_options.get_or_insert_with(|| {
+            let mut options = usvg::Options::default();
+            options.fontdb_mut().load_system_fonts();
+            options
+        });
+        let tree = usvg::Tree::from_data(data, options).map_err(|e| e.to_string())?;
+
+        let size = tree.size();
+        let scale = match self.config.max_size {
+            Some(max) if size.width().max(size.height()) > max as f32 => {
+                max as f32 / size.width().max(size.height())
+            }
+            _ => 1.0,
+        };
+        let width = (size.width() * scale).ceil() as u32;
+        let height = (size.height() * scale).ceil() as u32;
+        let mut pixmap = tiny_skia::Pixmap::new(width, height)
+            .ok_or_else(|| format!("invalid image size {width}x{height}"))?;
+        resvg::render(
+            &tree,
+            tiny_skia::Transform::from_scale(scale, scale),
+            &mut pixmap.as_mut(),
+        );
+        pixmap.encode_png().map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use image::{ImageFormat, RgbImage};
+
+    use super::*;
+
+    /// The size of an encoded image.
+    fn dimensions(data: &[u8]) -> (u32, u32) {
+        image::ImageReader::new(Cursor::new(data))
+            .with_guessed_format()
+            .unwrap()
+            .into_dimensions()
+            .unwrap()
+    }
+
+    fn encode(width: u32, height: u32, format: ImageFormat) -> Vec<u8> {
+        let image = RgbImage::from_fn(width, height, |x, y| image::Rgb([x as u8, y as u8, 128]));
+        let mut data = Cursor::new(Vec::new());
+        image.write_to(&mut data, format).unwrap();
+        data.into_inner()
+    }
+
+    #[test]
+    fn downscale_large_images() {
+        let config = Images {
+            max_size: Some(100),
+            ..Images::default()
+        };
+        let mut processor = ImageProcessor::new(&config).unwrap();
+
+        let png = encode(400, 200, ImageFormat::Png);
+        let processed = processor
+            .process(&png, &mime::IMAGE_PNG, &mime::IMAGE_PNG)
+            .unwrap();
+        assert_eq!(dimensions(&processed), (100, 50));
+
+        let small = encode(80, 60, ImageFormat::Jpeg);
+        let processed = processor
+            .process(&small, &mime::IMAGE_JPEG, &mime::IMAGE_JPEG)
+            .unwrap();
+        assert_eq!(processed, small);
+
+        assert!(processor
+            .process(b"not an image", &mime::IMAGE_PNG, &mime::IMAGE_PNG)
+            .is_err());
+        let gif = b"GIF89a".to_vec();
+        assert_eq!(
+            processor
+                .process(&gif, &mime::IMAGE_GIF, &mime::IMAGE_GIF)
+                .unwrap(),
+            gif
+        );
+    }
+
+    #[test]
+    fn recompress_jpeg() {
+        let config = Images {
+            quality: Some(20),
+            ..Images::default()
+        };
+        let mut processor = ImageProcessor::new(&config).unwrap();
+        let jpeg = encode(300, 300, ImageFormat::Jpeg);
+        let processed = processor
+            .process(&jpeg, &mime::IMAGE_JPEG, &mime::IMAGE_JPEG)
+            .unwrap();
+        assert!(processed.len() < jpeg.len());
+        assert_eq!(dimensions(&processed), (300, 300));
+    }
+
+    #[test]
+    fn convert_images() {
+        assert!(ImageProcessor::new(&Images::default()).is_none());
+        let config = Images {
+            convert: true,
+            rasterize_svg: true,
+            max_size: Some(50),
+            ..Images::default()
+        };
+        let mut processor = ImageProcessor::new(&config).unwrap();
+        let webp: Mime = "image/webp".parse().unwrap();
+        assert_eq!(processor.convert_to(&webp), Ok(Some(mime::IMAGE_PNG)));
+        assert_eq!(processor.convert_to(&mime::IMAGE_JPEG), Ok(None));
+        let avif: Mime = "image/avif".parse().unwrap();
+        assert_eq!(
+            processor.convert_to(&avif),
+            Err("AVIF images can't be decoded, convert it to PNG or JPEG".to_string())
+        );
+
+        let processed = processor
+            .process(&encode(20, 10, ImageFormat::WebP), &webp, &mime::IMAGE_PNG)
+            .unwrap();
+        assert!(processed.starts_with(b"\x89PNG"));
+
+        let svg = b"<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"200\" height=\"100\">\
+            <rect width=\"200\" height=\"100\" fill=\"red\"/></svg>";
+        let processed = processor
+            .process(svg, &mime::IMAGE_SVG, &mime::IMAGE_PNG)
+            .unwrap();
+        assert_eq!(dimensions(&processed), (50, 25));
+    }
+}
@@ -1,5 +1,3 @@
-#![allow(clippy::result_large_err)]
-
 //! A `mdbook` backend for generating a book in the `EPUB` format.
 #[macro_use]
 extern crate log;
@@ -8,16 +6,36 @@ use mdbook::config::Config as MdConfig;
 use mdbook::renderer::RenderContext;
 use mdbook::MDBook;
 use semver::{Version, VersionReq};
-use std::fs::{create_dir_all, File};
+use std::fs::{self, create_dir_all};
 use std::path::{Path, PathBuf};
 use thiserror::Error;
 
+mod accessibility;
+mod code;
 mod config;
+mod cover;
+mod css;
+mod fonts;
+mod footnotes;
 mod generator;
+mod headings;
+mod images;
+mod links;
+mod math;
+mod metadata;
+mod package;
+mod pages;
 mod resources;
-
-pub use crate::config::Config;
+mod validation;
+mod xhtml;
+
+pub use crate::config::{
+    Accessibility, Config, Contributor, FontFace, FontObfuscation, Fonts, Footnotes,
+    GeneratedCover, Images, Landmark, Metadata, PageProgression, PartTitles, Remote, RemoteFailure,
+    Series, WritingMode,
+};
 pub use crate::generator::Generator;
+pub use crate::validation::{validate, validate_reader, Issue, Report, Severity};
 
 /// The default stylesheet used to make the rendered document pretty.
 pub const DEFAULT_CSS: &str = include_str!("master.css");
@@ -39,6 +57,23 @@ pub enum Error {
     #[error("{0}")]
     AssetFileNotFound(String),
 
+    #[error("Failed to download {url}: {reason}")]
+    RemoteAsset {
+        url: String,
+        /// The HTTP status, `None` if there was no response.
+        status: Option<u16>,
+        reason: String,
+    },
+
+    #[error("Failed to download {} remote assets:\n{}", .0.len(), list_errors(.0))]
+    RemoteAssets(Vec<Error>),
+
+    #[error("{url} isn't allowed: {reason}")]
+    DisallowedAsset { url: String, reason: String },
+
+    #[error("{0} isn't cached and can't be downloaded offline")]
+    NotCached(String),
+
     #[error("Asset was not a file {0}")]
     AssetFile(PathBuf),
 
@@ -57,6 +92,9 @@ pub enum Error {
     #[error("Error reading stylesheet")]
     StylesheetRead,
 
+    #[error("Syntax highlighting theme {0}: {1}")]
+    HighlightTheme(String, String),
+
     #[error("Epub check failed: {0}")]
     EpubCheck(String),
 
@@ -74,7 +112,20 @@ pub enum Error {
     #[error(transparent)]
     TomlDeser(#[from] toml::de::Error),
     #[error(transparent)]
-    HttpError(#[from] ureq::Error),
+    HttpError(#[from] Box<ureq::Error>),
+    #[error(transparent)]
+    Zip(#[from] zip::result::ZipError),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+}
+
+/// One error per line, for errors aggregating others.
+fn list_errors(errors: &[Error]) -> String {
+    errors
+        .iter()
+        .map(|e| format!("  {e}"))
+        .collect::<Vec<_>>()
+        .join("\n")
 }
 
 /// The exact version of `mdbook` this crate is compiled against.
@@ -112,8 +163,10 @@ pub fn generate(ctx: &RenderContext) -> Result<(), Error> {
         create_dir_all(&ctx.destination)?;
     }
 
-    let f = File::create(&outfile)?;
-    Generator::new(ctx)?.generate(f)?;
+    // the book is only written once it's generated and validated
+    let mut epub = Vec::new();
+    Generator::new(ctx)?.generate(&mut epub)?;
+    fs::write(&outfile, epub)?;
 
     Ok(())
 }
@@ -0,0 +1,218 @@
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+
+use mdbook::book::{Book, BookItem};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use pulldown_cmark::{CowStr, Event, Tag};
+use url::Url;
+
+use crate::resources::normalize_path;
+
+/// Map the source path of every chapter in the book to the XHTML file it is
+/// rendered to, both relative to the `src/` directory.
+pub(crate) fn chapter_targets(book: &Book) -> HashMap<PathBuf, PathBuf> {
+    let mut targets = HashMap::new();
+    for item in book.iter() {
+        if let BookItem::Chapter(ref ch) = *item {
+            if let Some(ref path) = ch.path {
+                targets.insert(normalize_path(path), path.with_extension("html"));
+            }
+        }
+    }
+    targets
+}
+
+/// Rewrite links between chapters, e.g. `../setup.md#install`, to point at
+/// the XHTML documents inside the EPUB.
+pub(crate) struct ChapterLinkFilter<'a> {
+    targets: &'a HashMap<PathBuf, PathBuf>,
+    /// The source path of the chapter being rendered.
+    chapter: &'a Path,
+}
+
+impl<'a> ChapterLinkFilter<'a> {
+    pub(crate) fn new(targets: &'a HashMap<PathBuf, PathBuf>, chapter: &'a Path) -> Self {
+        Self { targets, chapter }
+    }
+
+    pub(crate) fn apply(&self, event: Event<'a>) -> Event<'a> {
+        match event {
+            Event::Start(Tag::Link {
+                link_type,
+                ref dest_url,
+                ref title,
+                ref id,
+            }) => match self.resolve(dest_url) {
+                Some(href) => Event::Start(Tag::Link {
+                    link_type,
+                    dest_url: CowStr::from(href),
+                    title: title.to_owned(),
+                    id: id.to_owned(),
+                }),
+                None => event,
+            },
+            _ => event,
+        }
+    }
+
+    /// Find the XHTML href for a relative link to a markdown file, if it's a
+    /// chapter of the book.
+    fn resolve(&self, link: &str) -> Option<String> {
+        if link.starts_with('#') || link.starts_with('/') || Url::parse(link).is_ok() {
+            return None;
+        }
+        let (path, fragment) = match link.split_once('#') {
+            Some((path, fragment)) => (path, Some(fragment)),
+            None => (link, None),
+        };
+        if !path.ends_with(".md") {
+            return None;
+        }
+
+        let chapter_dir = self.chapter.parent().unwrap_or_else(|| Path::new(""));
+        let path = percent_decode_str(path).decode_utf8_lossy();
+        let linked = normalize_path(&chapter_dir.join(path.as_ref()));
+        match self.targets.get(&linked) {
+            Some(target) => {
+                let mut href =
+                    utf8_percent_encode(&relative_href(chapter_dir, target), PATH).to_string();
+                if let Some(fragment) = fragment {
+                    href.push('#');
+                    href.push_str(fragment);
+                }
+                trace!("Rewrite chapter link {} to {}", link, href);
+                Some(href)
+            }
+            None => {
+                warn!(
+                    "Chapter {:?} links to \"{}\" which is not part of the book",
+                    self.chapter, link
+                );
+                None
+            }
+        }
+    }
+}
+
+/// The characters escaped in the path of a chapter link: all but the
+/// unreserved and sub-delimiter characters, `:`, `@` and `/`.
+const PATH: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~')
+    .remove(b'!')
+    .remove(b'$')
+    .remove(b'&')
+    .remove(b'\'')
+    .remove(b'(')
+    .remove(b')')
+    .remove(b'*')
+    .remove(b'+')
+    .remove(b',')
+    .remove(b';')
+    .remove(b'=')
+    .remove(b':')
+    .remove(b'@')
+    .remove(b'/');
+
+/// Build a forward slash separated href to `target` from the `from` directory,
+/// where both are relative to the same root.
+fn relative_href(from: &Path, target: &Path) -> String {
+    let from = normalize_path(from);
+    let from: Vec<Component> = from.components().collect();
+    let target: Vec<Component> = target.components().collect();
+    let common = from
+        .iter()
+        .zip(target.iter())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    from[common..]
+        .iter()
+        .map(|_| "..".to_string())
+        .chain(
+            target[common..]
+                .iter()
+                .map(|c| c.as_os_str().to_string_lossy().to_string()),
+        )
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use mdbook::utils::new_cmark_parser;
+    use pulldown_cmark::html;
+
+    use super::*;
+
+    fn render(content: &str, chapter: &str) -> String {
+        let targets = HashMap::from([
+            (
+                PathBuf::from("chapter_1.md"),
+                PathBuf::from("chapter_1.html"),
+            ),
+            (
+                PathBuf::from("02_advanced/setup.md"),
+                PathBuf::from("02_advanced/setup.html"),
+            ),
+            (
+                PathBuf::from("02_advanced/README.md"),
+                PathBuf::from("02_advanced/README.html"),
+            ),
+            (
+                PathBuf::from("my chapter.md"),
+                PathBuf::from("my chapter.html"),
+            ),
+        ]);
+        let filter = ChapterLinkFilter::new(&targets, Path::new(chapter));
+        let mut buf = String::new();
+        html::push_html(
+            &mut buf,
+            new_cmark_parser(content, false).map(|ev| filter.apply(ev)),
+        );
+        buf
+    }
+
+    #[test]
+    fn rewrite_links_between_chapters() {
+        let content = "[setup](../02_advanced/setup.md#install) [back](../chapter_1.md) \
+            [sibling](./README.md)";
+        assert_eq!(
+            render(content, "02_advanced/setup.md"),
+            "<p><a href=\"setup.html#install\">setup</a> \
+            <a href=\"../chapter_1.html\">back</a> \
+            <a href=\"README.html\">sibling</a></p>\n"
+        );
+        assert_eq!(
+            render("[setup](02_advanced/setup.md#install)", "chapter_1.md"),
+            "<p><a href=\"02_advanced/setup.html#install\">setup</a></p>\n"
+        );
+    }
+
+    #[test]
+    fn keep_links_not_in_book() {
+        let content = "[missing](missing.md#top) [web](https://rust-lang.org/index.md) \
+            [anchor](#top) [file](notes.txt)";
+        assert_eq!(
+            render(content, "chapter_1.md"),
+            "<p><a href=\"missing.md#top\">missing</a> \
+            <a href=\"https://rust-lang.org/index.md\">web</a> \
+            <a href=\"#top\">anchor</a> \
+            <a href=\"notes.txt\">file</a></p>\n"
+        );
+    }
+
+    #[test]
+    fn rewrite_percent_encoded_links() {
+        assert_eq!(
+            render(
+                "[x](../my%20chapter.md#top) [y](<../my chapter.md>)",
+                "02_advanced/setup.md"
+            ),
+            "<p><a href=\"../my%20chapter.html#top\">x</a> \
+            <a href=\"../my%20chapter.html\">y</a></p>\n"
+        );
+    }
+}
@@ -0,0 +1,312 @@
+use std::collections::HashMap;
+use std::process::Command;
+
+use handlebars::html_escape;
+use latex2mathml::{latex_to_mathml, DisplayStyle};
+use pulldown_cmark::{CowStr, Event, Tag, TagEnd};
+
+/// The delimiters of MathJax in mdBook: `\\(` and `\\)` in Markdown for
+/// inline math, `\\[` and `\\]` or `$$` for display math.
+const DELIMITERS: [(&str, &str, bool); 3] = [
+    ("$$", "$$", true),
+    ("\\[", "\\]", true),
+    ("\\(", "\\)", false),
+];
+
+/// Directory of the SVG images of formulas in the EPUB.
+const IMAGE_DIR: &str = "math";
+
+/// What LaTeX math is converted to.
+#[derive(Debug)]
+pub(crate) enum MathOutput {
+    /// MathML elements, for EPUB 3.
+    MathMl,
+    /// SVG images rendered by an external command, for EPUB 2 which has no
+    /// MathML support.
+    Svg { command: String },
+}
+
+/// Converts the LaTeX math of chapters, which mdBook leaves to MathJax in the
+/// browser.
+#[derive(Debug)]
+pub(crate) struct MathRenderer {
+    output: MathOutput,
+    /// The rendered images by their formula and whether it's display math.
+    images: HashMap<(String, bool), String>,
+    /// Paths and contents of the rendered images, in rendering order.
+    files: Vec<(String, Vec<u8>)>,
+}
+
+impl MathRenderer {
+    pub(crate) fn new(output: MathOutput) -> Self {
+        MathRenderer {
+            output,
+            images: HashMap::new(),
+            files: Vec::new(),
+        }
+    }
+
+    /// Take the SVG images rendered so far, with their paths in the EPUB.
+    pub(crate) fn take_images(&mut self) -> Vec<(String, Vec<u8>)> {
+        std::mem::take(&mut self.files)
+    }
+
+    /// Replace the math in the text of a chapter with MathML or images.
+    /// `to_root` makes an EPUB path relative to the chapter. Also returns
+    /// whether there's MathML in the chapter, which the package document
+    /// has to declare.
+    pub(crate) fn render<'a, I, F>(&mut self, events: I, to_root: F) -> (Vec<Event<'a>>, bool)
+    where
+        I: Iterator<Item = Event<'a>>,
+        F: Fn(&str) -> String,
+    {
+        let mut rendered = Vec::new();
+        let mut text = String::new();
+        let mut has_mathml = false;
+        let mut in_code_block = false;
+
+        for event in events {
+            match event {
+                // formulas are split into several events by escapes and line breaks
+                Event::Text(ref t) if !in_code_block => text.push_str(t),
+                Event::SoftBreak if !in_code_block => text.push('\n'),
+                event => {
+                    has_mathml |= self.flush(&mut text, &mut rendered, &to_root);
+                    match event {
+                        Event::Start(Tag::CodeBlock(_)) => in_code_block = true,
+                        Event::End(TagEnd::CodeBlock) => in_code_block = false,
+                        _ => {}
+                    }
+                    rendered.push(event);
+                }
+            }
+        }
+        has_mathml |= self.flush(&mut text, &mut rendered, &to_root);
+
+        (rendered, has_mathml)
+    }
+
+    /// Convert the math in the collected `text` into events.
+    fn flush<'a, F>(&mut self, text: &mut String, events: &mut Vec<Event<'a>>, to_root: &F) -> bool
+    where
+        F: Fn(&str) -> String,
+    {
+        let mut has_mathml = false;
+        let mut rest = text.as_str();
+
+        while let Some((start, (open, close, display))) = DELIMITERS
+            .iter()
+            .filter_map(|&delimiters| rest.find(delimiters.0).map(|i| (i, delimiters)))
+            .min_by_key(|&(i, _)| i)
+        {
+            let latex_start = start + open.len();
+            let Some(len) = rest[latex_start..].find(close) else {
+                break;
+            };
+            let latex = &rest[latex_start..latex_start + len];
+            let end = latex_start + len + close.len();
+
+            let html = match self.output {
+                MathOutput::MathMl => mathml(latex, display).inspect(|_| has_mathml = true),
+                MathOutput::Svg { .. } => self.image(latex, display).map(|path| {
+                    let class = if display {
+                        "math-display"
+                    } else {
+                        "math-inline"
+                    };
+                    format!(
+                        "<img class=\"{}\" src=\"{}\" alt=\"{}\" />",
+                        class,
+                        html_escape(&to_root(&path)),
+                        html_escape(latex.trim())
+                    )
+                }),
+            };
+            match html {
+                Some(html) => {
+                    if start > 0 {
+                        events.push(Event::Text(CowStr::from(rest[..start].to_string())));
+                    }
+                    events.push(Event::InlineHtml(CowStr::from(html)));
+                }
+                None => events.push(Event::Text(CowStr::from(rest[..end].to_string()))),
+            }
+            rest = &rest[end..];
+        }
+
+        if !rest.is_empty() {
+            events.push(Event::Text(CowStr::from(rest.to_string())));
+        }
+        text.clear();
+        has_mathml
+    }
+
+    /// Render a formula with the SVG command, returning the image's path.
+    fn image(&mut self, latex: &str, display: bool) -> Option<String> {
+        let MathOutput::Svg { ref command } = self.output else {
+            return None;
+        };
+        let key = (latex.trim().to_string(), display);
+        if let Some(path) = self.images.get(&key) {
+            return Some(path.clone());
+        }
+
+        let mut args = command.split_whitespace();
+        let program = args.next()?;
+        let mut cmd = Command::new(program);
+        cmd.args(args);
+        if !display {
+            cmd.arg("--inline");
+        }
+        let svg = match cmd.arg(&key.0).output() {
+            Ok(output) if output.status.success() && output.stdout.starts_with(b"<") => {
+                output.stdout
+            }
+            Ok(output) => {
+                warn!(
+                    "Failed to render math \"{}\" with `{}`: {}",
+                    key.0,
+                    command,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                );
+                return None;
+            }
+            Err(e) => {
+                warn!(
+                    "Failed to render math \"{}\" with `{}`: {}",
+                    key.0, command, e
+                );
+                return None;
+            }
+        };
+
+        let path = format!("{}/eq-{}.svg", IMAGE_DIR, self.files.len() + 1);
+        self.files.push((path.clone(), svg));
+        self.images.insert(key, path.clone());
+        Some(path)
+    }
+}
+
+/// Convert a formula to a MathML `<math>` element, keeping the LaTeX source
+/// as its `alttext` for screen readers.
+fn mathml(latex: &str, display: bool) -> Option<String> {
+    let style = if display {
+        DisplayStyle::Block
+    } else {
+        DisplayStyle::Inline
+    };
+    let math = match latex_to_mathml(latex, style) {
+        Ok(math) if !math.contains("[PARSE ERROR") => math,
+        Ok(_) => {
+            warn!("Failed to convert math \"{}\" to MathML", latex.trim());
+            return None;
+        }
+        Err(e) => {
+            warn!(
+                "Failed to convert math \"{}\" to MathML: {}",
+                latex.trim(),
+                e
+            );
+            return None;
+        }
+    };
+    let math = escape_operators(&math);
+    let alttext = format!(" alttext=\"{}\"", html_escape(latex.trim()));
+    Some(math.replacen("<math", &format!("<math{alttext}"), 1))
+}
+
+/// Escape the `<` and `&` operators latex2mathml leaves as they are in its
+/// output, like `<mo><</mo>`.
+fn escape_operators(mathml: &str) -> String {
+    let mut escaped = String::with_capacity(mathml.len());
+    let mut chars = mathml.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '<' if !matches!(chars.peek(), Some(next) if next.is_ascii_alphabetic() || *next == '/') => {
+                escaped.push_str("&lt;")
+            }
+            '&' => escaped.push_str("&amp;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use mdbook::utils::new_cmark_parser;
+    use pulldown_cmark::html;
+
+    use super::*;
+
+    fn render(content: &str, renderer: &mut MathRenderer) -> (String, bool) {
+        let (events, has_mathml) = renderer.render(new_cmark_parser(content, false), |path| {
+            format!("../{path}")
+        });
+        let mut buf = String::new();
+        html::push_html(&mut buf, events.into_iter());
+        (buf, has_mathml)
+    }
+
+    #[test]
+    fn convert_math_to_mathml() {
+        let mut renderer = MathRenderer::new(MathOutput::MathMl);
+        let content = "Where \\\\( a < b \\\\) holds:\n$$\nx^2\n$$\n\n\
+            ```\n$$ not math $$\n```\n\n`\\\\( code \\\\)` and \\\\( unclosed";
+        let (rendered, has_mathml) = render(content, &mut renderer);
+
+        assert!(has_mathml);
+        assert_eq!(
+            rendered,
+            "<p>Where <math alttext=\"a &lt; b\" xmlns=\"http://www.w3.org/1998/Math/MathML\" \
+            display=\"inline\"><mi>a</mi><mo>&lt;</mo><mi>b</mi></math> holds:\n\
+            <math alttext=\"x^2\" xmlns=\"http://www.w3.org/1998/Math/MathML\" display=\"block\">\
+            <msup><mi>x</mi><mn>2</mn></msup></math></p>\n\
+            <pre><code>$$ not math $$\n</code></pre>\n\
+            <p><code>\\\\( code \\\\)</code> and \\( unclosed</p>\n"
+        );
+
+        let (rendered, has_mathml) = render("No math costs $$5.", &mut renderer);
+        assert!(!has_mathml);
+        assert_eq!(rendered, "<p>No math costs $$5.</p>\n");
+    }
+
+    #[test]
+    fn keep_invalid_math() {
+        let mut renderer = MathRenderer::new(MathOutput::MathMl);
+        let (rendered, has_mathml) = render("\\\\[ \\frac{1 \\\\]", &mut renderer);
+        assert!(!has_mathml);
+        assert_eq!(rendered, "<p>\\[ \\frac{1 \\]</p>\n");
+    }
+
+    #[test]
+    fn missing_svg_command() {
+        let mut renderer = MathRenderer::new(MathOutput::Svg {
+            command: "mdbook-epub-no-such-command".to_string(),
+        });
+        let (rendered, _) = render("\\\\( x \\\\)", &mut renderer);
+        assert_eq!(rendered, "<p>\\( x \\)</p>\n");
+        assert!(renderer.take_images().is_empty());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn render_svg_images() {
+        // `echo` stands in for tex2svg
+        let mut renderer = MathRenderer::new(MathOutput::Svg {
+            command: "echo <svg/>".to_string(),
+        });
+        let (rendered, has_mathml) = render("\\\\( x \\\\) $$y$$ \\\\(x\\\\)", &mut renderer);
+        assert!(!has_mathml);
+        assert_eq!(
+            rendered,
+            "<p><img class=\"math-inline\" src=\"../math/eq-1.svg\" alt=\"x\" /> \
+            <img class=\"math-display\" src=\"../math/eq-2.svg\" alt=\"y\" /> \
+            <img class=\"math-inline\" src=\"../math/eq-1.svg\" alt=\"x\" /></p>\n"
+        );
+        let images = renderer.take_images();
+        assert_eq!(images.len(), 2);
+        assert_eq!(images[0].0, "math/eq-1.svg");
+        assert_eq!(images[1].1, b"<svg/> y\n");
+    }
+}
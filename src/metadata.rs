@@ -0,0 +1,269 @@
+use epub_builder::EpubVersion;
+
+use crate::config::{Contributor, Metadata};
+use crate::package::xml_escape;
+
+/// Render the `[output.epub.metadata]` entries `epub-builder` doesn't support
+/// as elements of the package document's `<metadata>`.
+///
+/// `authors` are the `book.authors`, used unless the metadata lists authors
+/// of its own.
+pub(crate) fn elements(meta: &Metadata, authors: &[String], version: EpubVersion) -> Vec<String> {
+    let mut elements = Vec::new();
+
+    let book_authors: Vec<Contributor>;
+    let authors = if meta.authors.is_empty() {
+        book_authors = authors
+            .iter()
+            .map(|name| Contributor {
+                name: name.clone(),
+                file_as: None,
+                role: None,
+            })
+            .collect();
+        &book_authors
+    } else {
+        &meta.authors
+    };
+    for (i, author) in authors.iter().enumerate() {
+        let id = format!("creator-{}", i + 1);
+        elements.extend(person("dc:creator", &id, author, "aut", version));
+    }
+    for (i, contributor) in meta.contributors.iter().enumerate() {
+        let id = format!("contributor-{}", i + 1);
+        elements.extend(person("dc:contributor", &id, contributor, "ctb", version));
+    }
+
+    if let Some(ref publisher) = meta.publisher {
+        elements.push(format!(
+            "<dc:publisher>{}</dc:publisher>",
+            xml_escape(publisher)
+        ));
+    }
+    if let Some(ref date) = meta.date {
+        elements.push(format!("<dc:date>{}</dc:date>", xml_escape(date)));
+    }
+
+    if let Some(ref series) = meta.series {
+        let name = xml_escape(&series.name);
+        if version == EpubVersion::V30 {
+            elements.push(format!(
+                "<meta property=\"belongs-to-collection\" id=\"collection-1\">{name}</meta>"
+            ));
+            elements.push(
+                "<meta refines=\"#collection-1\" property=\"collection-type\">series</meta>"
+                    .to_string(),
+            );
+            if let Some(position) = series.position {
+                elements.push(format!(
+                    "<meta refines=\"#collection-1\" property=\"group-position\">{position}</meta>"
+                ));
+            }
+        }
+        // calibre's own series metadata, understood by many reading systems
+        elements.push(format!(
+            "<meta name=\"calibre:series\" content=\"{name}\"/>"
+        ));
+        if let Some(position) = series.position {
+            elements.push(format!(
+                "<meta name=\"calibre:series_index\" content=\"{position}\"/>"
+            ));
+        }
+    }
+
+    elements
+}
+
+/// The `dc:creator` or `dc:contributor` element of a person with its role and
+/// sort name.
+fn person(
+    element: &str,
+    id: &str,
+    person: &Contributor,
+    default_role: &str,
+    version: EpubVersion,
+) -> Vec<String> {
+    let name = xml_escape(&person.name);
+    let role = person
+        .role
+        .as_deref()
+        .map(marc_relator)
+        .unwrap_or_else(|| default_role.to_string());
+    match version {
+        EpubVersion::V30 => {
+            let mut elements = vec![
+                format!("<{element} id=\"{id}\">{name}</{element}>"),
+                format!(
+                    "<meta refines=\"#{id}\" property=\"role\" scheme=\"marc:relators\">{role}</meta>"
+                ),
+            ];
+            if let Some(ref file_as) = person.file_as {
+                elements.push(format!(
+                    "<meta refines=\"#{id}\" property=\"file-as\">{}</meta>",
+                    xml_escape(file_as)
+                ));
+            }
+            elements
+        }
+        _ => {
+            let file_as = match person.file_as {
+                Some(ref file_as) => format!(" opf:file-as=\"{}\"", xml_escape(file_as)),
+                None => String::new(),
+            };
+            vec![format!(
+                "<{element} opf:role=\"{role}\"{file_as}>{name}</{element}>"
+            )]
+        }
+    }
+}
+
+/// Translate a role name into its MARC relator code, see
+/// <https://www.loc.gov/marc/relators/relaterm.html>.
+fn marc_relator(role: &str) -> String {
+    let lowercase = role.to_lowercase();
+    let code = match lowercase.as_str() {
+        "author" => "aut",
+        "editor" => "edt",
+        "illustrator" => "ill",
+        "translator" => "trl",
+        "contributor" => "ctb",
+        "narrator" => "nrt",
+        "photographer" => "pht",
+        "designer" => "dsr",
+        "publisher" => "pbl",
+        "reviewer" => "rev",
+        code if code.len() == 3 && code.chars().all(|c| c.is_ascii_lowercase()) => code,
+        _ => {
+            warn!(
+                "Unknown contributor role \"{}\", using \"ctb\" instead",
+                role
+            );
+            "ctb"
+        }
+    };
+    code.to_string()
+}
+
+/// Turn the configured identifier into a URN if it's a bare ISBN or UUID.
+pub(crate) fn identifier_urn(identifier: &str) -> String {
+    let identifier = identifier.trim();
+    if identifier.contains(':') {
+        return identifier.to_string();
+    }
+    let isbn: String = identifier
+        .chars()
+        .filter(|c| *c != '-' && *c != ' ')
+        .collect();
+    let is_isbn = match isbn.len() {
+        13 => isbn.chars().all(|c| c.is_ascii_digit()),
+        10 => {
+            isbn[..9].chars().all(|c| c.is_ascii_digit())
+                && isbn[9..].chars().all(|c| c.is_ascii_digit() || c == 'X')
+        }
+        _ => false,
+    };
+    let is_uuid = identifier.len() == 36
+        && identifier.char_indices().all(|(i, c)| match i {
+            8 | 13 | 18 | 23 => c == '-',
+            _ => c.is_ascii_hexdigit(),
+        });
+    if is_isbn {
+        format!("urn:isbn:{isbn}")
+    } else if is_uuid {
+        format!("urn:uuid:{}", identifier.to_lowercase())
+    } else {
+        identifier.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::Series;
+
+    use super::*;
+
+    fn metadata() -> Metadata {
+        Metadata {
+            publisher: Some("Acme & Co".to_string()),
+            date: Some("2024-05-01".to_string()),
+            contributors: vec![Contributor {
+                name: "John Roe".to_string(),
+                file_as: Some("Roe, John".to_string()),
+                role: Some("translator".to_string()),
+            }],
+            series: Some(Series {
+                name: "Manuals".to_string(),
+                position: Some(2),
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn epub3_metadata() {
+        let authors = vec!["Jane Doe".to_string(), "Max Mustermann".to_string()];
+        let got = elements(&metadata(), &authors, EpubVersion::V30);
+        assert_eq!(
+            got,
+            vec![
+                "<dc:creator id=\"creator-1\">Jane Doe</dc:creator>",
+                "<meta refines=\"#creator-1\" property=\"role\" scheme=\"marc:relators\">aut</meta>",
+                "<dc:creator id=\"creator-2\">Max Mustermann</dc:creator>",
+                "<meta refines=\"#creator-2\" property=\"role\" scheme=\"marc:relators\">aut</meta>",
+                "<dc:contributor id=\"contributor-1\">John Roe</dc:contributor>",
+                "<meta refines=\"#contributor-1\" property=\"role\" scheme=\"marc:relators\">trl</meta>",
+                "<meta refines=\"#contributor-1\" property=\"file-as\">Roe, John</meta>",
+                "<dc:publisher>Acme &amp; Co</dc:publisher>",
+                "<dc:date>2024-05-01</dc:date>",
+                "<meta property=\"belongs-to-collection\" id=\"collection-1\">Manuals</meta>",
+                "<meta refines=\"#collection-1\" property=\"collection-type\">series</meta>",
+                "<meta refines=\"#collection-1\" property=\"group-position\">2</meta>",
+                "<meta name=\"calibre:series\" content=\"Manuals\"/>",
+                "<meta name=\"calibre:series_index\" content=\"2\"/>",
+            ]
+        );
+    }
+
+    #[test]
+    fn epub2_metadata() {
+        let mut meta = metadata();
+        meta.authors = vec![Contributor {
+            name: "Jane Doe".to_string(),
+            file_as: Some("Doe, Jane".to_string()),
+            role: None,
+        }];
+        let got = elements(&meta, &["Ignored".to_string()], EpubVersion::V20);
+        assert_eq!(
+            &got[..2],
+            [
+                "<dc:creator opf:role=\"aut\" opf:file-as=\"Doe, Jane\">Jane Doe</dc:creator>",
+                "<dc:contributor opf:role=\"trl\" opf:file-as=\"Roe, John\">John Roe</dc:contributor>",
+            ]
+        );
+        assert!(!got.iter().any(|e| e.contains("belongs-to-collection")));
+    }
+
+    #[test]
+    fn relator_codes() {
+        assert_eq!(marc_relator("Illustrator"), "ill");
+        assert_eq!(marc_relator("aut"), "aut");
+        assert_eq!(marc_relator("AUT"), "aut");
+        assert_eq!(marc_relator("Trl"), "trl");
+        assert_eq!(marc_relator("ghostwriter"), "ctb");
+    }
+
+    #[test]
+    fn identifiers() {
+        assert_eq!(
+            identifier_urn("978-3-16-148410-0"),
+            "urn:isbn:9783161484100"
+        );
+        assert_eq!(identifier_urn("0-306-40615-X"), "urn:isbn:030640615X");
+        assert_eq!(
+            identifier_urn("67E55044-10B1-426F-9247-BB680E5FE0C8"),
+            "urn:uuid:67e55044-10b1-426f-9247-bb680e5fe0c8"
+        );
+        assert_eq!(identifier_urn("urn:doi:10.1000/182"), "urn:doi:10.1000/182");
+        assert_eq!(identifier_urn("my-handbook"), "my-handbook");
+    }
+}
@@ -0,0 +1,510 @@
+use std::cmp::Reverse;
+use std::io::{Cursor, Read, Write};
+use std::ops::Range;
+
+use roxmltree::{Document, Node, ParsingOptions};
+
+use zip::write::SimpleFileOptions;
+use zip::{DateTime, ZipArchive, ZipWriter};
+
+use crate::config::FontObfuscation;
+use crate::fonts;
+use crate::Error;
+
+/// Path of the package document in the EPUB written by `epub-builder`.
+const PACKAGE_DOCUMENT: &str = "OEBPS/content.opf";
+/// Path of the EPUB 3 navigation document.
+const NAVIGATION_DOCUMENT: &str = "OEBPS/nav.xhtml";
+/// Path of the NCX, the navigation document of EPUB 2.
+const NCX: &str = "OEBPS/toc.ncx";
+/// Path of the document declaring the obfuscated fonts.
+const ENCRYPTION: &str = "META-INF/encryption.xml";
+/// Namespace of the `epub:type` attribute.
+const OPS_NS: &str = "http://www.idpf.org/2007/ops";
+
+/// Changes to the package document `epub-builder` has no API for, applied to
+/// the finished EPUB.
+#[derive(Debug, Default)]
+pub(crate) struct Package {
+    /// Replaces the random `urn:uuid:` identifier of the book.
+    pub(crate) identifier: Option<String>,
+    /// Additional elements of the package document's `<metadata>`.
+    pub(crate) metadata: Vec<String>,
+    /// Hrefs of the content documents with MathML, which EPUB 3 requires to
+    /// be declared with the `mathml` property.
+    pub(crate) mathml: Vec<String>,
+    /// Landmarks `epub-builder` has no content document type for, as their
+    /// `epub:type`, href and title.
+    pub(crate) landmarks: Vec<(&'static str, String, String)>,
+    /// The page breaks of the print edition as label and href, listed in the
+    /// page list of the navigation documents.
+    pub(crate) pages: Vec<(String, String)>,
+    /// Paths of the fonts to obfuscate in the EPUB, with the algorithm.
+    pub(crate) obfuscated: Vec<(String, FontObfuscation)>,
+    /// The modification time of every file in a reproducible build.
+    pub(crate) timestamp: Option<DateTime>,
+}
+
+impl Package {
+    /// Copy the EPUB to `writer`, patching the package document on the way.
+    pub(crate) fn write<W: Write>(&self, epub: &[u8], mut writer: W) -> Result<(), Error> {
+        if self.identifier.is_none()
+            && self.metadata.is_empty()
+            && self.mathml.is_empty()
+            && self.landmarks.is_empty()
+            && self.pages.is_empty()
+            && self.obfuscated.is_empty()
+            && self.timestamp.is_none()
+        {
+            writer.write_all(epub)?;
+            return Ok(());
+        }
+
+        let mut archive = ZipArchive::new(Cursor::new(epub))?;
+        let identifier = if self.obfuscated.is_empty() {
+            String::new()
+        } else {
+            let mut opf = String::new();
+            archive
+                .by_name(PACKAGE_DOCUMENT)?
+                .read_to_string(&mut opf)?;
+            unique_identifier(&self.patch(&opf)?).ok_or_else(|| {
+                Error::EpubDocCreate("The package document has no identifier".to_string())
+            })?
+        };
+
+        let mut output = ZipWriter::new(Cursor::new(Vec::new()));
+        for i in 0..archive.len() {
+            let mut file = archive.by_index(i)?;
+            let patch = match file.name() {
+                PACKAGE_DOCUMENT => Some(Self::patch as fn(&Self, &str) -> Result<String, Error>),
+                NAVIGATION_DOCUMENT => Some(Self::patch_navigation as _),
+                NCX => Some(Self::patch_ncx as _),
+                _ => None,
+            };
+            let obfuscation = self
+                .obfuscated
+                .iter()
+                .find(|(path, _)| path == file.name())
+                .map(|&(_, obfuscation)| obfuscation);
+            if patch.is_none() && obfuscation.is_none() && self.timestamp.is_none() {
+                output.raw_copy_file(file)?;
+                continue;
+            }
+
+            let mut content = Vec::new();
+            file.read_to_end(&mut content)?;
+            if let Some(patch) = patch {
+                content = patch(self, &String::from_utf8_lossy(&content))?.into_bytes();
+            }
+            if let Some(obfuscation) = obfuscation {
+                let key = fonts::obfuscation_key(&identifier, obfuscation)?;
+                fonts::obfuscate(&mut content, &key, obfuscation);
+            }
+            let mut options = SimpleFileOptions::default().compression_method(file.compression());
+            if let Some(time) = self.timestamp.or_else(|| file.last_modified()) {
+                options = options.last_modified_time(time);
+            }
+            if self.timestamp.is_some() {
+                options = options.unix_permissions(0o644);
+            }
+            output.start_file(file.name(), options)?;
+            output.write_all(&content)?;
+        }
+
+        if !self.obfuscated.is_empty() {
+            let mut options = SimpleFileOptions::default();
+            if let Some(time) = self.timestamp {
+                options = options.last_modified_time(time).unix_permissions(0o644);
+            }
+            output.start_file(ENCRYPTION, options)?;
+            output.write_all(fonts::encryption_xml(&self.obfuscated).as_bytes())?;
+        }
+
+        writer.write_all(&output.finish()?.into_inner())?;
+        Ok(())
+    }
+
+    /// Apply the changes to the package document's source.
+    fn patch(&self, opf: &str) -> Result<String, Error> {
+        let doc = parse(opf, PACKAGE_DOCUMENT)?;
+        let mut edits = Edits::default();
+
+        if let Some(ref identifier) = self.identifier {
+            let content = identifier_element(&doc)
+                .and_then(|node| content_range(opf, node))
+                .ok_or_else(|| missing(PACKAGE_DOCUMENT, "unique identifier"))?;
+            edits.replace(content, xml_escape(identifier));
+        }
+
+        if !self.metadata.is_empty() {
+            let end = element(&doc, "metadata")
+                .and_then(|node| before_end_tag(opf, node))
+                .ok_or_else(|| missing(PACKAGE_DOCUMENT, "metadata"))?;
+            let elements: String = self
+                .metadata
+                .iter()
+                .map(|element| format!("    {element}\n"))
+                .collect();
+            edits.insert(end, elements);
+        }
+
+        let references: String = self
+            .landmarks
+            .iter()
+            .filter(|(kind, _, _)| *kind == "cover")
+            .map(|(kind, href, title)| {
+                format!(
+                    "    <reference type=\"{kind}\" title=\"{}\" href=\"{}\"/>\n",
+                    xml_escape(title),
+                    xml_escape(href)
+                )
+            })
+            .collect();
+        if !references.is_empty() {
+            let end = element(&doc, "guide")
+                .and_then(|node| before_end_tag(opf, node))
+                .ok_or_else(|| missing(PACKAGE_DOCUMENT, "guide"))?;
+            edits.insert(end, references);
+        }
+
+        for href in &self.mathml {
+            let item = doc
+                .descendants()
+                .find(|n| n.has_tag_name("item") && n.attribute("href") == Some(href.as_str()))
+                .ok_or_else(|| missing(PACKAGE_DOCUMENT, &format!("manifest item for {href}")))?;
+            match item.attributes().find(|a| a.name() == "properties") {
+                Some(properties) => edits.replace(
+                    properties.range_value(),
+                    xml_escape(&format!("{} mathml", properties.value())),
+                ),
+                None => edits.insert(name_end(opf, item), " properties=\"mathml\"".to_string()),
+            }
+        }
+
+        Ok(edits.apply(opf))
+    }
+}
+
+impl Package {
+    /// Add the landmarks and the page list to the EPUB 3 navigation document.
+    fn patch_navigation(&self, nav: &str) -> Result<String, Error> {
+        if self.landmarks.is_empty() && self.pages.is_empty() {
+            return Ok(nav.to_string());
+        }
+        let doc = parse(nav, NAVIGATION_DOCUMENT)?;
+        // the table of contents page of EPUB 2 isn't a navigation document
+        if element(&doc, "nav").is_none() {
+            return Ok(nav.to_string());
+        }
+        let mut edits = Edits::default();
+
+        if !self.landmarks.is_empty() {
+            let landmarks = doc
+                .descendants()
+                .find(|n| {
+                    n.has_tag_name("nav") && n.attribute((OPS_NS, "type")) == Some("landmarks")
+                })
+                .ok_or_else(|| missing(NAVIGATION_DOCUMENT, "landmarks"))?;
+            let items: String = self
+                .landmarks
+                .iter()
+                .map(|(kind, href, title)| {
+                    format!(
+                        "\n      <li><a epub:type=\"{kind}\" href=\"{}\">{}</a></li>",
+                        xml_escape(href),
+                        xml_escape(title)
+                    )
+                })
+                .collect();
+            match landmarks.children().find(|n| n.has_tag_name("ol")) {
+                Some(ol) => edits.insert(start_tag_end(nav, ol), items),
+                None => {
+                    let end = before_end_tag(nav, landmarks)
+                        .ok_or_else(|| missing(NAVIGATION_DOCUMENT, "landmarks"))?;
+                    edits.insert(end, format!("    <ol>{items}\n    </ol>\n"));
+                }
+            }
+        }
+
+        if !self.pages.is_empty() {
+            let end = element(&doc, "body")
+                .and_then(|node| before_end_tag(nav, node))
+                .ok_or_else(|| missing(NAVIGATION_DOCUMENT, "body"))?;
+            let mut page_list =
+                String::from("  <nav epub:type=\"page-list\" hidden=\"hidden\">\n    <ol>\n");
+            for (label, href) in &self.pages {
+                page_list.push_str(&format!(
+                    "      <li><a href=\"{}\">{}</a></li>\n",
+                    xml_escape(href),
+                    xml_escape(label)
+                ));
+            }
+            page_list.push_str("    </ol>\n  </nav>\n");
+            edits.insert(end, page_list);
+        }
+
+        Ok(edits.apply(nav))
+    }
+
+    /// Add the page list to the NCX.
+    fn patch_ncx(&self, ncx: &str) -> Result<String, Error> {
+        if self.pages.is_empty() {
+            return Ok(ncx.to_string());
+        }
+        let doc = parse(ncx, NCX)?;
+        let mut edits = Edits::default();
+
+        let max_page = self
+            .pages
+            .iter()
+            .filter_map(|(label, _)| label.parse::<u32>().ok())
+            .max()
+            .unwrap_or(0);
+        for (name, value) in [
+            ("dtb:totalPageCount", self.pages.len() as u32),
+            ("dtb:maxPageNumber", max_page),
+        ] {
+            let content = doc
+                .descendants()
+                .find(|n| n.has_tag_name("meta") && n.attribute("name") == Some(name))
+                .and_then(|meta| meta.attributes().find(|a| a.name() == "content"))
+                .ok_or_else(|| missing(NCX, name))?;
+            edits.replace(content.range_value(), value.to_string());
+        }
+
+        // page targets continue the play order of the navigation points
+        let nav_points = doc
+            .descendants()
+            .filter(|n| n.has_tag_name("navPoint"))
+            .count();
+        let mut page_list =
+            String::from("  <pageList>\n    <navLabel><text>Pages</text></navLabel>\n");
+        for (i, (label, href)) in self.pages.iter().enumerate() {
+            // only page numbers have a value
+            let (kind, value) = match label.parse::<u32>() {
+                Ok(number) => ("normal", format!(" value=\"{number}\"")),
+                Err(_) => ("front", String::new()),
+            };
+            page_list.push_str(&format!(
+                "    <pageTarget id=\"pageTarget-{n}\" type=\"{kind}\"{value} \
+                playOrder=\"{play_order}\">\n      <navLabel><text>{label}</text></navLabel>\n      \
+                <content src=\"{href}\"/>\n    </pageTarget>\n",
+                n = i + 1,
+                play_order = nav_points + i + 1,
+                label = xml_escape(label),
+                href = xml_escape(href),
+            ));
+        }
+        page_list.push_str("  </pageList>\n");
+        let end = before_end_tag(ncx, doc.root_element()).ok_or_else(|| missing(NCX, "ncx"))?;
+        edits.insert(end, page_list);
+
+        Ok(edits.apply(ncx))
+    }
+}
+
+/// Changes to the source of an XML document, applied from its end so the
+/// offsets of the others stay valid.
+#[derive(Default)]
+struct Edits(Vec<(Range<usize>, String)>);
+
+impl Edits {
+    fn insert(&mut self, at: usize, text: String) {
+        self.0.push((at..at, text));
+    }
+
+    fn replace(&mut self, range: Range<usize>, text: String) {
+        self.0.push((range, text));
+    }
+
+    fn apply(mut self, xml: &str) -> String {
+        self.0.sort_by_key(|(range, _)| Reverse(range.start));
+        let mut xml = xml.to_string();
+        for (range, text) in self.0 {
+            xml.replace_range(range, &text);
+        }
+        xml
+    }
+}
+
+fn parse<'i>(xml: &'i str, path: &str) -> Result<Document<'i>, Error> {
+    // the navigation documents have a doctype
+    let options = ParsingOptions {
+        allow_dtd: true,
+        ..ParsingOptions::default()
+    };
+    Document::parse_with_options(xml, options)
+        .map_err(|e| Error::EpubDocCreate(format!("Failed to parse {path} to patch it: {e}")))
+}
+
+fn missing(path: &str, what: &str) -> Error {
+    Error::EpubDocCreate(format!("Failed to patch {path}, it has no {what}"))
+}
+
+/// The first element named `name`, ignoring its namespace.
+fn element<'a, 'i>(doc: &'a Document<'i>, name: &str) -> Option<Node<'a, 'i>> {
+    doc.descendants().find(|n| n.has_tag_name(name))
+}
+
+/// The offset after the name of an element in its start tag.
+fn name_end(xml: &str, node: Node) -> usize {
+    let start = node.range().start + 1;
+    start
+        + xml[start..]
+            .find(|c: char| c.is_whitespace() || c == '/' || c == '>')
+            .unwrap_or(0)
+}
+
+/// The offset after the start tag of an element.
+fn start_tag_end(xml: &str, node: Node) -> usize {
+    // attribute values may contain `>`
+    let attributes_end = node
+        .attributes()
+        .map(|a| a.range().end)
+        .max()
+        .unwrap_or_else(|| name_end(xml, node));
+    attributes_end + xml[attributes_end..].find('>').map_or(0, |i| i + 1)
+}
+
+/// The offset of the end tag of an element, `None` if it's empty.
+fn end_tag_start(xml: &str, node: Node) -> Option<usize> {
+    let range = node.range();
+    if xml[range.clone()].ends_with("/>") {
+        return None;
+    }
+    xml[..range.end].rfind("</")
+}
+
+/// The content of an element between its tags, `None` if it's empty.
+fn content_range(xml: &str, node: Node) -> Option<Range<usize>> {
+    Some(start_tag_end(xml, node)..end_tag_start(xml, node)?)
+}
+
+/// Where content is appended to an element: at the start of the line of its
+/// end tag to keep the indentation, or else right before the end tag.
+fn before_end_tag(xml: &str, node: Node) -> Option<usize> {
+    let end = end_tag_start(xml, node)?;
+    let line_start = xml[..end].rfind('\n').map_or(end, |i| i + 1);
+    if xml[line_start..end].trim().is_empty() {
+        Some(line_start)
+    } else {
+        Some(end)
+    }
+}
+
+/// The element of the unique identifier of the book in its package document.
+fn identifier_element<'a, 'i>(doc: &'a Document<'i>) -> Option<Node<'a, 'i>> {
+    let id = doc.root_element().attribute("unique-identifier")?;
+    doc.descendants()
+        .find(|n| n.has_tag_name("identifier") && n.attribute("id") == Some(id))
+}
+
+/// The unique identifier of the book in its package document.
+fn unique_identifier(opf: &str) -> Option<String> {
+    let doc = Document::parse(opf).ok()?;
+    let identifier = identifier_element(&doc)?;
+    Some(identifier.text().unwrap_or_default().to_string())
+}
+
+/// Escape text for use in XML content and attribute values.
+pub(crate) fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn patch_package_document() {
+        let package = Package {
+            identifier: Some("urn:isbn:9783161484100".to_string()),
+            metadata: vec!["<dc:publisher>Acme</dc:publisher>".to_string()],
+            mathml: vec!["math.html".to_string(), "nav.xhtml".to_string()],
+            landmarks: vec![("cover", "cover.xhtml".to_string(), "Cover".to_string())],
+            ..Package::default()
+        };
+        let opf = "<package xmlns=\"http://www.idpf.org/2007/opf\" unique-identifier=\"id\">\n  \
+            <metadata xmlns:dc=\"http://purl.org/dc/elements/1.1/\">\n    \
+            <dc:identifier id=\"id\">urn:uuid:1234</dc:identifier>\n  </metadata>\n  <manifest>\n    \
+            <item id=\"nav\" href=\"nav.xhtml\" properties=\"nav\"/>\n    \
+            <item media-type=\"application/xhtml+xml\" id=\"math_html\" href=\"math.html\"/>\n  \
+            </manifest>\n  <guide>\n  </guide>\n</package>\n";
+        assert_eq!(
+            package.patch(opf).unwrap(),
+            "<package xmlns=\"http://www.idpf.org/2007/opf\" unique-identifier=\"id\">\n  \
+            <metadata xmlns:dc=\"http://purl.org/dc/elements/1.1/\">\n    \
+            <dc:identifier id=\"id\">urn:isbn:9783161484100</dc:identifier>\n    \
+            <dc:publisher>Acme</dc:publisher>\n  </metadata>\n  <manifest>\n    \
+            <item id=\"nav\" href=\"nav.xhtml\" properties=\"nav mathml\"/>\n    \
+            <item properties=\"mathml\" media-type=\"application/xhtml+xml\" id=\"math_html\" \
+            href=\"math.html\"/>\n  </manifest>\n  <guide>\n    \
+            <reference type=\"cover\" title=\"Cover\" href=\"cover.xhtml\"/>\n  </guide>\n\
+            </package>\n"
+        );
+
+        // changes that can't be made fail instead of being dropped
+        let opf = "<package unique-identifier=\"other\">\n  <metadata>\n    \
+            <dc:identifier xmlns:dc=\"http://purl.org/dc/elements/1.1/\" id=\"id\">1234\
+            </dc:identifier>\n  </metadata>\n</package>";
+        assert!(package
+            .patch(opf)
+            .unwrap_err()
+            .to_string()
+            .contains("Failed to patch OEBPS/content.opf, it has no unique identifier"));
+        assert!(Package::default().patch("<package>").is_err());
+    }
+
+    #[test]
+    fn patch_navigation_documents() {
+        let package = Package {
+            landmarks: vec![("cover", "cover.xhtml".to_string(), "Cover".to_string())],
+            pages: vec![
+                ("ii".to_string(), "intro.html#page-ii".to_string()),
+                ("1".to_string(), "chapter_1.html#page-1".to_string()),
+            ],
+            ..Package::default()
+        };
+
+        let html = "<html xmlns=\"http://www.w3.org/1999/xhtml\" \
+            xmlns:epub=\"http://www.idpf.org/2007/ops\">\n";
+        let nav = format!(
+            "{html}<body>\n  <nav epub:type = \"landmarks\">\n\n  </nav>\n</body>\n</html>"
+        );
+        assert_eq!(
+            package.patch_navigation(&nav).unwrap(),
+            format!(
+                "{html}<body>\n  <nav epub:type = \"landmarks\">\n\n    <ol>\n      \
+                <li><a epub:type=\"cover\" href=\"cover.xhtml\">Cover</a></li>\n    </ol>\n  </nav>\n  \
+                <nav epub:type=\"page-list\" hidden=\"hidden\">\n    <ol>\n      \
+                <li><a href=\"intro.html#page-ii\">ii</a></li>\n      \
+                <li><a href=\"chapter_1.html#page-1\">1</a></li>\n    </ol>\n  </nav>\n</body>\n</html>"
+            )
+        );
+        let nav = format!("{html}<body>\n  <nav epub:type=\"toc\"></nav>\n</body>\n</html>");
+        assert!(package.patch_navigation(&nav).is_err());
+        // EPUB 2 has a table of contents page instead
+        let toc = "<html xmlns=\"http://www.w3.org/1999/xhtml\"><body></body></html>";
+        assert_eq!(package.patch_navigation(toc).unwrap(), toc);
+
+        let ncx = "<ncx xmlns=\"http://www.daisy.org/z3986/2005/ncx/\">\n  <head>\n    \
+            <meta name=\"dtb:totalPageCount\" content=\"0\" />\n    \
+            <meta name=\"dtb:maxPageNumber\" content=\"0\" />\n  </head>\n  <navMap>\n    \
+            <navPoint playOrder=\"1\" id=\"navPoint-1\"></navPoint>\n  </navMap>\n</ncx>";
+        let patched = package.patch_ncx(ncx).unwrap();
+        assert!(patched.contains("<meta name=\"dtb:totalPageCount\" content=\"2\" />"));
+        assert!(patched.contains("<meta name=\"dtb:maxPageNumber\" content=\"1\" />"));
+        assert!(patched.contains(
+            "  <pageList>\n    <navLabel><text>Pages</text></navLabel>\n    \
+            <pageTarget id=\"pageTarget-1\" type=\"front\" playOrder=\"2\">\n      \
+            <navLabel><text>ii</text></navLabel>\n      <content src=\"intro.html#page-ii\"/>\n    \
+            </pageTarget>\n"
+        ));
+        assert!(patched.ends_with("</pageTarget>\n  </pageList>\n</ncx>"));
+        assert!(package.patch_ncx("<ncx><navMap></navMap></ncx>").is_err());
+    }
+}
@@ -0,0 +1,276 @@
+use std::collections::HashSet;
+
+use epub_builder::EpubVersion;
+use handlebars::html_escape;
+use pulldown_cmark::{CowStr, Event, Tag, TagEnd, TextMergeStream};
+
+use crate::Error;
+
+/// A page break of the print edition in a chapter.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct PageBreak {
+    /// The page number, or any other label like `xii`.
+    pub(crate) label: String,
+    /// The id of the page break element in the chapter.
+    pub(crate) id: String,
+}
+
+/// Finds the page break markers of the Markdown, like `<!-- page 12 -->`.
+#[derive(Debug)]
+pub(crate) struct PageMarker {
+    before: String,
+    after: String,
+}
+
+impl PageMarker {
+    /// Parse a marker pattern with `{}` in place of the page label.
+    pub(crate) fn new(pattern: &str) -> Result<Self, Error> {
+        match pattern.split_once("{}") {
+            Some((before, after)) if !before.is_empty() => Ok(PageMarker {
+                before: before.to_string(),
+                after: after.to_string(),
+            }),
+            _ => Err(Error::EpubDocCreate(format!(
+                "Invalid page-marker \"{pattern}\", it must contain `{{}}` after some text"
+            ))),
+        }
+    }
+
+    /// Replace the markers in the rendered events of a chapter by page break
+    /// elements, returning the page breaks found. Markers in code are left as
+    /// they are, and page ids never take one of the `taken` ids of headings.
+    pub(crate) fn mark<'a, I>(
+        &self,
+        events: I,
+        version: EpubVersion,
+        taken: HashSet<String>,
+    ) -> (Vec<Event<'a>>, Vec<PageBreak>)
+    where
+        I: Iterator<Item = Event<'a>>,
+    {
+        let mut marking = Marking {
+            version,
+            ids: taken,
+            pages: Vec::new(),
+        };
+        let mut marked = Vec::new();
+        let mut in_code_block = false;
+
+        // markers in text may be split over several text events
+        for event in TextMergeStream::new(events) {
+            match event {
+                Event::Start(Tag::CodeBlock(_)) => in_code_block = true,
+                Event::End(TagEnd::CodeBlock) => in_code_block = false,
+                Event::Text(ref text) if !in_code_block => {
+                    let segments = self.split(text);
+                    if segments.iter().any(|s| matches!(s, Segment::Page(_))) {
+                        for segment in segments {
+                            marked.push(match segment {
+                                Segment::Text(text) => Event::Text(CowStr::from(text.to_string())),
+                                Segment::Page(label) => {
+                                    Event::InlineHtml(CowStr::from(marking.page_break(label)))
+                                }
+                            });
+                        }
+                        continue;
+                    }
+                }
+                Event::Html(ref html) | Event::InlineHtml(ref html) if !in_code_block => {
+                    let segments = self.split(html);
+                    if segments.iter().any(|s| matches!(s, Segment::Page(_))) {
+                        let html: String = segments
+                            .into_iter()
+                            .map(|segment| match segment {
+                                Segment::Text(text) => text.to_string(),
+                                Segment::Page(label) => marking.page_break(label),
+                            })
+                            .collect();
+                        marked.push(match event {
+                            Event::Html(_) => Event::Html(CowStr::from(html)),
+                            _ => Event::InlineHtml(CowStr::from(html)),
+                        });
+                        continue;
+                    }
+                }
+                _ => {}
+            }
+            marked.push(event);
+        }
+        (marked, marking.pages)
+    }
+
+    /// Split text at the markers in it, into the text between and the
+    /// labels of the markers.
+    fn split<'t>(&self, text: &'t str) -> Vec<Segment<'t>> {
+        let mut segments = Vec::new();
+        let mut rest = text;
+        let mut scanned = 0;
+
+        while let Some(found) = rest[scanned..].find(&self.before) {
+            let start = scanned + found;
+            let label_start = start + self.before.len();
+            let label_len = match self.after.as_str() {
+                // the label runs to the end of the word without a suffix
+                "" => rest[label_start..]
+                    .find(char::is_whitespace)
+                    .unwrap_or(rest.len() - label_start),
+                after => match rest[label_start..].find(after) {
+                    Some(len) => len,
+                    None => break,
+                },
+            };
+            let label = rest[label_start..label_start + label_len].trim();
+            if label.is_empty() || label.contains('\n') {
+                scanned = label_start;
+                continue;
+            }
+            if start > 0 {
+                segments.push(Segment::Text(&rest[..start]));
+            }
+            segments.push(Segment::Page(label));
+            rest = &rest[label_start + label_len + self.after.len()..];
+            scanned = 0;
+        }
+
+        if !rest.is_empty() {
+            segments.push(Segment::Text(rest));
+        }
+        segments
+    }
+}
+
+/// Text split at the page break markers in it.
+enum Segment<'t> {
+    Text(&'t str),
+    /// A marker with its label.
+    Page(&'t str),
+}
+
+/// The page breaks of a chapter found so far.
+struct Marking {
+    version: EpubVersion,
+    /// The ids of the chapter's headings and page breaks.
+    ids: HashSet<String>,
+    pages: Vec<PageBreak>,
+}
+
+impl Marking {
+    /// The element of the next page break, with an id unique in the chapter.
+    fn page_break(&mut self, label: &str) -> String {
+        let mut id = format!("page-{}", id_safe(label));
+        let mut n = 1;
+        while !self.ids.insert(id.clone()) {
+            n += 1;
+            id = format!("page-{}-{}", id_safe(label), n);
+        }
+        let element = page_break(label, &id, self.version);
+        self.pages.push(PageBreak {
+            label: label.to_string(),
+            id,
+        });
+        element
+    }
+}
+
+/// The element marking a page break.
+fn page_break(label: &str, id: &str, version: EpubVersion) -> String {
+    let label = html_escape(label);
+    match version {
+        EpubVersion::V30 => format!(
+            "<span epub:type=\"pagebreak\" role=\"doc-pagebreak\" id=\"{id}\" aria-label=\"{label}\"/>"
+        ),
+        _ => format!("<span id=\"{id}\" title=\"{label}\"/>"),
+    }
+}
+
+/// Turn a page label into a part of an XML id.
+fn id_safe(label: &str) -> String {
+    label
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use mdbook::utils::new_cmark_parser;
+    use pulldown_cmark::html;
+
+    use super::*;
+
+    fn render(
+        marker: &PageMarker,
+        content: &str,
+        version: EpubVersion,
+        taken: &[&str],
+    ) -> (String, Vec<PageBreak>) {
+        let taken = taken.iter().map(|id| id.to_string()).collect();
+        let (events, pages) = marker.mark(new_cmark_parser(content, false), version, taken);
+        let mut rendered = String::new();
+        html::push_html(&mut rendered, events.into_iter());
+        (rendered, pages)
+    }
+
+    #[test]
+    fn mark_page_breaks() {
+        let marker = PageMarker::new("<!-- page {} -->").unwrap();
+        let content = "<!-- page 12 -->\n\nText <!-- page xii --> and <!-- page 12 -->.\n\
+            <!-- page  -->";
+        let (marked, pages) = render(&marker, content, EpubVersion::V30, &[]);
+        assert_eq!(
+            marked,
+            "<span epub:type=\"pagebreak\" role=\"doc-pagebreak\" id=\"page-12\" \
+            aria-label=\"12\"/>\n<p>Text <span epub:type=\"pagebreak\" role=\"doc-pagebreak\" \
+            id=\"page-xii\" aria-label=\"xii\"/> and <span epub:type=\"pagebreak\" \
+            role=\"doc-pagebreak\" id=\"page-12-2\" aria-label=\"12\"/>.</p>\n<!-- page  -->"
+        );
+        let ids: Vec<_> = pages.iter().map(|page| page.id.as_str()).collect();
+        assert_eq!(ids, ["page-12", "page-xii", "page-12-2"]);
+
+        let marker = PageMarker::new("[p.{}]").unwrap();
+        let (marked, pages) = render(&marker, "a [p.3/4] b [p.5", EpubVersion::V20, &[]);
+        assert_eq!(
+            marked,
+            "<p>a <span id=\"page-3_4\" title=\"3/4\"/> b [p.5</p>\n"
+        );
+        assert_eq!(pages[0].label, "3/4");
+
+        let marker = PageMarker::new("@page:{}").unwrap();
+        let (marked, _) = render(&marker, "@page:7\ntext", EpubVersion::V20, &[]);
+        assert_eq!(marked, "<p><span id=\"page-7\" title=\"7\"/>\ntext</p>\n");
+
+        assert!(PageMarker::new("{}").is_err());
+        assert!(PageMarker::new("<!-- page -->").is_err());
+    }
+
+    #[test]
+    fn skip_markers_in_code() {
+        let marker = PageMarker::new("[p.{}]").unwrap();
+        let content = "Use `[p.1]` to mark\n\n```\n[p.2]\n```\n\nText [p.3]";
+        let (marked, pages) = render(&marker, content, EpubVersion::V20, &[]);
+        assert_eq!(
+            marked,
+            "<p>Use <code>[p.1]</code> to mark</p>\n<pre><code>[p.2]\n</code></pre>\n\
+            <p>Text <span id=\"page-3\" title=\"3\"/></p>\n"
+        );
+        assert_eq!(pages.len(), 1);
+    }
+
+    #[test]
+    fn keep_page_ids_apart_from_headings() {
+        let marker = PageMarker::new("<!-- page {} -->").unwrap();
+        let (_, pages) = render(
+            &marker,
+            "# Page 12\n\n<!-- page 12 -->",
+            EpubVersion::V20,
+            &["page-12"],
+        );
+        assert_eq!(pages[0].id, "page-12-2");
+    }
+}
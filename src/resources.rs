@@ -11,9 +11,12 @@ use std::num::Wrapping;
 use std::path::{Component, Path, PathBuf};
 use url::Url;
 
+use crate::css::{self, Reference};
+use crate::xhtml::parse_attributes;
 use crate::Error;
 
-pub(crate) fn find(ctx: &RenderContext) -> Result<HashMap<String, Asset>, Error> {
+/// Find the assets of the chapters, caching remote assets in `cache_dir`.
+pub(crate) fn find(ctx: &RenderContext, cache_dir: &Path) -> Result<HashMap<String, Asset>, Error> {
     let mut assets: HashMap<String, Asset> = HashMap::new();
     debug!("Finding resources by:\n{:?}", ctx.config);
     let src_dir = ctx.root.join(&ctx.config.book.src).canonicalize()?;
@@ -33,7 +36,7 @@ pub(crate) fn find(ctx: &RenderContext) -> Result<HashMap<String, Asset>, Error>
                 }
                 for link in find_assets_in_markdown(&ch.content)? {
                     let asset = match Url::parse(&link) {
-                        Ok(url) => Asset::from_url(url, &ctx.destination),
+                        Ok(url) => Asset::from_url(url, cache_dir),
                         Err(_) => Asset::from_local(&link, &src_dir, ch.path.as_ref().unwrap()),
                     }?;
                     assets.insert(link, asset);
@@ -58,7 +61,7 @@ pub(crate) struct Asset {
     /// The asset's absolute location on disk.
     pub(crate) location_on_disk: PathBuf,
     /// The asset's filename relative to the `src/` directory. If it's a remote
-    /// asset it relative to the destination where the book generated.
+    /// asset it's in the `cache` directory of the book.
     pub(crate) filename: PathBuf,
     pub(crate) mimetype: Mime,
     /// The asset's original link as a enum [local][AssetKind::Local] or [remote][AssetKind::Remote].
@@ -107,13 +110,38 @@ impl Asset {
         }
     }
 
-    fn from_url(url: Url, dest_dir: &Path) -> Result<Asset, Error> {
+    /// The media type of the asset's content on disk, which `mimetype`
+    /// differs from for converted images. Remote assets are typed by their
+    /// download, local ones by their file name.
+    pub(crate) fn stored_type(&self) -> Mime {
+        let guessed = || mime_guess::from_path(&self.location_on_disk).first_or_octet_stream();
+        match self.source {
+            AssetKind::Remote(_) => {
+                handler::media_type(&self.location_on_disk).unwrap_or_else(guessed)
+            }
+            AssetKind::Local(_) => guessed(),
+        }
+    }
+
+    /// Change the media type of the asset, changing the extension of its
+    /// file name if it doesn't fit.
+    pub(crate) fn set_media_type(&mut self, mimetype: Mime) {
+        let fits = mime_guess::from_path(&self.filename)
+            .iter()
+            .any(|guessed| guessed == mimetype);
+        if !fits {
+            if let Some(extension) = extension(&mimetype) {
+                self.filename.set_extension(extension);
+            }
+        }
+        self.mimetype = mimetype;
+    }
+
+    fn from_url(url: Url, cache_dir: &Path) -> Result<Asset, Error> {
         let filename = hash_link(&url);
-        let dest_dir = normalize_path(dest_dir);
-        let full_filename = dest_dir.join("cache").join(filename);
         // Will fetch assets to normalized path later. fs::canonicalize() only works for existed path.
-        let absolute_location = normalize_path(full_filename.as_path());
-        let filename = absolute_location.strip_prefix(dest_dir).unwrap();
+        let absolute_location = normalize_path(&cache_dir.join(&filename));
+        let filename = Path::new("cache").join(filename);
         let asset = Asset::new(filename, &absolute_location, AssetKind::Remote(url));
         trace!("{:#?}", asset);
         Ok(asset)
@@ -155,20 +183,6 @@ impl Asset {
 fn find_assets_in_markdown(chapter_src_content: &str) -> Result<Vec<String>, Error> {
     let mut found_asset = Vec::new();
 
-    // Look up resources in nested HTML element
-    fn find_assets_in_nested_html_tags(element: &html_parser::Element, found: &mut Vec<String>) {
-        if element.name == "img" {
-            if let Some(dest) = &element.attributes["src"] {
-                found.push(dest.clone());
-            }
-        }
-        for item in &element.children {
-            if let Node::Element(ref nested_element) = item {
-                find_assets_in_nested_html_tags(nested_element, found);
-            }
-        }
-    }
-
     for event in new_cmark_parser(chapter_src_content, false) {
         match event {
             Event::Start(Tag::Image {
@@ -180,14 +194,7 @@ fn find_assets_in_markdown(chapter_src_content: &str) -> Result<Vec<String>, Err
                 found_asset.push(dest_url.to_string());
             }
             Event::Html(html) | Event::InlineHtml(html) => {
-                let content = html.into_string();
-                if let Ok(dom) = Dom::parse(&content) {
-                    for item in dom.children {
-                        if let Node::Element(ref element) = item {
-                            find_assets_in_nested_html_tags(element, &mut found_asset)
-                        }
-                    }
-                }
+                found_asset.extend(find_assets_in_html(&html));
             }
             _ => {}
         }
@@ -201,6 +208,233 @@ fn find_assets_in_markdown(chapter_src_content: &str) -> Result<Vec<String>, Err
     Ok(found_asset)
 }
 
+/// Look up the links to resources in HTML: the sources of images, media and
+/// embedded objects, and the `url()`s of inline styles.
+pub(crate) fn find_assets_in_html(html: &str) -> Vec<String> {
+    // Look up resources in nested HTML element
+    fn find_assets_in_nested_html_tags(element: &html_parser::Element, found: &mut Vec<String>) {
+        for &attribute in asset_attributes(&element.name) {
+            let Some(Some(value)) = element.attributes.get(attribute) else {
+                continue;
+            };
+            if attribute == "srcset" {
+                // comma separated candidates of a URL and a width or density
+                found.extend(
+                    value
+                        .split(',')
+                        .filter_map(|candidate| candidate.split_whitespace().next())
+                        .map(String::from),
+                );
+            } else {
+                found.push(value.clone());
+            }
+        }
+        if let Some(Some(style)) = element.attributes.get("style") {
+            css::rewrite(style, |reference| {
+                if let Reference::Url(url) = reference {
+                    found.push(url.to_string());
+                }
+                None
+            });
+        }
+
+        for item in &element.children {
+            if let Node::Element(ref nested_element) = item {
+                find_assets_in_nested_html_tags(nested_element, found);
+            }
+        }
+    }
+
+    let mut found = Vec::new();
+    let dom = Dom::parse(html)
+        .ok()
+        .filter(|dom| dom.children.iter().any(|n| matches!(n, Node::Element(_))))
+        .or_else(|| {
+            // an opening tag on its own, like the inline HTML `<audio src="a.mp3">`
+            // in a paragraph, is parsed as a void element
+            let tag = html.trim().strip_suffix('>')?;
+            if !tag.starts_with('<') || tag.starts_with("</") || tag.ends_with('/') {
+                return None;
+            }
+            Dom::parse(&format!("{tag}/>")).ok()
+        });
+    if let Some(dom) = dom {
+        for item in dom.children {
+            if let Node::Element(ref element) = item {
+                find_assets_in_nested_html_tags(element, &mut found)
+            }
+        }
+    }
+    // inline data and fragments of the document itself aren't files
+    found.retain(|link| !link.is_empty() && !link.starts_with("data:") && !link.starts_with('#'));
+    found
+}
+
+/// The attributes of an HTML element linking to assets.
+fn asset_attributes(element: &str) -> &'static [&'static str] {
+    match element {
+        "img" | "source" => &["src", "srcset"],
+        "video" => &["src", "poster"],
+        "audio" | "track" | "embed" => &["src"],
+        "object" => &["data"],
+        "image" => &["href", "xlink:href"],
+        _ => &[],
+    }
+}
+
+/// Replace the links to assets in HTML, the ones `find_assets_in_html`
+/// finds, by what `resolve` returns for them. Links are kept as they are if
+/// `resolve` returns `None`, as is everything but the attributes linking to
+/// assets.
+pub(crate) fn rewrite_assets_in_html<F>(html: &str, mut resolve: F) -> String
+where
+    F: FnMut(&str) -> Option<String>,
+{
+    let mut rewritten = String::with_capacity(html.len());
+    let mut rest = html;
+    while let Some(start) = rest.find('<') {
+        rewritten.push_str(&rest[..start]);
+        rest = &rest[start..];
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            let end = comment.find("-->").map_or(rest.len(), |i| i + 7);
+            rewritten.push_str(&rest[..end]);
+            rest = &rest[end..];
+            continue;
+        }
+        let name_len = rest[1..]
+            .find(|c: char| c.is_whitespace() || c == '/' || c == '>')
+            .unwrap_or(rest.len() - 1);
+        let name = rest[1..1 + name_len].to_ascii_lowercase();
+        let parsed = name
+            .starts_with(|c: char| c.is_ascii_alphabetic())
+            .then(|| parse_attributes(&rest[1 + name_len..]))
+            .flatten();
+        let Some((attributes, _, len)) = parsed else {
+            rewritten.push('<');
+            rest = &rest[1..];
+            continue;
+        };
+
+        let tag = &rest[..1 + name_len + len];
+        let mut copied = 0;
+        for (attribute, value) in attributes {
+            let Some(value) = value else {
+                continue;
+            };
+            let attribute = attribute.to_ascii_lowercase();
+            let new = if attribute == "srcset" && asset_attributes(&name).contains(&"srcset") {
+                rewrite_srcset(value, &mut resolve)
+            } else if asset_attributes(&name).contains(&attribute.as_str()) {
+                resolve(value)
+            } else if attribute == "style" {
+                let mut changed = false;
+                let style = css::rewrite(value, |reference| match reference {
+                    Reference::Url(url) => {
+                        let new = resolve(url);
+                        changed |= new.is_some();
+                        new
+                    }
+                    Reference::Import(..) => None,
+                });
+                changed.then_some(style)
+            } else {
+                None
+            };
+            let Some(new) = new else {
+                continue;
+            };
+            // the value is a slice of the tag, replaced along with its quotes
+            let mut start = value.as_ptr() as usize - tag.as_ptr() as usize;
+            let mut end = start + value.len();
+            if tag[..start].ends_with(['"', '\'']) {
+                start -= 1;
+                end += 1;
+            }
+            rewritten.push_str(&tag[copied..start]);
+            rewritten.push('"');
+            rewritten.push_str(&new.replace('"', "&quot;"));
+            rewritten.push('"');
+            copied = end;
+        }
+        rewritten.push_str(&tag[copied..]);
+        rest = &rest[tag.len()..];
+    }
+    rewritten.push_str(rest);
+    rewritten
+}
+
+/// Replace the URLs of the candidates of a `srcset` attribute, `None` if
+/// none of them changes.
+fn rewrite_srcset<F>(srcset: &str, resolve: &mut F) -> Option<String>
+where
+    F: FnMut(&str) -> Option<String>,
+{
+    let mut changed = false;
+    let candidates: Vec<String> = srcset
+        .split(',')
+        .map(|candidate| {
+            let new = candidate
+                .split_whitespace()
+                .next()
+                .and_then(|url| Some((url, resolve(url)?)));
+            match new {
+                Some((url, new)) => {
+                    changed = true;
+                    candidate.replacen(url, &new, 1)
+                }
+                None => candidate.to_string(),
+            }
+        })
+        .collect();
+    changed.then(|| candidates.join(","))
+}
+
+/// The usual file extension of a media type.
+fn extension(mimetype: &Mime) -> Option<&'static str> {
+    let extension = match mimetype.essence_str() {
+        "image/jpeg" => "jpg",
+        "image/svg+xml" => "svg",
+        "audio/mpeg" => "mp3",
+        "audio/ogg" => "ogg",
+        "font/ttf" => "ttf",
+        "font/otf" => "otf",
+        _ => return mime_guess::get_mime_extensions(mimetype)?.first().copied(),
+    };
+    Some(extension)
+}
+
+/// Detect the media type of a file from its first bytes, for images, audio,
+/// video and fonts.
+pub(crate) fn sniff_media_type(data: &[u8]) -> Option<Mime> {
+    if let Ok(format) = image::guess_format(data) {
+        return format.to_mime_type().parse().ok();
+    }
+    let signature =
+        |offset: usize, magic: &[u8]| data.get(offset..offset + magic.len()) == Some(magic);
+    let mimetype = if signature(0, b"ID3") || signature(0, b"\xff\xfb") {
+        "audio/mpeg"
+    } else if signature(0, b"OggS") {
+        "audio/ogg"
+    } else if signature(4, b"ftyp") {
+        "video/mp4"
+    } else if signature(0, b"\x1a\x45\xdf\xa3") {
+        "video/webm"
+    } else if signature(0, b"wOFF") {
+        "font/woff"
+    } else if signature(0, b"wOF2") {
+        "font/woff2"
+    } else if signature(0, b"OTTO") {
+        "font/otf"
+    } else if signature(0, b"\x00\x01\x00\x00") {
+        "font/ttf"
+    } else if String::from_utf8_lossy(&data[..data.len().min(1024)]).contains("<svg") {
+        "image/svg+xml"
+    } else {
+        return None;
+    };
+    mimetype.parse().ok()
+}
+
 pub(crate) fn hash_link(url: &Url) -> String {
     use std::collections::hash_map::DefaultHasher;
     use std::hash::{Hash, Hasher};
@@ -249,83 +483,468 @@ pub fn normalize_path(path: &Path) -> PathBuf {
 
 pub(crate) mod handler {
     use std::{
-        fs::{self, File, OpenOptions},
+        fs::{self, File},
         io::{self, Read},
-        path::Path,
+        path::{Path, PathBuf},
+        process,
+        sync::atomic::{AtomicUsize, Ordering},
+        thread,
+        time::{Duration, SystemTime, UNIX_EPOCH},
     };
 
+    use mime_guess::Mime;
     #[cfg(test)]
     use mockall::automock;
+    use serde::{Deserialize, Serialize};
+    use url::Url;
 
+    use crate::config::Remote;
     use crate::Error;
 
     use super::{Asset, AssetKind};
 
+    /// What is known about a cached asset, stored next to it.
+    #[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    #[serde(default, rename_all = "kebab-case")]
+    pub(crate) struct CacheEntry {
+        pub(crate) url: String,
+        pub(crate) etag: Option<String>,
+        pub(crate) last_modified: Option<String>,
+        pub(crate) content_type: Option<String>,
+        /// When the asset was last downloaded or revalidated, in seconds
+        /// since the Unix epoch.
+        pub(crate) fetched: u64,
+    }
+
+    impl CacheEntry {
+        /// The metadata file of a cached asset.
+        fn path(asset: &Path) -> PathBuf {
+            let mut path = asset.as_os_str().to_owned();
+            path.push(".json");
+            path.into()
+        }
+
+        fn load(asset: &Path) -> Option<CacheEntry> {
+            let data = fs::read(Self::path(asset)).ok()?;
+            serde_json::from_slice(&data).ok()
+        }
+
+        fn save(&self, asset: &Path) -> Result<(), Error> {
+            fs::write(Self::path(asset), serde_json::to_vec_pretty(self)?)?;
+            Ok(())
+        }
+
+        fn is_fresh(&self, ttl: u64) -> bool {
+            now().saturating_sub(self.fetched) < ttl
+        }
+    }
+
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_secs())
+    }
+
+    /// The content of a downloaded asset, with the headers validating the
+    /// cached copy later.
+    pub(crate) struct Response {
+        pub(crate) body: Box<dyn Read + Send + Sync + 'static>,
+        pub(crate) etag: Option<String>,
+        pub(crate) last_modified: Option<String>,
+        pub(crate) content_type: Option<String>,
+    }
+
+    impl Response {
+        pub(crate) fn new(body: impl Read + Send + Sync + 'static) -> Self {
+            Response {
+                body: Box::new(body),
+                etag: None,
+                last_modified: None,
+                content_type: None,
+            }
+        }
+    }
+
     #[cfg_attr(test, automock)]
-    pub(crate) trait ContentRetriever {
-        fn download(&self, asset: &Asset) -> Result<(), Error> {
-            if let AssetKind::Remote(url) = &asset.source {
-                let dest = &asset.location_on_disk;
-                if dest.is_file() {
-                    debug!("Cache file {:?} to {} already exists.", dest, url);
-                } else {
-                    if let Some(cache_dir) = dest.parent() {
-                        fs::create_dir_all(cache_dir)?;
-                    }
-                    debug!("Downloading asset : {}", url);
-                    let mut file = OpenOptions::new()
-                        .create(true)
-                        .truncate(true)
-                        .write(true)
-                        .open(dest)?;
-                    let mut resp = self.retrieve(url.as_str())?;
-                    io::copy(&mut resp, &mut file)?;
+    pub(crate) trait ContentRetriever: Sync {
+        /// Download a remote asset into the cache, unless its cached copy is
+        /// fresh or the download is offline.
+        fn download(&self, asset: &Asset, config: &Remote) -> Result<(), Error> {
+            let AssetKind::Remote(url) = &asset.source else {
+                return Ok(());
+            };
+            check_host(url, config)?;
+            let dest = &asset.location_on_disk;
+            // cache files without metadata are revalidated
+            let cached = dest
+                .is_file()
+                .then(|| CacheEntry::load(dest).unwrap_or_default());
+            let entry = match cached {
+                Some(entry) if config.offline => {
+                    debug!("Using cache file {:?} to {} offline.", dest, url);
+                    entry
+                }
+                Some(entry) if entry.is_fresh(config.cache_ttl) => {
+                    debug!("Cache file {:?} to {} is fresh.", dest, url);
+                    entry
+                }
+                None if config.offline => return Err(Error::NotCached(url.to_string())),
+                cached => fetch(self, url, dest, cached, config)?,
+            };
+            // cached copies may have been downloaded with other limits
+            if let Some(max) = config.max_bytes {
+                if fs::metadata(dest)?.len() > max {
+                    return Err(too_large(url, max));
                 }
             }
-            Ok(())
+            check_media_type(url, entry.content_type.as_deref(), dest, config)
         }
         fn read(&self, path: &Path, buffer: &mut Vec<u8>) -> Result<(), Error> {
             File::open(path)?.read_to_end(buffer)?;
             Ok(())
         }
-        fn retrieve(&self, url: &str) -> Result<Box<(dyn Read + Send + Sync + 'static)>, Error>;
+        /// Download an asset, `None` if it's unchanged since it was cached.
+        // the lifetime is named for mockall
+        #[allow(clippy::needless_lifetimes)]
+        fn retrieve<'a>(
+            &self,
+            url: &str,
+            cached: Option<&'a CacheEntry>,
+        ) -> Result<Option<Response>, Error>;
+    }
+
+    /// The media type of a cached asset: the `Content-Type` it was downloaded
+    /// with unless that's missing or generic, else detected from its content.
+    pub(crate) fn media_type(path: &Path) -> Option<Mime> {
+        let declared = CacheEntry::load(path)
+            .and_then(|entry| entry.content_type)
+            .and_then(|content_type| {
+                let essence = content_type.split(';').next()?.trim();
+                essence.to_ascii_lowercase().parse::<Mime>().ok()
+            })
+            .filter(|mimetype| {
+                !matches!(
+                    mimetype.essence_str(),
+                    "application/octet-stream"
+                        | "binary/octet-stream"
+                        | "application/unknown"
+                        | "text/plain"
+                )
+            });
+        declared.or_else(|| {
+            let mut start = Vec::with_capacity(1024);
+            File::open(path)
+                .and_then(|file| file.take(1024).read_to_end(&mut start))
+                .ok()?;
+            super::sniff_media_type(&start)
+        })
+    }
+
+    /// Download an asset into the cache, returning its new cache entry or
+    /// the entry of the cached copy if the download failed.
+    fn fetch<R: ContentRetriever + ?Sized>(
+        retriever: &R,
+        url: &Url,
+        dest: &Path,
+        cached: Option<CacheEntry>,
+        config: &Remote,
+    ) -> Result<CacheEntry, Error> {
+        if let Some(cache_dir) = dest.parent() {
+            fs::create_dir_all(cache_dir)?;
+        }
+        debug!("Downloading asset : {}", url);
+        let response = match retriever.retrieve(url.as_str(), cached.as_ref()) {
+            Ok(response) => response,
+            Err(e) => match cached {
+                Some(cached) => {
+                    warn!("{}, using the cached copy", e);
+                    return Ok(cached);
+                }
+                None => return Err(e),
+            },
+        };
+        let mut entry = CacheEntry {
+            url: url.to_string(),
+            fetched: now(),
+            ..cached.unwrap_or_default()
+        };
+        let Some(response) = response else {
+            debug!("Cache file {:?} to {} is unchanged.", dest, url);
+            entry.save(dest)?;
+            return Ok(entry);
+        };
+        check_media_type(url, response.content_type.as_deref(), dest, config)?;
+
+        // a failed download mustn't leave a cache file behind, and builds
+        // sharing the cache mustn't write the same file
+        let mut partial = dest.as_os_str().to_owned();
+        partial.push(format!(".{}.part", process::id()));
+        let max = config.max_bytes.unwrap_or(u64::MAX);
+        let mut body = response.body.take(max.saturating_add(1));
+        let copied = File::create(&partial).and_then(|mut file| io::copy(&mut body, &mut file));
+        let error = match copied {
+            Ok(len) if len > max => Some(too_large(url, max)),
+            Ok(_) => fs::rename(&partial, dest).err().map(Error::from),
+            Err(e) => Some(Error::RemoteAsset {
+                url: url.to_string(),
+                status: None,
+                reason: e.to_string(),
+            }),
+        };
+        if let Some(e) = error {
+            let _ = fs::remove_file(&partial);
+            return Err(e);
+        }
+        entry.etag = response.etag;
+        entry.last_modified = response.last_modified;
+        entry.content_type = response.content_type;
+        entry.save(dest)?;
+        Ok(entry)
+    }
+
+    /// Fail for assets on hosts which are denied or not allowed.
+    fn check_host(url: &Url, config: &Remote) -> Result<(), Error> {
+        let host = url.host_str().unwrap_or_default();
+        let matches = |pattern: &String| host_matches(pattern, host);
+        if config.denied_hosts.iter().any(matches) {
+            return Err(disallowed(url, format!("the host {host} is denied")));
+        }
+        if !config.allowed_hosts.is_empty() && !config.allowed_hosts.iter().any(matches) {
+            return Err(disallowed(url, format!("the host {host} isn't allowed")));
+        }
+        Ok(())
+    }
+
+    /// Whether a host matches a pattern, which is either a host name or
+    /// `*.` followed by a domain matching its subdomains.
+    fn host_matches(pattern: &str, host: &str) -> bool {
+        match pattern.strip_prefix("*.") {
+            Some(domain) => host.len().checked_sub(domain.len() + 1).is_some_and(|dot| {
+                host.as_bytes()[dot] == b'.' && host[dot + 1..].eq_ignore_ascii_case(domain)
+            }),
+            None => pattern.eq_ignore_ascii_case(host),
+        }
+    }
+
+    /// Fail for assets whose media type, the `Content-Type` of the response
+    /// or guessed from the file name, isn't allowed.
+    fn check_media_type(
+        url: &Url,
+        content_type: Option<&str>,
+        dest: &Path,
+        config: &Remote,
+    ) -> Result<(), Error> {
+        if config.media_types.is_empty() {
+            return Ok(());
+        }
+        let media_type = match content_type {
+            Some(content_type) => content_type.split(';').next().unwrap_or_default(),
+            None => mime_guess::from_path(dest).first_raw().unwrap_or_default(),
+        }
+        .trim()
+        .to_ascii_lowercase();
+        let allowed = config.media_types.iter().any(|pattern| {
+            let pattern = pattern.to_ascii_lowercase();
+            match pattern.strip_suffix("/*") {
+                Some(kind) => media_type.split('/').next() == Some(kind),
+                None => pattern == media_type,
+            }
+        });
+        if allowed {
+            Ok(())
+        } else {
+            Err(disallowed(
+                url,
+                format!("the media type \"{media_type}\" isn't allowed"),
+            ))
+        }
+    }
+
+    fn too_large(url: &Url, max: u64) -> Error {
+        disallowed(url, format!("it's larger than {max} bytes"))
+    }
+
+    fn disallowed(url: &Url, reason: String) -> Error {
+        Error::DisallowedAsset {
+            url: url.to_string(),
+            reason,
+        }
+    }
+
+    /// Downloads remote assets over HTTP, retrying failures which may be
+    /// temporary.
+    pub(crate) struct ResourceHandler {
+        agent: ureq::Agent,
+        retries: u32,
+        /// The wait before the first retry, doubled for each further one.
+        backoff: Duration,
+    }
+
+    impl ResourceHandler {
+        pub(crate) fn new(config: &Remote) -> Self {
+            let timeout = Duration::from_secs(config.timeout);
+            let agent = ureq::AgentBuilder::new()
+                .timeout_connect(timeout)
+                .timeout_read(timeout)
+                .redirects(config.redirects)
+                .build();
+            ResourceHandler {
+                agent,
+                retries: config.retries,
+                backoff: Duration::from_millis(500),
+            }
+        }
+
+        fn get(&self, url: &str, cached: Option<&CacheEntry>) -> Result<Option<Response>, Error> {
+            let mut request = self.agent.get(url);
+            if let Some(entry) = cached {
+                if let Some(ref etag) = entry.etag {
+                    request = request.set("If-None-Match", etag);
+                }
+                if let Some(ref last_modified) = entry.last_modified {
+                    request = request.set("If-Modified-Since", last_modified);
+                }
+            }
+            let (status, reason) = match request.call() {
+                Ok(res) if res.status() == 304 && cached.is_some() => return Ok(None),
+                Ok(res) if res.status() == 200 => {
+                    let etag = res.header("ETag").map(String::from);
+                    let last_modified = res.header("Last-Modified").map(String::from);
+                    let content_type = res.header("Content-Type").map(String::from);
+                    return Ok(Some(Response {
+                        etag,
+                        last_modified,
+                        content_type,
+                        ..Response::new(res.into_reader())
+                    }));
+                }
+                // redirects beyond the limit and other successful statuses
+                Ok(res) => (Some(res.status()), unexpected_status(&res)),
+                Err(ureq::Error::Status(status, res)) => (Some(status), unexpected_status(&res)),
+                Err(ureq::Error::Transport(e)) => (None, e.to_string()),
+            };
+            Err(Error::RemoteAsset {
+                url: url.to_string(),
+                status,
+                reason,
+            })
+        }
     }
 
-    pub(crate) struct ResourceHandler;
     impl ContentRetriever for ResourceHandler {
-        fn retrieve(&self, url: &str) -> Result<Box<(dyn Read + Send + Sync + 'static)>, Error> {
-            let res = ureq::get(url).call()?;
-            match res.status() {
-                200 => Ok(res.into_reader()),
-                404 => Err(Error::AssetFileNotFound(format!(
-                    "Missing remote resource: {url}"
-                ))),
-                _ => unreachable!("Unexpected response status"),
+        fn retrieve(
+            &self,
+            url: &str,
+            cached: Option<&CacheEntry>,
+        ) -> Result<Option<Response>, Error> {
+            let mut attempt = 0;
+            loop {
+                match self.get(url, cached) {
+                    Err(Error::RemoteAsset { status, .. })
+                        if attempt < self.retries && is_temporary(status) =>
+                    {
+                        let wait = self.backoff * 2u32.pow(attempt);
+                        attempt += 1;
+                        warn!(
+                            "Failed to download {url}, retrying in {:.1}s ({attempt}/{})",
+                            wait.as_secs_f32(),
+                            self.retries
+                        );
+                        thread::sleep(wait);
+                    }
+                    result => return result,
+                }
             }
         }
     }
 
+    /// Download the remote assets with up to `workers` concurrent downloads,
+    /// returning the result of each asset in their order.
+    pub(crate) fn download_all(
+        handler: &dyn ContentRetriever,
+        assets: &[&Asset],
+        config: &Remote,
+    ) -> Vec<Result<(), Error>> {
+        let total = assets.len();
+        let workers = config.workers.clamp(1, total.max(1));
+        info!("Downloading {total} remote assets with {workers} workers");
+
+        let next = AtomicUsize::new(0);
+        let done = AtomicUsize::new(0);
+        let mut results: Vec<(usize, Result<(), Error>)> = thread::scope(|scope| {
+            let workers: Vec<_> = (0..workers)
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut results = Vec::new();
+                        loop {
+                            let i = next.fetch_add(1, Ordering::Relaxed);
+                            let Some(asset) = assets.get(i) else {
+                                break results;
+                            };
+                            let result = handler.download(asset, config);
+                            let done = done.fetch_add(1, Ordering::Relaxed) + 1;
+                            match (&result, &asset.source) {
+                                (Ok(()), AssetKind::Remote(url)) => {
+                                    debug!("Downloaded {url} ({done}/{total})")
+                                }
+                                (Err(e), _) => debug!("{e} ({done}/{total})"),
+                                _ => {}
+                            }
+                            results.push((i, result));
+                        }
+                    })
+                })
+                .collect();
+            workers
+                .into_iter()
+                .flat_map(|worker| worker.join().expect("download worker panicked"))
+                .collect()
+        });
+        results.sort_by_key(|(i, _)| *i);
+
+        let failed = results.iter().filter(|(_, r)| r.is_err()).count();
+        info!("Downloaded {} of {total} remote assets", total - failed);
+        results.into_iter().map(|(_, result)| result).collect()
+    }
+
+    fn unexpected_status(res: &ureq::Response) -> String {
+        format!("HTTP status {} {}", res.status(), res.status_text())
+    }
+
+    /// Whether a download failing with `status`, or without a response, may
+    /// succeed when retried.
+    fn is_temporary(status: Option<u16>) -> bool {
+        match status {
+            None => true,
+            Some(status) => matches!(status, 408 | 429 | 500..=599),
+        }
+    }
+
     #[cfg(test)]
     mod tests {
-        use super::ContentRetriever;
-        use crate::{resources::Asset, Error};
-        use tempfile::TempDir;
+        use std::{io::Read, sync::mpsc, sync::Mutex, time::Duration};
 
-        type BoxRead = Box<(dyn std::io::Read + Send + Sync + 'static)>;
+        use super::{
+            download_all, host_matches, CacheEntry, ContentRetriever, ResourceHandler, Response,
+        };
+        use crate::{config::Remote, resources::Asset, Error};
+        use tempfile::TempDir;
 
         #[test]
         fn download_success() {
-            use std::io::Read;
-
             struct TestHandler;
             impl ContentRetriever for TestHandler {
-                fn retrieve(&self, _url: &str) -> Result<BoxRead, Error> {
-                    Ok(Box::new("donwload content".as_bytes()))
+                fn retrieve(
+                    &self,
+                    _url: &str,
+                    _cached: Option<&CacheEntry>,
+                ) -> Result<Option<Response>, Error> {
+                    Ok(Some(Response::new("donwload content".as_bytes())))
                 }
             }
             let cr = TestHandler {};
             let a = temp_remote_asset("https://mdbook-epub.org/image.svg").unwrap();
-            let r = cr.download(&a);
+            let r = cr.download(&a, &Remote::default());
 
             assert!(r.is_ok());
             let mut buffer = String::new();
@@ -338,7 +957,11 @@ pub(crate) mod handler {
         fn download_fail_when_resource_not_exist() {
             struct TestHandler;
             impl ContentRetriever for TestHandler {
-                fn retrieve(&self, url: &str) -> Result<BoxRead, Error> {
+                fn retrieve(
+                    &self,
+                    url: &str,
+                    _cached: Option<&CacheEntry>,
+                ) -> Result<Option<Response>, Error> {
                     Err(Error::AssetFileNotFound(format!(
                         "Missing remote resource: {url}",
                     )))
@@ -346,7 +969,7 @@ pub(crate) mod handler {
             }
             let cr = TestHandler {};
             let a = temp_remote_asset("https://mdbook-epub.org/not-exist.svg").unwrap();
-            let r = cr.download(&a);
+            let r = cr.download(&a, &Remote::default());
 
             assert!(r.is_err());
             assert!(matches!(r.unwrap_err(), Error::AssetFileNotFound(_)));
@@ -357,17 +980,467 @@ pub(crate) mod handler {
         fn download_fail_with_unexpected_status() {
             struct TestHandler;
             impl ContentRetriever for TestHandler {
-                fn retrieve(&self, _url: &str) -> Result<BoxRead, Error> {
+                fn retrieve(
+                    &self,
+                    _url: &str,
+                    _cached: Option<&CacheEntry>,
+                ) -> Result<Option<Response>, Error> {
                     panic!("NOT 200 or 404")
                 }
             }
             let cr = TestHandler {};
             let a = temp_remote_asset("https://mdbook-epub.org/bad.svg").unwrap();
-            let r = cr.download(&a);
+            let r = cr.download(&a, &Remote::default());
 
             panic!("{}", r.unwrap_err().to_string());
         }
 
+        /// Serve one canned HTTP response per connection, returning the
+        /// base URL of the server and the requests it receives.
+        fn serve(responses: &[&'static str]) -> (String, mpsc::Receiver<String>) {
+            use std::io::{Read, Write};
+
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            let url = format!("http://{}", listener.local_addr().unwrap());
+            let responses = responses.to_vec();
+            let (sender, requests) = mpsc::channel();
+            std::thread::spawn(move || {
+                for response in responses {
+                    let (mut stream, _) = listener.accept().unwrap();
+                    let mut request = Vec::new();
+                    let mut buffer = [0; 1024];
+                    while !request.ends_with(b"\r\n\r\n") {
+                        let n = stream.read(&mut buffer).unwrap();
+                        request.extend_from_slice(&buffer[..n]);
+                    }
+                    let _ = sender.send(String::from_utf8_lossy(&request).into_owned());
+                    stream.write_all(response.as_bytes()).unwrap();
+                }
+            });
+            (url, requests)
+        }
+
+        fn handler(retries: u32, redirects: u32) -> ResourceHandler {
+            let config = Remote {
+                timeout: 5,
+                retries,
+                redirects,
+                ..Remote::default()
+            };
+            ResourceHandler {
+                backoff: Duration::ZERO,
+                ..ResourceHandler::new(&config)
+            }
+        }
+
+        const OK: &str = "HTTP/1.1 200 OK\r\nContent-Length: 7\r\nConnection: close\r\n\r\ncontent";
+        const UNAVAILABLE: &str =
+            "HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+
+        #[test]
+        fn retry_temporary_failures() {
+            let (url, _) = serve(&[UNAVAILABLE, OK]);
+            let mut content = String::new();
+            handler(1, 0)
+                .retrieve(&format!("{url}/image.png"), None)
+                .unwrap()
+                .unwrap()
+                .body
+                .read_to_string(&mut content)
+                .unwrap();
+            assert_eq!(content, "content");
+
+            let (url, _) = serve(&[UNAVAILABLE]);
+            let r = handler(0, 0).retrieve(&format!("{url}/image.png"), None);
+            assert!(matches!(
+                r.err().unwrap(),
+                Error::RemoteAsset {
+                    status: Some(503),
+                    ..
+                }
+            ));
+        }
+
+        #[test]
+        fn fail_without_retrying_client_errors() {
+            let (url, _) = serve(&[
+                "HTTP/1.1 403 Forbidden\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            ]);
+            let r = handler(2, 0).retrieve(&format!("{url}/image.png"), None);
+            let error = r.err().unwrap();
+            assert!(matches!(
+                error,
+                Error::RemoteAsset {
+                    status: Some(403),
+                    ..
+                }
+            ));
+            assert_eq!(
+                error.to_string(),
+                format!("Failed to download {url}/image.png: HTTP status 403 Forbidden")
+            );
+        }
+
+        #[test]
+        fn follow_redirects() {
+            const MOVED: &str = "HTTP/1.1 301 Moved Permanently\r\nLocation: /moved.png\r\n\
+                Content-Length: 0\r\nConnection: close\r\n\r\n";
+            let (url, _) = serve(&[MOVED, OK]);
+            assert!(handler(0, 5)
+                .retrieve(&format!("{url}/image.png"), None)
+                .is_ok());
+
+            let (url, _) = serve(&[MOVED]);
+            let r = handler(0, 0).retrieve(&format!("{url}/image.png"), None);
+            assert!(matches!(
+                r.err().unwrap(),
+                Error::RemoteAsset {
+                    status: Some(301),
+                    ..
+                }
+            ));
+        }
+
+        #[test]
+        fn download_failure_leaves_no_cache_file() {
+            struct TestHandler;
+            impl ContentRetriever for TestHandler {
+                fn retrieve(
+                    &self,
+                    _url: &str,
+                    _cached: Option<&CacheEntry>,
+                ) -> Result<Option<Response>, Error> {
+                    Ok(Some(Response::new(FailingReader)))
+                }
+            }
+            struct FailingReader;
+            impl std::io::Read for FailingReader {
+                fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+                    Err(std::io::Error::new(
+                        std::io::ErrorKind::ConnectionReset,
+                        "connection reset",
+                    ))
+                }
+            }
+            let a = temp_remote_asset("https://mdbook-epub.org/image.svg").unwrap();
+            let r = TestHandler.download(&a, &Remote::default());
+
+            assert!(matches!(r.unwrap_err(), Error::RemoteAsset { .. }));
+            let cache_dir = a.location_on_disk.parent().unwrap();
+            assert_eq!(std::fs::read_dir(cache_dir).unwrap().count(), 0);
+        }
+
+        #[test]
+        fn download_concurrently() {
+            use std::sync::atomic::{AtomicUsize, Ordering};
+
+            #[derive(Default)]
+            struct SlowHandler {
+                running: AtomicUsize,
+                most: AtomicUsize,
+            }
+            impl ContentRetriever for SlowHandler {
+                fn retrieve(
+                    &self,
+                    url: &str,
+                    _cached: Option<&CacheEntry>,
+                ) -> Result<Option<Response>, Error> {
+                    let running = self.running.fetch_add(1, Ordering::SeqCst) + 1;
+                    self.most.fetch_max(running, Ordering::SeqCst);
+                    std::thread::sleep(Duration::from_millis(50));
+                    self.running.fetch_sub(1, Ordering::SeqCst);
+                    if url.contains("broken") {
+                        Err(Error::AssetFileNotFound(url.to_string()))
+                    } else {
+                        Ok(Some(Response::new("content".as_bytes())))
+                    }
+                }
+            }
+            let dest_dir = TempDir::with_prefix("mdbook-epub").unwrap();
+            let assets: Vec<Asset> = (0..6)
+                .map(|i| {
+                    let name = if i == 4 { "broken" } else { "image" };
+                    let url = format!("https://mdbook-epub.org/{name}{i}.png");
+                    Asset::from_url(url::Url::parse(&url).unwrap(), dest_dir.path()).unwrap()
+                })
+                .collect();
+            let handler = SlowHandler::default();
+            let config = Remote {
+                workers: 3,
+                ..Remote::default()
+            };
+            let results = download_all(&handler, &assets.iter().collect::<Vec<_>>(), &config);
+
+            assert_eq!(handler.most.load(Ordering::SeqCst), 3);
+            let failed: Vec<usize> = (0..6).filter(|&i| results[i].is_err()).collect();
+            assert_eq!(failed, [4]);
+            assert!(assets[5].location_on_disk.is_file());
+        }
+
+        #[test]
+        fn revalidate_cached_assets() {
+            #[derive(Default)]
+            struct TestHandler {
+                requests: Mutex<Vec<Option<CacheEntry>>>,
+            }
+            impl ContentRetriever for TestHandler {
+                fn retrieve(
+                    &self,
+                    _url: &str,
+                    cached: Option<&CacheEntry>,
+                ) -> Result<Option<Response>, Error> {
+                    self.requests.lock().unwrap().push(cached.cloned());
+                    if cached.is_some() {
+                        return Ok(None);
+                    }
+                    Ok(Some(Response {
+                        etag: Some("\"v1\"".to_string()),
+                        ..Response::new("content".as_bytes())
+                    }))
+                }
+            }
+            let cache_dir = TempDir::with_prefix("mdbook-epub").unwrap();
+            let url = url::Url::parse("https://mdbook-epub.org/image.svg").unwrap();
+            let a = Asset::from_url(url, cache_dir.path()).unwrap();
+            let handler = TestHandler::default();
+
+            handler.download(&a, &Remote::default()).unwrap();
+            handler.download(&a, &Remote::default()).unwrap();
+            let stale = Remote {
+                cache_ttl: 0,
+                ..Remote::default()
+            };
+            handler.download(&a, &stale).unwrap();
+
+            let requests = handler.requests.lock().unwrap();
+            assert_eq!(requests.len(), 2);
+            assert!(requests[0].is_none());
+            let cached = requests[1].as_ref().unwrap();
+            assert_eq!(cached.url, "https://mdbook-epub.org/image.svg");
+            assert_eq!(cached.etag.as_deref(), Some("\"v1\""));
+            let content = std::fs::read_to_string(&a.location_on_disk).unwrap();
+            assert_eq!(content, "content");
+        }
+
+        #[test]
+        fn download_offline() {
+            struct OfflineHandler;
+            impl ContentRetriever for OfflineHandler {
+                fn retrieve(
+                    &self,
+                    url: &str,
+                    _cached: Option<&CacheEntry>,
+                ) -> Result<Option<Response>, Error> {
+                    panic!("{url} was requested offline")
+                }
+            }
+            let cache_dir = TempDir::with_prefix("mdbook-epub").unwrap();
+            let url = url::Url::parse("https://mdbook-epub.org/image.svg").unwrap();
+            let a = Asset::from_url(url, cache_dir.path()).unwrap();
+            let offline = Remote {
+                offline: true,
+                cache_ttl: 0,
+                ..Remote::default()
+            };
+
+            let error = OfflineHandler.download(&a, &offline).unwrap_err();
+            assert_eq!(
+                error.to_string(),
+                "https://mdbook-epub.org/image.svg isn't cached and can't be downloaded offline"
+            );
+            std::fs::write(&a.location_on_disk, "content").unwrap();
+            assert!(OfflineHandler.download(&a, &offline).is_ok());
+        }
+
+        #[test]
+        fn send_cache_validators() {
+            let (url, requests) = serve(&[
+                "HTTP/1.1 304 Not Modified\r\nConnection: close\r\n\r\n",
+                "HTTP/1.1 200 OK\r\nETag: \"v2\"\r\nLast-Modified: Sat, 17 Oct 2026 10:00:00 GMT\r\n\
+                Content-Length: 7\r\nConnection: close\r\n\r\ncontent",
+            ]);
+            let cached = CacheEntry {
+                etag: Some("\"v1\"".to_string()),
+                last_modified: Some("Fri, 16 Oct 2026 10:00:00 GMT".to_string()),
+                ..CacheEntry::default()
+            };
+            let handler = handler(0, 0);
+            let image = format!("{url}/image.png");
+            assert!(handler.retrieve(&image, Some(&cached)).unwrap().is_none());
+            let request = requests.recv().unwrap().to_lowercase();
+            assert!(request.contains("if-none-match: \"v1\"\r\n"));
+            assert!(request.contains("if-modified-since: fri, 16 oct 2026 10:00:00 gmt\r\n"));
+
+            let response = handler.retrieve(&image, None).unwrap().unwrap();
+            assert_eq!(response.etag.as_deref(), Some("\"v2\""));
+            assert_eq!(
+                response.last_modified.as_deref(),
+                Some("Sat, 17 Oct 2026 10:00:00 GMT")
+            );
+        }
+
+        #[test]
+        fn match_hosts() {
+            assert!(host_matches("example.com", "example.com"));
+            assert!(host_matches("Example.com", "example.com"));
+            assert!(!host_matches("example.com", "cdn.example.com"));
+            assert!(host_matches("*.example.com", "cdn.example.com"));
+            assert!(host_matches("*.example.com", "a.b.example.com"));
+            assert!(!host_matches("*.example.com", "example.com"));
+            assert!(!host_matches("*.example.com", "badexample.com"));
+        }
+
+        #[test]
+        fn restrict_remote_assets() {
+            struct TestHandler;
+            impl ContentRetriever for TestHandler {
+                fn retrieve(
+                    &self,
+                    url: &str,
+                    _cached: Option<&CacheEntry>,
+                ) -> Result<Option<Response>, Error> {
+                    let content_type = if url.ends_with(".svg") {
+                        "image/svg+xml"
+                    } else {
+                        "application/zip; charset=binary"
+                    };
+                    Ok(Some(Response {
+                        content_type: Some(content_type.to_string()),
+                        ..Response::new("0123456789".as_bytes())
+                    }))
+                }
+            }
+            let cache_dir = TempDir::with_prefix("mdbook-epub").unwrap();
+            let asset = |url: &str| {
+                Asset::from_url(url::Url::parse(url).unwrap(), cache_dir.path()).unwrap()
+            };
+            let config = Remote {
+                allowed_hosts: vec!["*.example.com".to_string()],
+                denied_hosts: vec!["ads.example.com".to_string()],
+                media_types: vec!["image/*".to_string()],
+                max_bytes: Some(10),
+                ..Remote::default()
+            };
+            let download = |url: &str, config: &Remote| {
+                TestHandler
+                    .download(&asset(url), config)
+                    .map_err(|e| e.to_string())
+            };
+
+            assert!(download("https://cdn.example.com/logo.svg", &config).is_ok());
+            assert_eq!(
+                download("https://example.org/logo.svg", &config).unwrap_err(),
+                "https://example.org/logo.svg isn't allowed: the host example.org isn't allowed"
+            );
+            assert_eq!(
+                download("https://ads.example.com/logo.svg", &config).unwrap_err(),
+                "https://ads.example.com/logo.svg isn't allowed: the host ads.example.com is denied"
+            );
+            assert_eq!(
+                download("https://cdn.example.com/logo.zip", &config).unwrap_err(),
+                "https://cdn.example.com/logo.zip isn't allowed: \
+                the media type \"application/zip\" isn't allowed"
+            );
+            assert!(!asset("https://cdn.example.com/logo.zip")
+                .location_on_disk
+                .exists());
+
+            let small = Remote {
+                max_bytes: Some(9),
+                ..config.clone()
+            };
+            assert_eq!(
+                download("https://cdn.example.com/large.svg", &small).unwrap_err(),
+                "https://cdn.example.com/large.svg isn't allowed: it's larger than 9 bytes"
+            );
+            assert!(!asset("https://cdn.example.com/large.svg")
+                .location_on_disk
+                .exists());
+            // cached copies are checked too
+            assert!(download("https://cdn.example.com/logo.svg", &small).is_err());
+        }
+
+        #[test]
+        fn check_stale_cached_copies() {
+            struct FailingHandler;
+            impl ContentRetriever for FailingHandler {
+                fn retrieve(
+                    &self,
+                    url: &str,
+                    _cached: Option<&CacheEntry>,
+                ) -> Result<Option<Response>, Error> {
+                    Err(Error::RemoteAsset {
+                        url: url.to_string(),
+                        status: Some(503),
+                        reason: "Service Unavailable".to_string(),
+                    })
+                }
+            }
+            let cache_dir = TempDir::with_prefix("mdbook-epub").unwrap();
+            let url = url::Url::parse("https://cdn.example.com/archive").unwrap();
+            let a = Asset::from_url(url, cache_dir.path()).unwrap();
+            std::fs::create_dir_all(a.location_on_disk.parent().unwrap()).unwrap();
+            std::fs::write(&a.location_on_disk, "0123456789").unwrap();
+            // cached by an earlier build with looser limits
+            let entry = CacheEntry {
+                content_type: Some("application/zip".to_string()),
+                ..CacheEntry::default()
+            };
+            entry.save(&a.location_on_disk).unwrap();
+            let download = |config: Remote| {
+                let config = Remote {
+                    cache_ttl: 0,
+                    ..config
+                };
+                FailingHandler
+                    .download(&a, &config)
+                    .map_err(|e| e.to_string())
+            };
+
+            assert!(download(Remote::default()).is_ok());
+            assert_eq!(
+                download(Remote {
+                    media_types: vec!["image/*".to_string()],
+                    ..Remote::default()
+                })
+                .unwrap_err(),
+                "https://cdn.example.com/archive isn't allowed: \
+                the media type \"application/zip\" isn't allowed"
+            );
+            assert_eq!(
+                download(Remote {
+                    max_bytes: Some(9),
+                    ..Remote::default()
+                })
+                .unwrap_err(),
+                "https://cdn.example.com/archive isn't allowed: it's larger than 9 bytes"
+            );
+        }
+
+        #[test]
+        fn type_cached_assets() {
+            let cache_dir = TempDir::with_prefix("mdbook-epub").unwrap();
+            let cache = |name: &str, content_type: &str, content: &[u8]| {
+                let path = cache_dir.path().join(name);
+                std::fs::write(&path, content).unwrap();
+                let entry = CacheEntry {
+                    content_type: Some(content_type.to_string()),
+                    ..CacheEntry::default()
+                };
+                entry.save(&path).unwrap();
+                super::media_type(&path).map(|mimetype| mimetype.to_string())
+            };
+            assert_eq!(
+                cache("badge", "image/svg+xml;charset=utf-8", b"<svg/>").as_deref(),
+                Some("image/svg+xml")
+            );
+            assert_eq!(
+                cache("image", "application/octet-stream", b"\x89PNG\r\n\x1a\n").as_deref(),
+                Some("image/png")
+            );
+            assert_eq!(cache("blob", "application/octet-stream", b"data"), None);
+        }
+
         fn temp_remote_asset(url: &str) -> Result<Asset, Error> {
             let dest_dir = TempDir::with_prefix("mdbook-epub")?;
             Asset::from_url(url::Url::parse(url).unwrap(), dest_dir.path())
@@ -403,6 +1476,90 @@ mod tests {
         assert_eq!(got, should_be);
     }
 
+    #[test]
+    fn find_media() {
+        let src = "<picture><source srcset=\"small.jpg 480w, large.jpg 1080w\" />\
+            <img src=\"small.jpg\" srcset=\"small.jpg, large.jpg 2x\" /></picture>\n\n\
+            <video poster=\"poster.png\"><source src=\"clip.mp4\" />\
+            <track src=\"clip.vtt\" /></video>\n\n\
+            Listen: <audio src=\"https://example.com/sample.mp3\"></audio>\n\n\
+            <object data=\"diagram.svg\"></object><embed src=\"applet.swf\" />\n\n\
+            <svg><image href=\"a.png\" /><image xlink:href=\"b.png\" /></svg>\n\n\
+            <div style=\"background: url('bg.png')\"><img src=\"data:image/png;base64,AA\" />\
+            </div>\n";
+
+        assert_eq!(
+            find_assets_in_markdown(src).unwrap(),
+            [
+                "a.png",
+                "applet.swf",
+                "b.png",
+                "bg.png",
+                "clip.mp4",
+                "clip.vtt",
+                "diagram.svg",
+                "https://example.com/sample.mp3",
+                "large.jpg",
+                "poster.png",
+                "small.jpg",
+            ]
+        );
+    }
+
+    #[test]
+    fn rewrite_media() {
+        let html = "<picture><source srcset=\"small.jpg 480w, large.jpg 1080w\" />\
+            <img src=small.jpg alt=\"small.jpg\" /></picture>\n\
+            <!-- <img src=\"a.png\"> --><video poster='poster.png'></video>\
+            <svg><image xlink:href=\"a.png\" /></svg><a href=\"a.png\">a.png</a>\n\
+            <div style=\"background: url('bg.png')\"></div>\n";
+
+        assert_eq!(
+            rewrite_assets_in_html(html, |link| {
+                (link != "large.jpg").then(|| format!("new/{link}"))
+            }),
+            "<picture><source srcset=\"new/small.jpg 480w, large.jpg 1080w\" />\
+            <img src=\"new/small.jpg\" alt=\"small.jpg\" /></picture>\n\
+            <!-- <img src=\"a.png\"> --><video poster=\"new/poster.png\"></video>\
+            <svg><image xlink:href=\"new/a.png\" /></svg><a href=\"a.png\">a.png</a>\n\
+            <div style=\"background: url(&quot;new/bg.png&quot;)\"></div>\n"
+        );
+    }
+
+    #[test]
+    fn detect_media_types() {
+        let sniff = |data: &[u8]| sniff_media_type(data).map(|mimetype| mimetype.to_string());
+        assert_eq!(sniff(b"\x89PNG\r\n\x1a\n").as_deref(), Some("image/png"));
+        assert_eq!(sniff(b"\xff\xd8\xff\xe0").as_deref(), Some("image/jpeg"));
+        assert_eq!(sniff(b"GIF89a").as_deref(), Some("image/gif"));
+        assert_eq!(
+            sniff(b"<?xml version=\"1.0\"?>\n<svg xmlns=\"http://www.w3.org/2000/svg\"/>")
+                .as_deref(),
+            Some("image/svg+xml")
+        );
+        assert_eq!(sniff(b"ID3\x04").as_deref(), Some("audio/mpeg"));
+        assert_eq!(sniff(b"\0\0\0\x18ftypmp42").as_deref(), Some("video/mp4"));
+        assert_eq!(sniff(b"wOF2").as_deref(), Some("font/woff2"));
+        assert_eq!(sniff(b"<html></html>"), None);
+    }
+
+    #[test]
+    fn retype_assets() {
+        let url = Url::parse("https://img.shields.io/badge/foo").unwrap();
+        let mut asset = Asset::from_url(url, Path::new("/tmp/cache")).unwrap();
+        assert_eq!(asset.mimetype, mime_guess::mime::APPLICATION_OCTET_STREAM);
+        asset.set_media_type(mime_guess::mime::IMAGE_SVG);
+        assert_eq!(asset.filename.extension().unwrap(), "svg");
+        assert_eq!(asset.mimetype, mime_guess::mime::IMAGE_SVG);
+
+        let url = Url::parse("https://cdn.test/photo.jpeg").unwrap();
+        let mut asset = Asset::from_url(url, Path::new("/tmp/cache")).unwrap();
+        asset.set_media_type(mime_guess::mime::IMAGE_JPEG);
+        assert_eq!(asset.filename.extension().unwrap(), "jpeg");
+        asset.set_media_type(mime_guess::mime::IMAGE_PNG);
+        assert_eq!(asset.filename.extension().unwrap(), "png");
+    }
+
     #[test]
     fn find_local_asset() {
         let link = "./rust-logo.png";
@@ -419,7 +1576,7 @@ mod tests {
             "parent_names": []}}]);
         let ctx = ctx_with_chapters(&chapters, &dest_dir).unwrap();
 
-        let mut assets = find(&ctx).unwrap();
+        let mut assets = find(&ctx, &Path::new(&dest_dir).join("cache")).unwrap();
         assert!(assets.len() == 2);
 
         fn assert_asset(a: Asset, link: &str, ctx: &RenderContext) {
@@ -455,7 +1612,7 @@ mod tests {
             "parent_names": []}}]);
         let ctx = ctx_with_chapters(&chapters, &dest_dir).unwrap();
 
-        let mut assets = find(&ctx).unwrap();
+        let mut assets = find(&ctx, &Path::new(&dest_dir).join("cache")).unwrap();
 
         assert!(assets.len() == 2);
         let got = assets.remove(link).unwrap();
@@ -480,7 +1637,9 @@ mod tests {
             "path": null,
             "parent_names": []}}]);
         let ctx = ctx_with_chapters(&chapters, &dest_dir).unwrap();
-        assert!(find(&ctx).unwrap().is_empty());
+        assert!(find(&ctx, &Path::new(&dest_dir).join("cache"))
+            .unwrap()
+            .is_empty());
     }
 
     #[test]
@@ -0,0 +1,577 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Display, Formatter};
+use std::fs::File;
+use std::io::{Read, Seek};
+use std::path::Path;
+
+use percent_encoding::percent_decode_str;
+use roxmltree::{Document, Node, ParsingOptions};
+use url::Url;
+use zip::{CompressionMethod, ZipArchive};
+
+use crate::Error;
+
+const MIMETYPE: &str = "application/epub+zip";
+const CONTAINER: &str = "META-INF/container.xml";
+
+/// How bad an [`Issue`] is.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Severity {
+    /// The book is broken, e.g. a link points to a file that doesn't exist.
+    Error,
+    /// The book works, but isn't quite right.
+    Warning,
+}
+
+/// A problem found in an EPUB.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Issue {
+    pub severity: Severity,
+    /// The file inside the EPUB the problem was found in.
+    pub location: String,
+    pub message: String,
+}
+
+impl Display for Issue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(f, "{}: {}: {}", severity, self.location, self.message)
+    }
+}
+
+/// Everything found wrong with an EPUB by [`validate`].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Report {
+    pub issues: Vec<Issue>,
+}
+
+impl Report {
+    pub fn errors(&self) -> impl Iterator<Item = &Issue> {
+        self.issues
+            .iter()
+            .filter(|issue| issue.severity == Severity::Error)
+    }
+
+    pub fn warnings(&self) -> impl Iterator<Item = &Issue> {
+        self.issues
+            .iter()
+            .filter(|issue| issue.severity == Severity::Warning)
+    }
+
+    /// Is the EPUB free of errors? Warnings are fine.
+    pub fn is_valid(&self) -> bool {
+        self.errors().next().is_none()
+    }
+
+    fn error(&mut self, location: &str, message: String) {
+        self.issues.push(Issue {
+            severity: Severity::Error,
+            location: location.to_string(),
+            message,
+        });
+    }
+
+    fn warning(&mut self, location: &str, message: String) {
+        self.issues.push(Issue {
+            severity: Severity::Warning,
+            location: location.to_string(),
+            message,
+        });
+    }
+}
+
+/// Check the structure of the EPUB file at `path`, without the need for the
+/// Java `epubcheck`.
+///
+/// This covers the `mimetype` file, the container and package documents, the
+/// consistency of manifest and spine, missing resources, dangling links and
+/// the well-formedness and unique ids of XHTML documents. Only files which
+/// can't be read as a zip archive at all are an `Err`.
+pub fn validate<P: AsRef<Path>>(path: P) -> Result<Report, Error> {
+    validate_reader(File::open(path)?)
+}
+
+/// Check the structure of an EPUB, see [`validate`].
+pub fn validate_reader<R: Read + Seek>(reader: R) -> Result<Report, Error> {
+    let mut archive = ZipArchive::new(reader)?;
+    let mut validator = Validator::default();
+
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i)?;
+        if i == 0 {
+            validator.check_mimetype(&mut file)?;
+        }
+        if file.is_dir() {
+            continue;
+        }
+        let mut content = Vec::new();
+        file.read_to_end(&mut content)?;
+        validator.files.insert(file.name().to_string(), content);
+    }
+
+    validator.check_package();
+    Ok(validator.report)
+}
+
+#[derive(Default)]
+struct Validator {
+    /// The content of every file in the archive by its path.
+    files: HashMap<String, Vec<u8>>,
+    report: Report,
+}
+
+/// A document of the manifest.
+struct Item {
+    path: String,
+    media_type: String,
+}
+
+impl Validator {
+    fn check_mimetype(&mut self, file: &mut zip::read::ZipFile) -> Result<(), Error> {
+        if file.name() != "mimetype" {
+            self.report.error(
+                file.name(),
+                "The `mimetype` file must be the first file of the archive".to_string(),
+            );
+            return Ok(());
+        }
+        if file.compression() != CompressionMethod::Stored {
+            self.report.error(
+                "mimetype",
+                "The `mimetype` file must not be compressed".to_string(),
+            );
+        }
+        let mut content = String::new();
+        file.read_to_string(&mut content)?;
+        if content != MIMETYPE {
+            self.report.error(
+                "mimetype",
+                format!("Expected `{MIMETYPE}` but found `{content}`"),
+            );
+        }
+        Ok(())
+    }
+
+    fn check_package(&mut self) {
+        if !self.files.contains_key("mimetype") {
+            self.report
+                .error("mimetype", "The `mimetype` file is missing".to_string());
+        }
+
+        let Some(opf_path) = self.rootfile() else {
+            return;
+        };
+        let Some(text) = self.text(&opf_path) else {
+            return;
+        };
+        let opf = match parse(&text) {
+            Ok(opf) => opf,
+            Err(e) => {
+                self.report.error(
+                    &opf_path,
+                    format!("The package document is not well-formed: {e}"),
+                );
+                return;
+            }
+        };
+        let root = opf.root_element();
+
+        let unique_identifier = root.attribute("unique-identifier").unwrap_or_default();
+        let has_identifier = root
+            .descendants()
+            .any(|n| n.has_tag_name("identifier") && n.attribute("id") == Some(unique_identifier));
+        if !has_identifier {
+            self.report.error(
+                &opf_path,
+                format!("The unique identifier `{unique_identifier}` is missing"),
+            );
+        }
+
+        let items = self.check_manifest(&opf_path, root);
+        self.check_spine(&opf_path, root, &items);
+
+        let mut ids = HashMap::new();
+        let mut links = Vec::new();
+        let mut documents: Vec<&Item> = items
+            .values()
+            .filter(|item| is_markup(&item.media_type))
+            .collect();
+        documents.sort_by(|a, b| a.path.cmp(&b.path));
+        for item in documents {
+            if let Some((doc_ids, doc_links)) = self.check_document(&item.path) {
+                ids.insert(item.path.clone(), doc_ids);
+                links.extend(doc_links.into_iter().map(|link| (item.path.clone(), link)));
+            }
+        }
+        for reference in root.descendants().filter(|n| n.has_tag_name("reference")) {
+            if let Some(href) = reference.attribute("href") {
+                links.push((opf_path.clone(), href.to_string()));
+            }
+        }
+        for (document, href) in links {
+            self.check_link(&document, &href, &ids);
+        }
+    }
+
+    /// Find the package document in the container.
+    fn rootfile(&mut self) -> Option<String> {
+        if !self.files.contains_key(CONTAINER) {
+            self.report
+                .error(CONTAINER, "The container file is missing".to_string());
+            return None;
+        }
+        let text = self.text(CONTAINER)?;
+        let container = match parse(&text) {
+            Ok(container) => container,
+            Err(e) => {
+                self.report
+                    .error(CONTAINER, format!("The container is not well-formed: {e}"));
+                return None;
+            }
+        };
+        let path = container
+            .descendants()
+            .find(|n| n.has_tag_name("rootfile"))
+            .and_then(|n| n.attribute("full-path"))
+            .map(str::to_string);
+        match path {
+            Some(path) if self.files.contains_key(&path) => Some(path),
+            Some(path) => {
+                self.report.error(
+                    CONTAINER,
+                    format!("The package document `{path}` is missing"),
+                );
+                None
+            }
+            None => {
+                self.report
+                    .error(CONTAINER, "No package document is listed".to_string());
+                None
+            }
+        }
+    }
+
+    /// Check every manifest item exists, returning them by id.
+    fn check_manifest(&mut self, opf_path: &str, root: Node) -> HashMap<String, Item> {
+        let mut items = HashMap::new();
+        let mut has_nav = false;
+
+        for node in root.descendants().filter(|n| n.has_tag_name("item")) {
+            let id = node.attribute("id").unwrap_or_default();
+            let href = node.attribute("href").unwrap_or_default();
+            let Some((path, _)) = resolve(opf_path, href) else {
+                self.report.error(
+                    opf_path,
+                    format!("Manifest item `{id}` has an invalid href `{href}`"),
+                );
+                continue;
+            };
+            if !self.files.contains_key(&path) {
+                self.report.error(
+                    opf_path,
+                    format!("Manifest item `{id}` refers to the missing file `{path}`"),
+                );
+            }
+            has_nav |= node
+                .attribute("properties")
+                .is_some_and(|p| p.split_whitespace().any(|p| p == "nav"));
+            let item = Item {
+                path,
+                media_type: node.attribute("media-type").unwrap_or_default().to_string(),
+            };
+            if items.insert(id.to_string(), item).is_some() {
+                self.report
+                    .error(opf_path, format!("Duplicate manifest item id `{id}`"));
+            }
+        }
+
+        if root
+            .attribute("version")
+            .is_some_and(|v| v.starts_with('3'))
+            && !has_nav
+        {
+            self.report.error(
+                opf_path,
+                "No manifest item is the navigation document".to_string(),
+            );
+        }
+
+        let manifested: HashSet<&str> = items.values().map(|item| item.path.as_str()).collect();
+        let mut unlisted: Vec<&String> = self
+            .files
+            .keys()
+            .filter(|name| {
+                !manifested.contains(name.as_str())
+                    && *name != "mimetype"
+                    && *name != opf_path
+                    && !name.starts_with("META-INF/")
+            })
+            .collect();
+        unlisted.sort();
+        for name in unlisted {
+            self.report.warning(
+                opf_path,
+                format!("The file `{name}` is not listed in the manifest"),
+            );
+        }
+
+        items
+    }
+
+    fn check_spine(&mut self, opf_path: &str, root: Node, items: &HashMap<String, Item>) {
+        let Some(spine) = root.descendants().find(|n| n.has_tag_name("spine")) else {
+            self.report
+                .error(opf_path, "The spine is missing".to_string());
+            return;
+        };
+        if let Some(toc) = spine.attribute("toc") {
+            if !items.contains_key(toc) {
+                self.report.error(
+                    opf_path,
+                    format!("The spine's table of contents `{toc}` is not in the manifest"),
+                );
+            }
+        }
+
+        let itemrefs: Vec<Node> = spine
+            .children()
+            .filter(|n| n.has_tag_name("itemref"))
+            .collect();
+        if itemrefs.is_empty() {
+            self.report
+                .error(opf_path, "The spine is empty".to_string());
+        }
+        for itemref in itemrefs {
+            let idref = itemref.attribute("idref").unwrap_or_default();
+            match items.get(idref) {
+                None => self.report.error(
+                    opf_path,
+                    format!("Spine item `{idref}` is not in the manifest"),
+                ),
+                Some(item) if !is_markup(&item.media_type) => self.report.warning(
+                    opf_path,
+                    format!(
+                        "Spine item `{idref}` is a `{}` and not a content document",
+                        item.media_type
+                    ),
+                ),
+                Some(_) => {}
+            }
+        }
+    }
+
+    /// Check a content document is well-formed and has unique ids, returning
+    /// its ids and links.
+    fn check_document(&mut self, path: &str) -> Option<(HashSet<String>, Vec<String>)> {
+        let text = self.text(path)?;
+        let doc = match parse(&text) {
+            Ok(doc) => doc,
+            Err(e) => {
+                self.report
+                    .error(path, format!("The document is not well-formed: {e}"));
+                return None;
+            }
+        };
+
+        let mut ids = HashSet::new();
+        let mut links = Vec::new();
+        for node in doc.descendants().filter(Node::is_element) {
+            for attr in node.attributes() {
+                match attr.name() {
+                    "id" if !ids.insert(attr.value().to_string()) => {
+                        self.report
+                            .error(path, format!("Duplicate id `{}`", attr.value()));
+                    }
+                    "href" | "src" | "poster" => links.push(attr.value().to_string()),
+                    _ => {}
+                }
+            }
+        }
+        Some((ids, links))
+    }
+
+    fn check_link(&mut self, document: &str, href: &str, ids: &HashMap<String, HashSet<String>>) {
+        if href.is_empty() || Url::parse(href).is_ok() {
+            // links to the web, `mailto:`, `data:` URLs etc.
+            return;
+        }
+        let Some((path, fragment)) = resolve(document, href) else {
+            self.report
+                .error(document, format!("Invalid link `{href}`"));
+            return;
+        };
+        if !self.files.contains_key(&path) {
+            self.report.error(
+                document,
+                format!("Dangling link `{href}`, `{path}` is missing"),
+            );
+            return;
+        }
+        if let (Some(fragment), Some(ids)) = (fragment, ids.get(&path)) {
+            if !fragment.is_empty() && !ids.contains(&fragment) {
+                self.report.error(
+                    document,
+                    format!("Dangling link `{href}`, there is no `{fragment}` in `{path}`"),
+                );
+            }
+        }
+    }
+
+    /// The content of a file as text, reporting it if it isn't UTF-8.
+    fn text(&mut self, path: &str) -> Option<String> {
+        let content = self.files.get(path)?;
+        match String::from_utf8(content.clone()) {
+            Ok(text) => Some(text),
+            Err(_) => {
+                self.report
+                    .error(path, "The document is not UTF-8 encoded".to_string());
+                None
+            }
+        }
+    }
+}
+
+fn parse(text: &str) -> Result<Document<'_>, roxmltree::Error> {
+    let options = ParsingOptions {
+        allow_dtd: true,
+        ..ParsingOptions::default()
+    };
+    Document::parse_with_options(text, options)
+}
+
+/// Documents which are checked for well-formedness and links.
+fn is_markup(media_type: &str) -> bool {
+    matches!(
+        media_type,
+        "application/xhtml+xml" | "image/svg+xml" | "application/x-dtbncx+xml"
+    )
+}
+
+/// Resolve an href in the document at `base` to the path of the file inside
+/// the archive and the fragment, both percent-decoded.
+fn resolve(base: &str, href: &str) -> Option<(String, Option<String>)> {
+    let base = Url::parse("http://epub/").ok()?.join(base).ok()?;
+    let url = base.join(href).ok()?;
+    let path = percent_decode(url.path().trim_start_matches('/'));
+    Some((path, url.fragment().map(percent_decode)))
+}
+
+fn percent_decode(text: &str) -> String {
+    percent_decode_str(text).decode_utf8_lossy().into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Write};
+
+    use zip::write::SimpleFileOptions;
+    use zip::ZipWriter;
+
+    use super::*;
+
+    const CONTAINER_XML: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+  <rootfiles>
+    <rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/>
+  </rootfiles>
+</container>"#;
+
+    const OPF: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<package version="3.0" xmlns="http://www.idpf.org/2007/opf" unique-identifier="id">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+    <dc:identifier id="id">urn:uuid:1234</dc:identifier>
+  </metadata>
+  <manifest>
+    <item id="nav" href="nav.xhtml" media-type="application/xhtml+xml" properties="nav"/>
+    <item id="one" href="text/one%20page.xhtml" media-type="application/xhtml+xml"/>
+    <item id="logo" href="logo.png" media-type="image/png"/>
+  </manifest>
+  <spine>
+    <itemref idref="one"/>
+  </spine>
+</package>"#;
+
+    fn xhtml(body: &str) -> String {
+        format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<!DOCTYPE html>\n\
+            <html xmlns=\"http://www.w3.org/1999/xhtml\"><head><title>T</title></head>\
+            <body>{body}</body></html>"
+        )
+    }
+
+    fn epub(files: &[(&str, &str)]) -> Vec<u8> {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        let stored = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+        zip.start_file("mimetype", stored).unwrap();
+        zip.write_all(MIMETYPE.as_bytes()).unwrap();
+        for (name, content) in files {
+            zip.start_file(*name, SimpleFileOptions::default()).unwrap();
+            zip.write_all(content.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap().into_inner()
+    }
+
+    fn messages(report: &Report) -> Vec<String> {
+        report.issues.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn valid_epub() {
+        let nav = xhtml("<nav><a href=\"text/one%20page.xhtml#top\">One</a></nav>");
+        let one = xhtml("<h1 id=\"top\">One</h1><img src=\"../logo.png\"/><a href=\"https://rust-lang.org\">web</a>");
+        let epub = epub(&[
+            (CONTAINER, CONTAINER_XML),
+            ("OEBPS/content.opf", OPF),
+            ("OEBPS/nav.xhtml", &nav),
+            ("OEBPS/text/one page.xhtml", &one),
+            ("OEBPS/logo.png", "png"),
+        ]);
+        let report = validate_reader(Cursor::new(epub)).unwrap();
+        assert_eq!(messages(&report), Vec::<String>::new());
+        assert!(report.is_valid());
+    }
+
+    #[test]
+    fn broken_epub() {
+        let nav = xhtml("<nav><a href=\"text/one%20page.xhtml#missing\">One</a></nav>");
+        let one = xhtml("<p id=\"a\">One</p><p id=\"a\"><img src=\"../cover.png\"/></p>");
+        let opf = OPF.replace("<itemref idref=\"one\"/>", "<itemref idref=\"two\"/>").replace(
+            "</manifest>",
+            "<item id=\"bad\" href=\"bad.xhtml\" media-type=\"application/xhtml+xml\"/></manifest>",
+        );
+        let epub = epub(&[
+            (CONTAINER, CONTAINER_XML),
+            ("OEBPS/content.opf", &opf),
+            ("OEBPS/nav.xhtml", &nav),
+            ("OEBPS/text/one page.xhtml", &one),
+            ("OEBPS/bad.xhtml", &xhtml("<p>unclosed<br></p>")),
+            ("OEBPS/notes.txt", "not listed"),
+        ]);
+        let report = validate_reader(Cursor::new(epub)).unwrap();
+        assert!(!report.is_valid());
+        assert_eq!(report.warnings().count(), 1);
+        let messages = messages(&report);
+        assert_eq!(messages.len(), 7);
+        assert_eq!(
+            messages[..3],
+            [
+                "error: OEBPS/content.opf: Manifest item `logo` refers to the missing file `OEBPS/logo.png`",
+                "warning: OEBPS/content.opf: The file `OEBPS/notes.txt` is not listed in the manifest",
+                "error: OEBPS/content.opf: Spine item `two` is not in the manifest",
+            ]
+        );
+        assert!(messages[3].starts_with("error: OEBPS/bad.xhtml: The document is not well-formed:"));
+        assert_eq!(
+            messages[4..],
+            [
+                "error: OEBPS/text/one page.xhtml: Duplicate id `a`",
+                "error: OEBPS/nav.xhtml: Dangling link `text/one%20page.xhtml#missing`, \
+                there is no `missing` in `OEBPS/text/one page.xhtml`",
+                "error: OEBPS/text/one page.xhtml: Dangling link `../cover.png`, `OEBPS/cover.png` is missing",
+            ]
+        );
+    }
+}
@@ -0,0 +1,782 @@
+//! Turn the HTML of a rendered chapter into well-formed XHTML.
+//!
+//! Raw HTML in a chapter is copied verbatim by pulldown-cmark, so `<br>`,
+//! unclosed `<p>`s, `&nbsp;` or comments containing `--` would end up in the
+//! XHTML documents of the book, which strict reading systems refuse to open.
+
+/// Elements which never have content and are written as `<br />`.
+const VOID_ELEMENTS: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param", "source",
+    "track", "wbr",
+];
+
+/// Elements whose end tag may be left out in HTML.
+const OPTIONAL_END_TAGS: &[&str] = &[
+    "p", "li", "dt", "dd", "tr", "td", "th", "thead", "tbody", "tfoot", "option", "colgroup",
+];
+
+/// Elements starting a block, which implicitly end an open `<p>`.
+const CLOSES_PARAGRAPH: &[&str] = &[
+    "address",
+    "article",
+    "aside",
+    "blockquote",
+    "details",
+    "div",
+    "dl",
+    "fieldset",
+    "figcaption",
+    "figure",
+    "footer",
+    "form",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "header",
+    "hr",
+    "main",
+    "nav",
+    "ol",
+    "p",
+    "pre",
+    "section",
+    "table",
+    "ul",
+];
+
+/// The HTML 4 entities which XML doesn't know, along with `&check;`, by
+/// their code point.
+const ENTITIES: &[(&str, u32)] = &[
+    ("nbsp", 160),
+    ("iexcl", 161),
+    ("cent", 162),
+    ("pound", 163),
+    ("curren", 164),
+    ("yen", 165),
+    ("brvbar", 166),
+    ("sect", 167),
+    ("uml", 168),
+    ("copy", 169),
+    ("ordf", 170),
+    ("laquo", 171),
+    ("not", 172),
+    ("shy", 173),
+    ("reg", 174),
+    ("macr", 175),
+    ("deg", 176),
+    ("plusmn", 177),
+    ("sup2", 178),
+    ("sup3", 179),
+    ("acute", 180),
+    ("micro", 181),
+    ("para", 182),
+    ("middot", 183),
+    ("cedil", 184),
+    ("sup1", 185),
+    ("ordm", 186),
+    ("raquo", 187),
+    ("frac14", 188),
+    ("frac12", 189),
+    ("frac34", 190),
+    ("iquest", 191),
+    ("Agrave", 192),
+    ("Aacute", 193),
+    ("Acirc", 194),
+    ("Atilde", 195),
+    ("Auml", 196),
+    ("Aring", 197),
+    ("AElig", 198),
+    ("Ccedil", 199),
+    ("Egrave", 200),
+    ("Eacute", 201),
+    ("Ecirc", 202),
+    ("Euml", 203),
+    ("Igrave", 204),
+    ("Iacute", 205),
+    ("Icirc", 206),
+    ("Iuml", 207),
+    ("ETH", 208),
+    ("Ntilde", 209),
+    ("Ograve", 210),
+    ("Oacute", 211),
+    ("Ocirc", 212),
+    ("Otilde", 213),
+    ("Ouml", 214),
+    ("times", 215),
+    ("Oslash", 216),
+    ("Ugrave", 217),
+    ("Uacute", 218),
+    ("Ucirc", 219),
+    ("Uuml", 220),
+    ("Yacute", 221),
+    ("THORN", 222),
+    ("szlig", 223),
+    ("agrave", 224),
+    ("aacute", 225),
+    ("acirc", 226),
+    ("atilde", 227),
+    ("auml", 228),
+    ("aring", 229),
+    ("aelig", 230),
+    ("ccedil", 231),
+    ("egrave", 232),
+    ("eacute", 233),
+    ("ecirc", 234),
+    ("euml", 235),
+    ("igrave", 236),
+    ("iacute", 237),
+    ("icirc", 238),
+    ("iuml", 239),
+    ("eth", 240),
+    ("ntilde", 241),
+    ("ograve", 242),
+    ("oacute", 243),
+    ("ocirc", 244),
+    ("otilde", 245),
+    ("ouml", 246),
+    ("divide", 247),
+    ("oslash", 248),
+    ("ugrave", 249),
+    ("uacute", 250),
+    ("ucirc", 251),
+    ("uuml", 252),
+    ("yacute", 253),
+    ("thorn", 254),
+    ("yuml", 255),
+    ("OElig", 338),
+    ("oelig", 339),
+    ("Scaron", 352),
+    ("scaron", 353),
+    ("Yuml", 376),
+    ("fnof", 402),
+    ("circ", 710),
+    ("tilde", 732),
+    ("Alpha", 913),
+    ("Beta", 914),
+    ("Gamma", 915),
+    ("Delta", 916),
+    ("Epsilon", 917),
+    ("Zeta", 918),
+    ("Eta", 919),
+    ("Theta", 920),
+    ("Iota", 921),
+    ("Kappa", 922),
+    ("Lambda", 923),
+    ("Mu", 924),
+    ("Nu", 925),
+    ("Xi", 926),
+    ("Omicron", 927),
+    ("Pi", 928),
+    ("Rho", 929),
+    ("Sigma", 931),
+    ("Tau", 932),
+    ("Upsilon", 933),
+    ("Phi", 934),
+    ("Chi", 935),
+    ("Psi", 936),
+    ("Omega", 937),
+    ("alpha", 945),
+    ("beta", 946),
+    ("gamma", 947),
+    ("delta", 948),
+    ("epsilon", 949),
+    ("zeta", 950),
+    ("eta", 951),
+    ("theta", 952),
+    ("iota", 953),
+    ("kappa", 954),
+    ("lambda", 955),
+    ("mu", 956),
+    ("nu", 957),
+    ("xi", 958),
+    ("omicron", 959),
+    ("pi", 960),
+    ("rho", 961),
+    ("sigmaf", 962),
+    ("sigma", 963),
+    ("tau", 964),
+    ("upsilon", 965),
+    ("phi", 966),
+    ("chi", 967),
+    ("psi", 968),
+    ("omega", 969),
+    ("thetasym", 977),
+    ("upsih", 978),
+    ("piv", 982),
+    ("ensp", 8194),
+    ("emsp", 8195),
+    ("thinsp", 8201),
+    ("zwnj", 8204),
+    ("zwj", 8205),
+    ("lrm", 8206),
+    ("rlm", 8207),
+    ("ndash", 8211),
+    ("mdash", 8212),
+    ("lsquo", 8216),
+    ("rsquo", 8217),
+    ("sbquo", 8218),
+    ("ldquo", 8220),
+    ("rdquo", 8221),
+    ("bdquo", 8222),
+    ("dagger", 8224),
+    ("Dagger", 8225),
+    ("bull", 8226),
+    ("hellip", 8230),
+    ("permil", 8240),
+    ("prime", 8242),
+    ("Prime", 8243),
+    ("lsaquo", 8249),
+    ("rsaquo", 8250),
+    ("oline", 8254),
+    ("frasl", 8260),
+    ("euro", 8364),
+    ("image", 8465),
+    ("weierp", 8472),
+    ("real", 8476),
+    ("trade", 8482),
+    ("alefsym", 8501),
+    ("larr", 8592),
+    ("uarr", 8593),
+    ("rarr", 8594),
+    ("darr", 8595),
+    ("harr", 8596),
+    ("crarr", 8629),
+    ("lArr", 8656),
+    ("uArr", 8657),
+    ("rArr", 8658),
+    ("dArr", 8659),
+    ("hArr", 8660),
+    ("forall", 8704),
+    ("part", 8706),
+    ("exist", 8707),
+    ("empty", 8709),
+    ("nabla", 8711),
+    ("isin", 8712),
+    ("notin", 8713),
+    ("ni", 8715),
+    ("prod", 8719),
+    ("sum", 8721),
+    ("minus", 8722),
+    ("lowast", 8727),
+    ("radic", 8730),
+    ("prop", 8733),
+    ("infin", 8734),
+    ("ang", 8736),
+    ("and", 8743),
+    ("or", 8744),
+    ("cap", 8745),
+    ("cup", 8746),
+    ("int", 8747),
+    ("there4", 8756),
+    ("sim", 8764),
+    ("cong", 8773),
+    ("asymp", 8776),
+    ("ne", 8800),
+    ("equiv", 8801),
+    ("le", 8804),
+    ("ge", 8805),
+    ("sub", 8834),
+    ("sup", 8835),
+    ("nsub", 8836),
+    ("sube", 8838),
+    ("supe", 8839),
+    ("oplus", 8853),
+    ("otimes", 8855),
+    ("perp", 8869),
+    ("sdot", 8901),
+    ("lceil", 8968),
+    ("rceil", 8969),
+    ("lfloor", 8970),
+    ("rfloor", 8971),
+    ("lang", 9001),
+    ("rang", 9002),
+    ("loz", 9674),
+    ("spades", 9824),
+    ("clubs", 9827),
+    ("hearts", 9829),
+    ("diams", 9830),
+    ("check", 10003),
+];
+
+/// Whether XML allows a character, which excludes most control characters,
+/// surrogates and the noncharacters U+FFFE and U+FFFF.
+fn is_xml_char(c: u32) -> bool {
+    matches!(c, 0x9 | 0xA | 0xD | 0x20..=0xD7FF | 0xE000..=0xFFFD | 0x10000..=0x10FFFF)
+}
+
+/// Length of the snippets of the original HTML in warnings.
+const SNIPPET_LEN: usize = 60;
+
+/// Normalise `html` into well-formed XHTML, returning it along with a
+/// warning for every fix that changed more than the spelling.
+pub(crate) fn normalize(html: &str) -> (String, Vec<String>) {
+    let mut normalizer = Normalizer {
+        out: String::with_capacity(html.len()),
+        open: Vec::new(),
+        warnings: Vec::new(),
+    };
+    normalizer.run(html);
+    (normalizer.out, normalizer.warnings)
+}
+
+struct Normalizer {
+    out: String,
+    /// The open elements, innermost last.
+    open: Vec<String>,
+    warnings: Vec<String>,
+}
+
+impl Normalizer {
+    fn run(&mut self, html: &str) {
+        let mut rest = html;
+        while let Some(i) = rest.find(['<', '&']) {
+            self.out.push_str(&rest[..i]);
+            rest = &rest[i..];
+            rest = if rest.starts_with('&') {
+                let len = self.entity(rest, rest);
+                &rest[len..]
+            } else if let Some(comment) = rest.strip_prefix("<!--") {
+                self.comment(comment)
+            } else if rest.starts_with("<![CDATA[") {
+                let end = rest.find("]]>").map_or(rest.len(), |i| i + 3);
+                self.out.push_str(&rest[..end]);
+                &rest[end..]
+            } else if rest.starts_with("<!") || rest.starts_with("<?") {
+                let end = rest.find('>').map_or(rest.len(), |i| i + 1);
+                self.warn("Removed a declaration", &rest[..end]);
+                &rest[end..]
+            } else {
+                match self.tag(rest) {
+                    Some(len) => &rest[len..],
+                    None => {
+                        self.warn("Escaped a stray `<`", rest);
+                        self.out.push_str("&lt;");
+                        &rest[1..]
+                    }
+                }
+            };
+        }
+        self.out.push_str(rest);
+
+        while let Some(name) = self.open.pop() {
+            if !OPTIONAL_END_TAGS.contains(&name.as_str()) {
+                self.warnings
+                    .push(format!("Closed `<{name}>` which is never closed"));
+            }
+            self.end_tag(&name);
+        }
+    }
+
+    /// Write the entity at the start of `text`, returning its length. Fixes
+    /// are reported with the `snippet`.
+    fn entity(&mut self, text: &str, snippet: &str) -> usize {
+        let body = &text[1..];
+        let len = body
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '#'))
+            .unwrap_or(body.len());
+        if len == 0 || !body[len..].starts_with(';') {
+            self.warn("Escaped a stray `&`", snippet);
+            self.out.push_str("&amp;");
+            return 1;
+        }
+
+        let name = &body[..len];
+        let code_point = match name.strip_prefix('#') {
+            Some(number) => match number.strip_prefix(['x', 'X']) {
+                Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                None => number.parse().ok(),
+            }
+            .filter(|&c| is_xml_char(c)),
+            None => ENTITIES
+                .iter()
+                .find(|(entity, _)| *entity == name)
+                .map(|(_, c)| *c),
+        };
+        if matches!(name, "amp" | "lt" | "gt" | "quot" | "apos") {
+            self.out.push_str(&text[..len + 2]);
+        } else if let Some(c) = code_point {
+            self.out.push_str(&format!("&#{c};"));
+        } else if name.starts_with('#') {
+            self.warn("Escaped a reference to a character XML forbids", snippet);
+            self.out.push_str("&amp;");
+            return 1;
+        } else {
+            self.warn("Escaped an unknown entity", snippet);
+            self.out.push_str("&amp;");
+            return 1;
+        }
+        len + 2
+    }
+
+    /// Write the comment following `<!--` if it's valid XML, returning the
+    /// rest of the text after it.
+    fn comment<'t>(&mut self, text: &'t str) -> &'t str {
+        let Some(end) = text.find("-->") else {
+            self.warn("Removed an unterminated comment", text);
+            return "";
+        };
+        let content = &text[..end];
+        if content.contains("--") || content.ends_with('-') {
+            // `--` isn't allowed in XML comments, but it's just a comment
+            self.warn("Removed a comment containing `--`", content);
+        } else {
+            self.out.push_str("<!--");
+            self.out.push_str(content);
+            self.out.push_str("-->");
+        }
+        &text[end + 3..]
+    }
+
+    /// Write the start or end tag at the start of `text`, returning its
+    /// length, or `None` if it's no tag at all.
+    fn tag(&mut self, text: &str) -> Option<usize> {
+        let (is_end, name_start) = match text.strip_prefix("</") {
+            Some(_) => (true, 2),
+            None => (false, 1),
+        };
+        if !text[name_start..].starts_with(|c: char| c.is_ascii_alphabetic()) {
+            return None;
+        }
+        let name_len = text[name_start..]
+            .find(|c: char| c.is_whitespace() || c == '/' || c == '>')
+            .unwrap_or(text.len() - name_start);
+        let raw_name = &text[name_start..name_start + name_len];
+
+        let (attributes, self_closing, len) = parse_attributes(&text[name_start + name_len..])?;
+        let len = name_start + name_len + len;
+        let snippet = &text[..len];
+
+        let foreign = self.in_foreign_content() || matches!(raw_name, "svg" | "math");
+        let name = if foreign {
+            raw_name.to_string()
+        } else {
+            raw_name.to_ascii_lowercase()
+        };
+
+        if is_end {
+            self.close(&name, snippet);
+            return Some(len);
+        }
+
+        if !foreign {
+            self.close_implied(&name);
+        }
+        self.out.push('<');
+        self.out.push_str(&name);
+        let mut seen = Vec::new();
+        for (attr, value) in attributes {
+            let attr = if foreign {
+                attr.to_string()
+            } else {
+                attr.to_ascii_lowercase()
+            };
+            if seen.contains(&attr) {
+                self.warn("Removed a duplicate attribute", snippet);
+                continue;
+            }
+            // boolean attributes like `controls` need a value in XML
+            let value = value.unwrap_or(&attr).to_string();
+            self.out.push(' ');
+            self.out.push_str(&attr);
+            self.out.push_str("=\"");
+            self.attribute_value(&value, snippet);
+            self.out.push('"');
+            seen.push(attr);
+        }
+
+        if VOID_ELEMENTS.contains(&name.as_str()) || (foreign && self_closing) {
+            self.out.push_str(" />");
+        } else if self_closing {
+            self.out.push_str("></");
+            self.out.push_str(&name);
+            self.out.push('>');
+        } else {
+            self.out.push('>');
+            if matches!(name.as_str(), "script" | "style") {
+                return Some(len + self.raw_text(&name, &text[len..]));
+            }
+            self.open.push(name);
+        }
+        Some(len)
+    }
+
+    /// Write an attribute value, escaping markup characters.
+    fn attribute_value(&mut self, value: &str, snippet: &str) {
+        let mut rest = value;
+        while let Some(i) = rest.find(['&', '<', '"']) {
+            self.out.push_str(&rest[..i]);
+            rest = &rest[i..];
+            let len = match rest.as_bytes()[0] {
+                b'&' => self.entity(rest, snippet),
+                b'<' => {
+                    self.out.push_str("&lt;");
+                    1
+                }
+                _ => {
+                    self.out.push_str("&quot;");
+                    1
+                }
+            };
+            rest = &rest[len..];
+        }
+        self.out.push_str(rest);
+    }
+
+    /// Write the content of a `<script>` or `<style>` element, which isn't
+    /// markup in HTML, returning its length including the end tag.
+    fn raw_text(&mut self, name: &str, text: &str) -> usize {
+        let end_tag = format!("</{name}");
+        let end = text
+            .char_indices()
+            .map(|(i, _)| i)
+            .find(|&i| {
+                text[i..]
+                    .get(..end_tag.len())
+                    .is_some_and(|t| t.eq_ignore_ascii_case(&end_tag))
+            })
+            .unwrap_or(text.len());
+        let content = &text[..end];
+        if content.contains(['<', '&']) {
+            // a `]]>` in the content is split across two CDATA sections
+            self.out.push_str("<![CDATA[");
+            self.out
+                .push_str(&content.replace("]]>", "]]]]><![CDATA[>"));
+            self.out.push_str("]]>");
+        } else {
+            self.out.push_str(content);
+        }
+        self.end_tag(name);
+        text[end..].find('>').map_or(text.len(), |i| end + i + 1)
+    }
+
+    /// Handle the end tag of `name`, closing all elements left open inside
+    /// it or dropping it if the element isn't open.
+    fn close(&mut self, name: &str, snippet: &str) {
+        if VOID_ELEMENTS.contains(&name) {
+            self.warn("Removed the end tag of an empty element", snippet);
+            return;
+        }
+        let Some(position) = self.open.iter().rposition(|open| open == name) else {
+            self.warn("Removed an end tag without start tag", snippet);
+            return;
+        };
+        while self.open.len() > position + 1 {
+            let inner = self.open.pop().unwrap_or_default();
+            if !OPTIONAL_END_TAGS.contains(&inner.as_str()) {
+                self.warn(&format!("Closed `<{inner}>` before the end tag"), snippet);
+            }
+            self.end_tag(&inner);
+        }
+        self.open.pop();
+        self.end_tag(name);
+    }
+
+    /// Close the elements whose end tags HTML allows to leave out when the
+    /// element `name` starts.
+    fn close_implied(&mut self, name: &str) {
+        let (closes, scope): (&[&str], &[&str]) = match name {
+            "li" => (&["li", "p"], &["ul", "ol"]),
+            "dt" | "dd" => (&["dt", "dd", "p"], &["dl"]),
+            "tr" => (&["tr", "td", "th"], &["table", "thead", "tbody", "tfoot"]),
+            "td" | "th" => (&["td", "th"], &["tr", "table"]),
+            "thead" | "tbody" | "tfoot" => {
+                (&["thead", "tbody", "tfoot", "tr", "td", "th"], &["table"])
+            }
+            "option" => (&["option"], &["select", "datalist"]),
+            _ if CLOSES_PARAGRAPH.contains(&name) => (
+                &["p"],
+                &["td", "th", "caption", "table", "button", "object"],
+            ),
+            _ => return,
+        };
+        // only close what's open since the enclosing list, table etc.
+        let start = self
+            .open
+            .iter()
+            .rposition(|open| scope.contains(&open.as_str()))
+            .map_or(0, |i| i + 1);
+        if let Some(i) = self.open[start..]
+            .iter()
+            .position(|open| closes.contains(&open.as_str()))
+        {
+            // the elements inside are closed too, e.g. `<li><p>one<li>two`
+            while self.open.len() > start + i {
+                let inner = self.open.pop().unwrap_or_default();
+                self.end_tag(&inner);
+            }
+        }
+    }
+
+    fn in_foreign_content(&self) -> bool {
+        self.open.iter().any(|name| name == "svg" || name == "math")
+    }
+
+    fn end_tag(&mut self, name: &str) {
+        self.out.push_str("</");
+        self.out.push_str(name);
+        self.out.push('>');
+    }
+
+    fn warn(&mut self, message: &str, snippet: &str) {
+        self.warnings
+            .push(format!("{}: `{}`", message, truncate(snippet)));
+    }
+}
+
+/// Parse the attributes of a tag up to and including its `>`, returning them
+/// along with whether the tag is self-closing and its length. `None` if the
+/// tag never ends.
+#[allow(clippy::type_complexity)]
+pub(crate) fn parse_attributes(text: &str) -> Option<(Vec<(&str, Option<&str>)>, bool, usize)> {
+    let mut attributes = Vec::new();
+    let mut i = 0;
+    loop {
+        i += text[i..].find(|c: char| !c.is_whitespace())?;
+        let rest = &text[i..];
+        if rest.starts_with('>') {
+            return Some((attributes, false, i + 1));
+        }
+        if rest.starts_with("/>") {
+            return Some((attributes, true, i + 2));
+        }
+        if rest.starts_with('/') {
+            i += 1;
+            continue;
+        }
+
+        let name_len = rest
+            .find(|c: char| c.is_whitespace() || c == '=' || c == '>' || c == '/')
+            .unwrap_or(rest.len());
+        let name = &rest[..name_len];
+        i += name_len;
+        let after_name = i + text[i..].find(|c: char| !c.is_whitespace())?;
+        if !text[after_name..].starts_with('=') {
+            attributes.push((name, None));
+            continue;
+        }
+        i = after_name + 1;
+        i += text[i..].find(|c: char| !c.is_whitespace())?;
+        let rest = &text[i..];
+        let value = match rest.chars().next()? {
+            quote @ ('"' | '\'') => {
+                let len = rest[1..].find(quote)?;
+                i += len + 2;
+                &rest[1..len + 1]
+            }
+            _ => {
+                let len = rest
+                    .find(|c: char| c.is_whitespace() || c == '>')
+                    .unwrap_or(rest.len());
+                i += len;
+                &rest[..len]
+            }
+        };
+        attributes.push((name, Some(value)));
+    }
+}
+
+/// Shorten a snippet of HTML for a warning.
+fn truncate(snippet: &str) -> String {
+    let snippet = snippet.trim();
+    match snippet.char_indices().nth(SNIPPET_LEN) {
+        Some((i, _)) => format!("{}...", &snippet[..i]),
+        None => snippet.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixed(html: &str) -> String {
+        normalize(html).0
+    }
+
+    #[test]
+    fn keep_valid_xhtml() {
+        let html = "<h1 id=\"intro\">Intro</h1>\n<p>A &amp; B <em>x</em><br />y</p>\n\
+            <!-- note -->\n<img src=\"a.png\" alt=\"\" />\n<pre><code>a &lt; b</code></pre>\n\
+            <svg viewBox=\"0 0 1 1\"><linearGradient id=\"g\"/></svg>";
+        let (normalized, warnings) = normalize(html);
+        assert_eq!(normalized, html.replace("\"g\"/>", "\"g\" />"));
+        assert!(warnings.is_empty(), "{warnings:?}");
+    }
+
+    #[test]
+    fn close_void_and_implied_elements() {
+        assert_eq!(
+            fixed("<P CLASS=note>one<BR>two<hr><img src=a.png alt=\"\"></p>"),
+            "<p class=\"note\">one<br />two</p><hr /><img src=\"a.png\" alt=\"\" />"
+        );
+        assert_eq!(
+            fixed("<ul><li>one<li>two<ul><li>nested</ul></ul>"),
+            "<ul><li>one</li><li>two<ul><li>nested</li></ul></li></ul>"
+        );
+        assert_eq!(
+            fixed("<table><tr><td>a<td>b<tr><td>c</table>"),
+            "<table><tr><td>a</td><td>b</td></tr><tr><td>c</td></tr></table>"
+        );
+        assert_eq!(
+            fixed("<video controls src='a.mp4'></video>"),
+            "<video controls=\"controls\" src=\"a.mp4\"></video>"
+        );
+    }
+
+    #[test]
+    fn fix_broken_markup() {
+        let (normalized, warnings) =
+            normalize("<div><b>bold</div></span>\n<!-- a -- b -->\n<p>x<y & z&nbsp;&foo; &#x2014;");
+        assert_eq!(
+            normalized,
+            "<div><b>bold</b></div>\n\n<p>x&lt;y &amp; z&#160;&amp;foo; &#8212;</p>"
+        );
+        assert_eq!(
+            warnings,
+            vec![
+                "Closed `<b>` before the end tag: `</div>`",
+                "Removed an end tag without start tag: `</span>`",
+                "Removed a comment containing `--`: `a -- b`",
+                "Escaped a stray `<`: `<y & z&nbsp;&foo; &#x2014;`",
+                "Escaped a stray `&`: `& z&nbsp;&foo; &#x2014;`",
+                "Escaped an unknown entity: `&foo; &#x2014;`",
+            ]
+        );
+    }
+
+    #[test]
+    fn convert_entities() {
+        let (normalized, warnings) = normalize(
+            "<p>&eacute;&auml;&Agrave; &alpha;&Omega; &hearts;&lArr; &#0;&#x1F;&#xFFFE; &#9;&#X41;&#x42;</p>",
+        );
+        assert_eq!(
+            normalized,
+            "<p>&#233;&#228;&#192; &#945;&#937; &#9829;&#8656; &amp;#0;&amp;#x1F;&amp;#xFFFE; &#9;&#65;&#66;</p>"
+        );
+        assert_eq!(
+            warnings,
+            vec![
+                "Escaped a reference to a character XML forbids: `&#0;&#x1F;&#xFFFE; &#9;&#X41;&#x42;</p>`",
+                "Escaped a reference to a character XML forbids: `&#x1F;&#xFFFE; &#9;&#X41;&#x42;</p>`",
+                "Escaped a reference to a character XML forbids: `&#xFFFE; &#9;&#X41;&#x42;</p>`",
+            ]
+        );
+    }
+
+    #[test]
+    fn escape_attribute_values_and_scripts() {
+        assert_eq!(
+            fixed("<a href=\"?a=1&b=2\" title='say \"hi\"'>x</a><script>if (a < b) {}</script>"),
+            "<a href=\"?a=1&amp;b=2\" title=\"say &quot;hi&quot;\">x</a>\
+            <script><![CDATA[if (a < b) {}]]></script>"
+        );
+        let (normalized, _) = normalize("<script>if (a[b[0]]>c && d) {}</script>");
+        assert_eq!(
+            normalized,
+            "<script><![CDATA[if (a[b[0]]]]><![CDATA[>c && d) {}]]></script>"
+        );
+        assert!(roxmltree::Document::parse(&normalized).is_ok());
+    }
+}
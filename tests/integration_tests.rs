@@ -86,6 +86,19 @@ fn output_epub_is_valid() {
     epub_check(&output_file).expect("Valitdating ePub");
 }
 
+#[test]
+#[serial]
+fn output_epub_passes_builtin_validation() {
+    init_logging();
+    let (ctx, _md, temp) = create_dummy_book().unwrap();
+    mdbook_epub::generate(&ctx).unwrap();
+
+    let output_file = mdbook_epub::output_filename(temp.path(), &ctx.config);
+    let report = mdbook_epub::validate(&output_file).unwrap();
+    let errors: Vec<String> = report.errors().map(ToString::to_string).collect();
+    assert!(errors.is_empty(), "{errors:#?}");
+}
+
 fn epub_check(path: &Path) -> Result<(), Error> {
     init_logging();
     let cmd = if cfg!(target_os = "windows") {
@@ -137,7 +150,7 @@ fn look_for_chapter_1_heading() {
     debug!("file = {:?}", &file);
     let content = file.unwrap();
     debug!("content = {:?}", content.len());
-    assert!(content.contains("<h1>Chapter 1</h1>"));
+    assert!(content.contains("<h1 id=\"chapter-1\">Chapter 1</h1>"));
     assert!(!content.contains("{{#rustdoc_include"));
     assert!(content.contains("fn main() {"));
 }
@@ -160,6 +173,41 @@ fn look_for_chapter_2_image_link_in_readme() {
     assert!(content.contains("<img src=\"../rust-logo.png\""));
 }
 
+#[test]
+#[serial]
+fn look_for_chapter_2_link_to_chapter_1() {
+    init_logging();
+    let mut doc = generate_epub().unwrap();
+
+    let path = RelativePath::new("OEBPS/02_advanced/README.html");
+    let content = doc.0.get_resource_str_by_path(path.as_str()).unwrap();
+
+    assert!(content.contains("<a href=\"../chapter_1.html\">Back to Chapter 1</a>"));
+}
+
+#[test]
+#[serial]
+fn hidden_lines_are_removed_from_rust_code() {
+    init_logging();
+    let mut doc = generate_epub().unwrap();
+
+    let path = RelativePath::new("OEBPS/02_advanced/README.html");
+    let content = doc.0.get_resource_str_by_path(path.as_str()).unwrap();
+
+    assert!(content.contains("<code class=\"language-rust\">let answer = 42;\n</code>"));
+}
+
+#[test]
+#[serial]
+fn toc_lists_chapter_headings() {
+    init_logging();
+    let mut doc = generate_epub().unwrap();
+
+    let toc = doc.0.get_resource_str_by_path("OEBPS/toc.ncx").unwrap();
+    assert!(toc.contains("<content src=\"02_advanced/README.html#image-link\"/>"));
+    assert!(toc.contains("<content src=\"02_advanced/README.html#chapter-link\"/>"));
+}
+
 #[test]
 #[serial]
 fn rendered_document_contains_all_chapter_files_and_assets() {